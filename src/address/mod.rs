@@ -0,0 +1,440 @@
+//  Short checksummed account addresses
+
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+
+use crate::hash::{ChainHasher, HashAlgorithm};
+
+//  Prefix of addresses on the main network
+pub const MAIN_PREFIX: &str = "dr";
+
+//  Prefix of addresses on test networks
+pub const TEST_PREFIX: &str = "tdr";
+
+//  Bytes of the public key hash an address carries
+const HASH_LENGTH: usize = 20;
+
+const CHARSET: &[u8; 32] = b"qpzry9x8gf2tvdw0s3jn54khce6mua7l";
+
+const GENERATOR: [u32; 5] = [0x3b6a57b2, 0x26508e6d, 0x1ea119fa, 0x3d4233dd, 0x2a1462b3];
+
+const CHECKSUM_LENGTH: usize = 6;
+
+//  Longest string Bech32 allows, checked before anything is decoded
+const MAX_LENGTH: usize = 90;
+
+/**
+    Account address: a network prefix, the separator `1`, then
+    the first 20 bytes of the SHA-256 of the account's tagged
+    public key and a checksum, both in the Bech32 alphabet, e.g.
+    `dr1` followed by 38 characters. Any single character typo
+    is detected, see `Address::parse`.
+ */
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Address {
+    prefix: String,
+    hash: [u8; HASH_LENGTH],
+}
+
+/**
+    Why a string is not an address. Positions count
+    characters from the start of the string.
+ */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AddressError {
+    MissingSeparator,
+    //  Upper and lower case letters mixed, only one is allowed
+    MixedCase,
+    //  Character outside the alphabet, such as `b`, `i`, `o` or `1` after the separator
+    InvalidCharacter { position: usize, character: char },
+    InvalidLength(usize),
+    //  Valid address not written as `Display` writes it, such as in upper case
+    NotCanonical,
+    //  Whole string is longer than `MAX_LENGTH`
+    TooLong(usize),
+    WrongPrefix { expected: String, found: String },
+    BadChecksum,
+    //  Checksum fails but changing the character at `position`,
+    //  or swapping it with the next one, gives a valid address
+    Typo { position: usize },
+}
+
+impl fmt::Display for AddressError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            AddressError::MissingSeparator => write!(f, "address has no separator"),
+            AddressError::MixedCase => write!(f, "address mixes upper and lower case"),
+            AddressError::InvalidCharacter { position, character } =>
+                write!(f, "invalid character {:?} at position {}", character, position),
+            AddressError::InvalidLength(length) =>
+                write!(f, "address has {} characters of data", length),
+            AddressError::NotCanonical => write!(f, "address is not in canonical lower case form"),
+            AddressError::TooLong(length) =>
+                write!(f, "address has {} characters, at most {} are allowed", length, MAX_LENGTH),
+            AddressError::WrongPrefix { expected, found } =>
+                write!(f, "address prefix is {} but {} was expected", found, expected),
+            AddressError::BadChecksum => write!(f, "address checksum doesn't match"),
+            AddressError::Typo { position } =>
+                write!(f, "address has a typo at position {}", position),
+        }
+    }
+}
+
+impl Error for AddressError {}
+
+impl Address {
+    //  Address of the tagged public key `public_key` on the network using `prefix`
+    pub fn from_public_key(prefix: &str, public_key: &[u8]) -> Self {
+        let digest = HashAlgorithm::Sha256.hash(public_key);
+        let mut hash = [0; HASH_LENGTH];
+        hash.copy_from_slice(&digest.as_bytes()[..HASH_LENGTH]);
+
+        Address { prefix: prefix.to_string(), hash }
+    }
+
+    /**
+        Reads an address typed by a user, in either case. On a
+        checksum failure it looks for the single typo that
+        explains it, so an operator is told which character to fix.
+     */
+    pub fn parse(s: &str) -> Result<Self, AddressError> {
+        match Self::decode(s) {
+            Err(AddressError::BadChecksum) => {
+                let address = s.to_lowercase();
+                let (prefix, data) = split(&address)?;
+                Err(find_typo(prefix, data)
+                    .map(|position| AddressError::Typo { position: position + prefix.len() + 1 })
+                    .unwrap_or(AddressError::BadChecksum))
+            }
+            result => result,
+        }
+    }
+
+    /**
+        Same as `parse` without the typo search, for addresses
+        found in operations and blocks, where nobody is there
+        to fix them and anyone can make the search run. State is
+        keyed by the address string, so only the canonical form is
+        accepted, otherwise one key would have several accounts.
+     */
+    pub fn parse_strict(s: &str) -> Result<Self, AddressError> {
+        let address = Self::decode(s)?;
+        if address.to_string() != s {
+            return Err(AddressError::NotCanonical);
+        }
+        Ok(address)
+    }
+
+    //  Address written in either case, without looking for typos
+    fn decode(s: &str) -> Result<Self, AddressError> {
+        if s.len() > MAX_LENGTH {
+            return Err(AddressError::TooLong(s.len()));
+        }
+        let address = s.to_lowercase();
+        if address != s && s.to_uppercase() != s {
+            return Err(AddressError::MixedCase);
+        }
+        let (prefix, data) = split(&address)?;
+
+        let values = decode_values(&address, prefix.len() + 1)?;
+        if !verify_checksum(prefix, &values) {
+            return Err(AddressError::BadChecksum);
+        }
+        if values.len() != 32 + CHECKSUM_LENGTH {
+            return Err(AddressError::InvalidLength(data.len()));
+        }
+
+        let bytes = regroup(&values[..values.len() - CHECKSUM_LENGTH]);
+        let mut hash = [0; HASH_LENGTH];
+        hash.copy_from_slice(&bytes);
+        Ok(Address { prefix: prefix.to_string(), hash })
+    }
+
+    //  Same as `parse`, also requiring the prefix of one network
+    pub fn parse_with_prefix(prefix: &str, s: &str) -> Result<Self, AddressError> {
+        let address = Self::parse(s)?;
+        if address.prefix != prefix {
+            return Err(AddressError::WrongPrefix {
+                expected: prefix.to_string(),
+                found: address.prefix,
+            });
+        }
+        Ok(address)
+    }
+
+    pub fn is_valid(s: &str) -> bool {
+        Self::parse_strict(s).is_ok()
+    }
+
+    pub fn get_prefix(&self) -> &str {
+        &self.prefix
+    }
+
+    pub fn get_hash(&self) -> &[u8; HASH_LENGTH] {
+        &self.hash
+    }
+
+    //  Whether the address commits to the tagged public key `public_key`
+    pub fn matches_key(&self, public_key: &[u8]) -> bool {
+        Self::from_public_key(&self.prefix, public_key) == *self
+    }
+
+    //  Same as `matches_key` for an address and key as stored on chain, hex encoding the key
+    pub fn is_address_of(address: &str, public_key: &str) -> bool {
+        match (Self::parse_strict(address), hex::decode(public_key)) {
+            (Ok(address), Ok(public_key)) => address.matches_key(&public_key),
+            _ => false,
+        }
+    }
+}
+
+impl fmt::Display for Address {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut values = ungroup(&self.hash);
+        values.extend(create_checksum(&self.prefix, &values));
+        let data: String = values.iter().map(|v| CHARSET[*v as usize] as char).collect();
+
+        write!(f, "{}1{}", self.prefix, data)
+    }
+}
+
+impl FromStr for Address {
+    type Err = AddressError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::parse(s)
+    }
+}
+
+//  Prefix and data either side of the last `1`
+fn split(address: &str) -> Result<(&str, &str), AddressError> {
+    let separator = address.rfind('1').ok_or(AddressError::MissingSeparator)?;
+    if separator == 0 {
+        return Err(AddressError::MissingSeparator);
+    }
+    if let Some((position, character)) = address[..separator].char_indices()
+        .find(|(_, c)| !c.is_ascii_graphic()) {
+        return Err(AddressError::InvalidCharacter { position, character });
+    }
+    Ok((&address[..separator], &address[separator + 1..]))
+}
+
+//  5 bit values of the data part, which starts at `offset`
+fn decode_values(address: &str, offset: usize) -> Result<Vec<u8>, AddressError> {
+    address[offset..].chars().enumerate()
+        .map(|(i, character)| CHARSET.iter()
+            .position(|c| *c as char == character)
+            .map(|v| v as u8)
+            .ok_or(AddressError::InvalidCharacter { position: offset + i, character }))
+        .collect()
+}
+
+/**
+    Position in `data` of the only single substitution or
+    adjacent swap that fixes the checksum. `None` if there is
+    no such fix or more than one.
+ */
+fn find_typo(prefix: &str, data: &str) -> Option<usize> {
+    let original: Vec<u8> = data.bytes().collect();
+    let mut fixes: Vec<(usize, Vec<u8>)> = Vec::new();
+    for position in 0..original.len() {
+        for c in CHARSET.iter().filter(|c| **c != original[position]) {
+            let mut candidate = original.clone();
+            candidate[position] = *c;
+            fixes.push((position, candidate));
+        }
+        if position + 1 < original.len() && original[position] != original[position + 1] {
+            let mut candidate = original.clone();
+            candidate.swap(position, position + 1);
+            fixes.push((position, candidate));
+        }
+    }
+
+    let mut valid: Vec<(usize, Vec<u8>)> = fixes.into_iter()
+        .filter(|(_, candidate)| {
+            let values: Vec<u8> = candidate.iter()
+                .map(|c| CHARSET.iter().position(|x| x == c).unwrap() as u8)
+                .collect();
+            verify_checksum(prefix, &values)
+        })
+        .collect();
+    //  A swap of two characters can also be two substitutions
+    valid.sort_by(|a, b| a.1.cmp(&b.1).then(a.0.cmp(&b.0)));
+    valid.dedup_by(|a, b| a.1 == b.1);
+    match valid.as_slice() {
+        [(position, _)] => Some(*position),
+        _ => None,
+    }
+}
+
+fn polymod(values: impl Iterator<Item = u8>) -> u32 {
+    let mut checksum: u32 = 1;
+    for value in values {
+        let top = checksum >> 25;
+        checksum = (checksum & 0x1ffffff) << 5 ^ value as u32;
+        for (i, generator) in GENERATOR.iter().enumerate() {
+            if (top >> i) & 1 == 1 {
+                checksum ^= generator;
+            }
+        }
+    }
+    checksum
+}
+
+//  Prefix as checksummed: high bits of each character, a zero, then the low bits
+fn expand_prefix(prefix: &str) -> Vec<u8> {
+    let mut values: Vec<u8> = prefix.bytes().map(|c| c >> 5).collect();
+    values.push(0);
+    values.extend(prefix.bytes().map(|c| c & 31));
+    values
+}
+
+fn create_checksum(prefix: &str, values: &[u8]) -> Vec<u8> {
+    let expanded = expand_prefix(prefix).into_iter()
+        .chain(values.iter().copied())
+        .chain([0; CHECKSUM_LENGTH]);
+    let checksum = polymod(expanded) ^ 1;
+    (0..CHECKSUM_LENGTH)
+        .map(|i| ((checksum >> (5 * (CHECKSUM_LENGTH - 1 - i))) & 31) as u8)
+        .collect()
+}
+
+fn verify_checksum(prefix: &str, values: &[u8]) -> bool {
+    values.len() >= CHECKSUM_LENGTH
+        && polymod(expand_prefix(prefix).into_iter().chain(values.iter().copied())) == 1
+}
+
+//  Bytes to 5 bit values, the hash length is a multiple of 5 bytes so nothing is padded
+fn ungroup(bytes: &[u8]) -> Vec<u8> {
+    let bits: Vec<bool> = bytes.iter()
+        .flat_map(|byte| (0..8).rev().map(move |i| (byte >> i) & 1 == 1))
+        .collect();
+    bits.chunks(5)
+        .map(|chunk| chunk.iter().fold(0, |value, bit| value << 1 | *bit as u8))
+        .collect()
+}
+
+//  Inverse of `ungroup`
+fn regroup(values: &[u8]) -> Vec<u8> {
+    let bits: Vec<bool> = values.iter()
+        .flat_map(|value| (0..5).rev().map(move |i| (value >> i) & 1 == 1))
+        .collect();
+    bits.chunks(8)
+        .map(|chunk| chunk.iter().fold(0, |byte, bit| byte << 1 | *bit as u8))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::keysig::{KeyAlgorithm, KeySig};
+    use super::{Address, AddressError, MAIN_PREFIX, TEST_PREFIX};
+
+    fn get_address() -> String {
+        let key = KeySig::generate(KeyAlgorithm::Ed25519);
+        Address::from_public_key(MAIN_PREFIX, &key.get_public_key()).to_string()
+    }
+
+    //  Golden vector, must not change between releases
+    #[test]
+    fn test_address_encoding() {
+        let address = Address::from_public_key(MAIN_PREFIX, b"key");
+
+        assert_eq!(
+            hex::encode(address.get_hash()),
+            "2c70e12b7a0646f92279f427c7b38e7334d8e538"
+        );
+        assert_eq!(address.to_string(), "dr193cwz2m6qer0jgne7snu0vuwwv6d3efcu4gj4m");
+    }
+
+    #[test]
+    fn test_parse() {
+        let key = KeySig::generate(KeyAlgorithm::Ed25519);
+        let address = Address::from_public_key(MAIN_PREFIX, &key.get_public_key());
+        let s = address.to_string();
+
+        assert_eq!(s.len(), 41);
+        assert_eq!(Address::parse(&s), Ok(address.clone()));
+        assert_eq!(s.to_uppercase().parse::<Address>(), Ok(address.clone()));
+        assert!(address.matches_key(&key.get_public_key()));
+        assert!(!address.matches_key(&KeySig::generate(KeyAlgorithm::Ed25519).get_public_key()));
+    }
+
+    //  Users may type an address in upper case, the chain only knows the lower case one
+    #[test]
+    fn test_strict_parse_is_canonical() {
+        let key = KeySig::generate(KeyAlgorithm::Ed25519).get_public_key();
+        let s = Address::from_public_key(MAIN_PREFIX, &key).to_string();
+        let alias = s.to_uppercase();
+
+        assert!(Address::parse_strict(&s).is_ok());
+        assert!(Address::parse(&alias).is_ok());
+        assert_eq!(Address::parse_strict(&alias), Err(AddressError::NotCanonical));
+        assert!(!Address::is_valid(&alias));
+        assert!(Address::is_address_of(&s, &hex::encode(&key)));
+        assert!(!Address::is_address_of(&alias, &hex::encode(&key)));
+    }
+
+    #[test]
+    fn test_reject_malformed() {
+        let address = get_address();
+        let mut mixed = address.clone();
+        mixed.replace_range(..1, "D");
+
+        assert_eq!(Address::parse(&mixed), Err(AddressError::MixedCase));
+        assert_eq!(Address::parse("drqpzry"), Err(AddressError::MissingSeparator));
+        assert_eq!(
+            Address::parse(&address.replacen("dr1", "dr1b", 1)),
+            Err(AddressError::InvalidCharacter { position: 3, character: 'b' })
+        );
+        //  Fixed address, as a random one cut short can be one substitution away from valid
+        assert_eq!(
+            Address::parse("dr193cwz2m6qer0jgne7snu0vuwwv6d3efcu4gj4"),
+            Err(AddressError::BadChecksum)
+        );
+    }
+
+    //  Typo search is quadratic in the length, so overlong input is refused up front
+    #[test]
+    fn test_reject_long_input() {
+        let long = format!("dr1{}", "q".repeat(100_000));
+
+        assert_eq!(Address::parse(&long), Err(AddressError::TooLong(long.len())));
+        assert_eq!(Address::parse_strict(&long), Err(AddressError::TooLong(long.len())));
+        assert!(!Address::is_valid(&long));
+        assert!(!Address::is_address_of(&long, "00"));
+    }
+
+    #[test]
+    fn test_wrong_prefix() {
+        let key = KeySig::generate(KeyAlgorithm::Ed25519).get_public_key();
+        let address = Address::from_public_key(TEST_PREFIX, &key).to_string();
+
+        assert!(Address::parse_with_prefix(TEST_PREFIX, &address).is_ok());
+        assert_eq!(
+            Address::parse_with_prefix(MAIN_PREFIX, &address),
+            Err(AddressError::WrongPrefix {
+                expected: MAIN_PREFIX.to_string(), found: TEST_PREFIX.to_string()
+            })
+        );
+    }
+
+    //  An operator mistyping one character is told which one
+    #[test]
+    fn test_detect_typo() {
+        let address = get_address();
+        let mut typo: Vec<char> = address.chars().collect();
+        typo[10] = if typo[10] == 'q' { 'p' } else { 'q' };
+        let typo: String = typo.into_iter().collect();
+
+        assert_eq!(Address::parse(&typo), Err(AddressError::Typo { position: 10 }));
+        assert_eq!(Address::parse_strict(&typo), Err(AddressError::BadChecksum));
+
+        let mut swapped: Vec<char> = address.chars().collect();
+        let position = (3..address.len() - 1).find(|i| swapped[*i] != swapped[i + 1]).unwrap();
+        swapped.swap(position, position + 1);
+        let swapped: String = swapped.into_iter().collect();
+
+        assert_eq!(Address::parse(&swapped), Err(AddressError::Typo { position }));
+    }
+}
@@ -0,0 +1,82 @@
+use std::fmt;
+use serde::Serialize;
+
+/**
+    Number of tokens held or moved by an account.
+    Arithmetic is checked so balances can never wrap,
+    callers turn `None` into a validation error.
+ */
+#[derive(Serialize, Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[serde(transparent)]
+pub struct Amount(u64);
+
+impl Amount {
+    pub const ZERO: Amount = Amount(0);
+    pub const MAX: Amount = Amount(u64::MAX);
+
+    pub const fn new(tokens: u64) -> Self {
+        Amount(tokens)
+    }
+
+    pub fn get_tokens(&self) -> u64 {
+        self.0
+    }
+
+    pub fn is_zero(&self) -> bool {
+        self.0 == 0
+    }
+
+    pub fn checked_add(self, other: Amount) -> Option<Amount> {
+        self.0.checked_add(other.0).map(Amount)
+    }
+
+    pub fn checked_sub(self, other: Amount) -> Option<Amount> {
+        self.0.checked_sub(other.0).map(Amount)
+    }
+
+    pub fn saturating_add(self, other: Amount) -> Amount {
+        Amount(self.0.saturating_add(other.0))
+    }
+
+    pub fn saturating_sub(self, other: Amount) -> Amount {
+        Amount(self.0.saturating_sub(other.0))
+    }
+}
+
+impl From<u64> for Amount {
+    fn from(tokens: u64) -> Self {
+        Amount(tokens)
+    }
+}
+
+impl fmt::Display for Amount {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Amount;
+
+    #[test]
+    fn test_checked_add() {
+        assert_eq!(Amount::new(2).checked_add(Amount::new(3)), Some(Amount::new(5)));
+        assert_eq!(Amount::MAX.checked_add(Amount::new(1)), None);
+    }
+
+    #[test]
+    fn test_checked_sub() {
+        assert_eq!(Amount::new(5).checked_sub(Amount::new(3)), Some(Amount::new(2)));
+        assert_eq!(Amount::new(3).checked_sub(Amount::new(5)), None);
+    }
+
+    #[test]
+    fn test_beyond_u8() {
+        let balance = Amount::new(255).checked_add(Amount::new(1)).unwrap();
+
+        assert_eq!(balance.get_tokens(), 256);
+        assert_eq!(balance.to_string(), "256");
+        assert_eq!(serde_json::to_string(&balance).unwrap(), "256");
+    }
+}
@@ -0,0 +1,426 @@
+use serde::Serialize;
+
+use crate::account::Account;
+use crate::blockchain::BlockHash;
+use crate::consensus::meets_difficulty;
+use crate::encoding::{Encode, Encoder};
+use crate::hash::{merkle_root, ChainHasher, Hash32, HashAlgorithm, MerkleProof, MerkleTree};
+use crate::transops::Transaction;
+use crate::utils::get_timestamp;
+
+//  Header layout produced by this release
+pub const BLOCK_VERSION: u32 = 1;
+
+/**
+    Everything needed to place and check a block without its body.
+    The header hash, under the chain's hash algorithm, is the block id.
+ */
+#[derive(Serialize, Clone, Debug, PartialEq, Eq)]
+pub struct BlockHeader {
+    version: u32,
+    height: u64,
+    parent: BlockHash,
+    //  Commits to the ids of the block's transactions
+    merkle_root: Hash32,
+    timestamp: u64,
+    producer: String,
+    //  Commits to the balances after the block is applied
+    state_root: Hash32,
+    //  Leading zero bits the id must have, 0 when not mined
+    difficulty: u32,
+    nonce: u64,
+}
+
+#[derive(Serialize, Clone)]
+pub struct Block {
+    id: BlockHash,
+    //  Inherited from the parent, so blocks hash like the rest of their chain
+    #[serde(skip)]
+    algorithm: HashAlgorithm,
+    header: BlockHeader,
+    //  Producer's signature over the id, empty if unsealed
+    seal: Vec<u8>,
+    //  Tagged public key that made the seal, the producer is only an address
+    sealer_key: Vec<u8>,
+    transactions: Vec<Transaction>
+}
+
+impl BlockHeader {
+    //  Hash of the canonical encoding, see `Encode for BlockHeader`
+    pub fn hash(&self, algorithm: HashAlgorithm) -> BlockHash {
+        algorithm.hash(&self.encode())
+    }
+
+    pub fn get_version(&self) -> u32 {
+        self.version
+    }
+
+    pub fn get_height(&self) -> u64 {
+        self.height
+    }
+
+    pub fn get_parent(&self) -> BlockHash {
+        self.parent
+    }
+
+    pub fn get_merkle_root(&self) -> Hash32 {
+        self.merkle_root
+    }
+
+    pub fn get_timestamp(&self) -> u64 {
+        self.timestamp
+    }
+
+    pub fn get_producer(&self) -> String {
+        self.producer.clone()
+    }
+
+    pub fn get_state_root(&self) -> Hash32 {
+        self.state_root
+    }
+
+    pub fn get_difficulty(&self) -> u32 {
+        self.difficulty
+    }
+
+    pub fn get_nonce(&self) -> u64 {
+        self.nonce
+    }
+}
+
+impl Encode for BlockHeader {
+    const TAG: u8 = 3;
+
+    fn encode_fields(&self, encoder: &mut Encoder) {
+        encoder
+            .put_u32(self.version)
+            .put_u64(self.height)
+            .put_hash(&self.parent)
+            .put_hash(&self.merkle_root)
+            .put_u64(self.timestamp)
+            .put_str(&self.producer)
+            .put_hash(&self.state_root)
+            .put_u32(self.difficulty)
+            .put_u64(self.nonce);
+    }
+}
+
+impl Block {
+    /**
+        Unsealed block on top of `parent` with an empty state root.
+        `Blockchain::build_block` fills in the state root.
+     */
+    pub fn create_block(transactions: Vec<Transaction>, parent: &Block) -> Self {
+        Self::from_parts(
+            transactions,
+            parent.algorithm,
+            parent.header.height + 1,
+            parent.get_id(),
+            Hash32::default()
+        )
+    }
+
+    /**
+        First block of a chain. It is trusted as is and only
+        records the starting state and difficulty.
+     */
+    pub(super) fn genesis(
+        transactions: Vec<Transaction>,
+        algorithm: HashAlgorithm,
+        state_root: Hash32,
+        difficulty: u32
+    ) -> Self {
+        let mut block = Self::from_parts(
+            transactions, algorithm, 0, Hash32::default(), state_root
+        );
+        block.header.difficulty = difficulty;
+        block.rehash();
+        block
+    }
+
+    fn from_parts(
+        transactions: Vec<Transaction>,
+        algorithm: HashAlgorithm,
+        height: u64,
+        parent: BlockHash,
+        state_root: Hash32
+    ) -> Self {
+        let header = BlockHeader {
+            version: BLOCK_VERSION,
+            height,
+            parent,
+            merkle_root: Self::compute_merkle_root(&transactions, algorithm),
+            timestamp: get_timestamp(),
+            producer: String::new(),
+            state_root,
+            difficulty: 0,
+            nonce: 0,
+        };
+
+        Block {
+            id: header.hash(algorithm),
+            algorithm,
+            header,
+            seal: Vec::new(),
+            sealer_key: Vec::new(),
+            transactions
+        }
+    }
+
+    pub fn compute_merkle_root(
+        transactions: &[Transaction], algorithm: HashAlgorithm
+    ) -> Hash32 {
+        merkle_root(&Self::transaction_ids(transactions), algorithm)
+    }
+
+    fn transaction_ids(transactions: &[Transaction]) -> Vec<Hash32> {
+        transactions.iter().map(|t| t.get_id()).collect()
+    }
+
+    //  Proof that `tx_id` is in this block, checked against the header merkle root
+    pub fn prove_inclusion(&self, tx_id: &Hash32) -> Option<MerkleProof> {
+        MerkleTree::new(Self::transaction_ids(&self.transactions), self.algorithm)
+            .prove_inclusion(tx_id)
+    }
+
+    //  Recomputes the id after a header change, invalidating any seal
+    fn rehash(&mut self) {
+        self.id = self.header.hash(self.algorithm);
+        self.seal.clear();
+        self.sealer_key.clear();
+    }
+
+    pub fn set_state_root(&mut self, state_root: Hash32) {
+        self.header.state_root = state_root;
+        self.rehash();
+    }
+
+    pub fn set_timestamp(&mut self, timestamp: u64) {
+        self.header.timestamp = timestamp;
+        self.rehash();
+    }
+
+    /**
+        Searches for a nonce giving the block an id with at least
+        `difficulty` leading zero bits. Any seal must be applied after.
+     */
+    pub fn mine(&mut self, difficulty: u32) {
+        self.header.difficulty = difficulty;
+        self.header.nonce = 0;
+        self.rehash();
+
+        while !meets_difficulty(&self.id, difficulty) {
+            self.header.nonce += 1;
+            self.rehash();
+        }
+    }
+
+    /**
+        Makes `sealer` the producer and signs the bytes of the
+        resulting id with its signing key.
+     */
+    pub fn seal(&mut self, sealer: &Account) {
+        self.header.producer = sealer.get_id();
+        self.rehash();
+        let keysig = sealer.get_signing_keysig();
+        self.seal = keysig.sign(self.id.as_bytes());
+        self.sealer_key = keysig.get_public_key();
+    }
+
+    pub fn get_seal(&self) -> &[u8] {
+        &self.seal
+    }
+
+    pub fn get_sealer_key(&self) -> &[u8] {
+        &self.sealer_key
+    }
+
+    pub fn get_header(&self) -> &BlockHeader {
+        &self.header
+    }
+
+    pub fn get_id(&self) -> BlockHash {
+        self.id
+    }
+
+    pub fn get_hash_algorithm(&self) -> HashAlgorithm {
+        self.algorithm
+    }
+
+    pub fn get_previous(&self) -> BlockHash {
+        self.header.get_parent()
+    }
+
+    pub fn get_producer(&self) -> String {
+        self.header.get_producer()
+    }
+
+    pub fn get_timestamp(&self) -> u64 {
+        self.header.timestamp
+    }
+
+    pub fn get_height(&self) -> u64 {
+        self.header.height
+    }
+
+    pub fn get_transactions(&self) -> &[Transaction] {
+        &self.transactions
+    }
+
+    //  Expected number of hashes needed to mine this block, unmined blocks count once
+    pub fn work(&self) -> u64 {
+        1u64 << self.header.difficulty.min(63)
+    }
+}
+
+//  The id is left out, it is the hash of the header
+impl Encode for Block {
+    const TAG: u8 = 4;
+
+    fn encode_fields(&self, encoder: &mut Encoder) {
+        self.header.encode_fields(encoder);
+        encoder
+            .put_bytes(&self.seal)
+            .put_bytes(&self.sealer_key)
+            .put_list(&self.transactions);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::account::Account;
+    use crate::amount::Amount;
+    use crate::blockchain::{Blockchain, BlockValidationError, GenesisConfig};
+    use crate::blockchain::BlockHeader;
+    use crate::consensus::{Consensus, ProofOfAuthority};
+    use crate::encoding::Encode;
+    use crate::hash::{verify_inclusion, Hash32, HashAlgorithm};
+    use crate::transops::{Operation, Transaction};
+
+    fn get_transaction(bc: &mut Blockchain) -> Transaction {
+        let mut sender = Account::gen_account();
+        bc.get_token_from_faucet(&mut sender, Amount::new(5)).unwrap();
+        let chain_id = bc.get_config().get_chain_id();
+        let op = Operation::create_operation(
+            &Account::gen_account(), &sender, Amount::new(1), 0, chain_id
+        );
+        let mut trans = Transaction::create_transaction(vec![op]);
+        sender.sign_transaction(&mut trans, chain_id);
+        trans
+    }
+
+    #[test]
+    fn test_header_hash_is_id() {
+        let mut bc = Blockchain::init();
+        let trans = get_transaction(&mut bc);
+        let mut block = bc.build_block(vec![trans], &bc.genesis().get_id()).unwrap();
+        let id = block.get_id();
+
+        assert_eq!(block.get_header().hash(HashAlgorithm::Sha256), id);
+
+        block.header.nonce += 1;
+        assert_ne!(block.get_header().hash(HashAlgorithm::Sha256), id);
+    }
+
+    //  Golden vector, must not change between releases
+    #[test]
+    fn test_header_encoding() {
+        let header = BlockHeader {
+            version: 1,
+            height: 2,
+            parent: Hash32::new([0x11; 32]),
+            merkle_root: Hash32::new([0x22; 32]),
+            timestamp: 1_700_000_000,
+            producer: "waypoint".to_string(),
+            state_root: Hash32::new([0x33; 32]),
+            difficulty: 4,
+            nonce: 9,
+        };
+
+        assert_eq!(
+            hex::encode(header.encode()),
+            "0103\
+             00000001\
+             0000000000000002"
+                .to_string()
+                + &"11".repeat(32)
+                + &"22".repeat(32)
+                + "000000006553f100\
+                   00000008776179706f696e74"
+                + &"33".repeat(32)
+                + "00000004\
+                   0000000000000009"
+        );
+        assert_eq!(
+            header.hash(HashAlgorithm::Sha256).to_hex(),
+            "33773b7b752c486122bf00942b49dd0ab351ea1a9a9e6ba574de36d3bb1a49a2"
+        );
+    }
+
+    #[test]
+    fn test_prove_inclusion() {
+        let mut bc = Blockchain::init();
+        let transactions: Vec<Transaction> = (0..3).map(|_| get_transaction(&mut bc)).collect();
+        let outsider = get_transaction(&mut bc);
+        let block = bc.build_block(transactions.clone(), &bc.genesis().get_id()).unwrap();
+        let root = block.get_header().get_merkle_root();
+
+        for transaction in &transactions {
+            let proof = block.prove_inclusion(&transaction.get_id()).unwrap();
+            assert!(verify_inclusion(&root, &proof));
+        }
+        assert!(block.prove_inclusion(&outsider.get_id()).is_none());
+    }
+
+    #[test]
+    fn test_reject_tampered_body() {
+        let mut bc = Blockchain::init();
+        let trans = get_transaction(&mut bc);
+        let extra = get_transaction(&mut bc);
+        let mut block = bc.build_block(vec![trans], &bc.genesis().get_id()).unwrap();
+        block.transactions.push(extra);
+        let id = block.get_id();
+
+        assert_eq!(
+            bc.validate_block(block),
+            Err(BlockValidationError::BadMerkleRoot(id))
+        );
+    }
+
+    #[test]
+    fn test_reject_wrong_height() {
+        let mut bc = Blockchain::init();
+        let trans = get_transaction(&mut bc);
+        let mut block = bc.build_block(vec![trans], &bc.genesis().get_id()).unwrap();
+        block.header.height = 5;
+        block.rehash();
+
+        assert_eq!(
+            bc.validate_block(block),
+            Err(BlockValidationError::WrongHeight { expected: 1, found: 5 })
+        );
+    }
+
+    #[test]
+    fn test_reject_tampered_seal() {
+        let authority = Account::gen_account();
+        let mut bc = Blockchain::from_genesis(
+            GenesisConfig::default().with_consensus(
+                Consensus::Authority(ProofOfAuthority::new(vec![authority.get_id()]))
+            )
+        ).unwrap();
+        let trans = get_transaction(&mut bc);
+        let mut block = bc.build_block(vec![trans], &bc.genesis().get_id()).unwrap();
+        block.seal(&authority);
+
+        //  Keep the seal but change what it covers
+        block.header.timestamp += 1;
+        block.id = block.header.hash(HashAlgorithm::Sha256);
+        let id = block.get_id();
+
+        assert_eq!(
+            bc.validate_block(block),
+            Err(BlockValidationError::BadSeal(id))
+        );
+    }
+}
@@ -0,0 +1,302 @@
+use std::collections::HashMap;
+use std::slice;
+
+use crate::blockchain::BlockHash;
+
+/**
+    Index over the block store.
+    Tracks the height, parent, children and cumulative fork-choice
+    weight of every known block, including competing branches, and
+    the canonical chain from genesis (height 0) to tip.
+ */
+#[derive(Clone)]
+pub struct ChainIndex {
+    heights: HashMap<BlockHash, u64>,
+    //  Sum of block weights from genesis up to and including the block
+    weights: HashMap<BlockHash, u64>,
+    parents: HashMap<BlockHash, BlockHash>,
+    children: HashMap<BlockHash, Vec<BlockHash>>,
+    //  Block id at each height, genesis first
+    canonical: Vec<BlockHash>,
+}
+
+/**
+    Walks parent links from a block back to genesis.
+ */
+pub struct Ancestors<'a> {
+    index: &'a ChainIndex,
+    next: Option<&'a BlockHash>,
+}
+
+impl ChainIndex {
+    pub fn new(genesis: BlockHash) -> Self {
+        let mut heights = HashMap::new();
+        heights.insert(genesis, 0);
+        let mut weights = HashMap::new();
+        weights.insert(genesis, 0);
+
+        ChainIndex {
+            heights,
+            weights,
+            parents: HashMap::new(),
+            children: HashMap::new(),
+            canonical: vec![genesis],
+        }
+    }
+
+    /**
+        Links `id` under `parent` with the block's own fork-choice
+        `weight` and returns its height.
+        The canonical chain only grows when `parent` is the tip,
+        otherwise the block sits on a side branch until `set_tip`.
+        Panics if `parent` is not indexed.
+     */
+    pub fn insert(&mut self, id: BlockHash, parent: BlockHash, weight: u64) -> u64 {
+        let height = self.heights[&parent] + 1;
+        let cumulative = self.weights[&parent].saturating_add(weight);
+
+        if parent == *self.tip() {
+            self.canonical.push(id);
+        }
+        self.children.entry(parent).or_default().push(id);
+        self.parents.insert(id, parent);
+        self.heights.insert(id, height);
+        self.weights.insert(id, cumulative);
+
+        height
+    }
+
+    /**
+        Makes `id` the tip, rebuilding the canonical chain from its
+        ancestors. Panics if `id` is not indexed.
+     */
+    pub fn set_tip(&mut self, id: &BlockHash) {
+        let mut canonical: Vec<BlockHash> = self.ancestors(id).cloned().collect();
+        canonical.reverse();
+        self.canonical = canonical;
+    }
+
+    /**
+        Drops `id` and every block built on top of it.
+        Returns the removed ids. The canonical chain must not
+        contain any of them.
+     */
+    pub fn remove(&mut self, id: &BlockHash) -> Vec<BlockHash> {
+        let mut removed = Vec::new();
+        let mut pending = vec![*id];
+
+        while let Some(current) = pending.pop() {
+            if let Some(children) = self.children.remove(&current) {
+                pending.extend(children);
+            }
+            if let Some(parent) = self.parents.remove(&current) {
+                if let Some(siblings) = self.children.get_mut(&parent) {
+                    siblings.retain(|c| *c != current);
+                }
+            }
+            self.heights.remove(&current);
+            self.weights.remove(&current);
+            removed.push(current);
+        }
+
+        removed
+    }
+
+    //  Latest block shared by the branches ending at `a` and `b`
+    pub fn common_ancestor<'a>(
+        &'a self, a: &'a BlockHash, b: &'a BlockHash
+    ) -> Option<&'a BlockHash> {
+        let mut a = Some(a);
+        let mut b = Some(b);
+
+        while let (Some(x), Some(y)) = (a, b) {
+            if x == y {
+                return Some(x);
+            }
+            let (hx, hy) = (self.height(x)?, self.height(y)?);
+            if hx >= hy { a = self.parent(x); }
+            if hy >= hx { b = self.parent(y); }
+        }
+
+        None
+    }
+
+    pub fn contains(&self, id: &BlockHash) -> bool {
+        self.heights.contains_key(id)
+    }
+
+    pub fn height(&self, id: &BlockHash) -> Option<u64> {
+        self.heights.get(id).copied()
+    }
+
+    //  Cumulative fork-choice weight of the branch ending at `id`
+    pub fn weight(&self, id: &BlockHash) -> Option<u64> {
+        self.weights.get(id).copied()
+    }
+
+    pub fn parent(&self, id: &BlockHash) -> Option<&BlockHash> {
+        self.parents.get(id)
+    }
+
+    pub fn children(&self, id: &BlockHash) -> &[BlockHash] {
+        self.children.get(id).map(|c| c.as_slice()).unwrap_or(&[])
+    }
+
+    pub fn genesis(&self) -> &BlockHash {
+        &self.canonical[0]
+    }
+
+    pub fn tip(&self) -> &BlockHash {
+        self.canonical.last().unwrap()
+    }
+
+    //  Height of the tip, genesis being 0
+    pub fn tip_height(&self) -> u64 {
+        self.canonical.len() as u64 - 1
+    }
+
+    //  Canonical block at `height`
+    pub fn at_height(&self, height: u64) -> Option<&BlockHash> {
+        self.canonical.get(height as usize)
+    }
+
+    pub fn is_canonical(&self, id: &BlockHash) -> bool {
+        match self.height(id) {
+            Some(h) => self.at_height(h) == Some(id),
+            None => false,
+        }
+    }
+
+    //  Canonical chain from genesis to tip, `.rev()` walks back from the tip
+    pub fn iter(&self) -> slice::Iter<'_, BlockHash> {
+        self.canonical.iter()
+    }
+
+    //  `id` followed by its parents down to genesis
+    pub fn ancestors<'a>(&'a self, id: &'a BlockHash) -> Ancestors<'a> {
+        let next = if self.contains(id) { Some(id) } else { None };
+        Ancestors { index: self, next }
+    }
+
+    //  Number of indexed blocks, including those off the canonical chain
+    pub fn len(&self) -> usize {
+        self.heights.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.heights.is_empty()
+    }
+}
+
+impl<'a> Iterator for Ancestors<'a> {
+    type Item = &'a BlockHash;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let current = self.next?;
+        self.next = self.index.parent(current);
+        Some(current)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::blockchain::BlockHash;
+    use crate::hash::{ChainHasher, HashAlgorithm};
+    use super::ChainIndex;
+
+    fn h(name: &str) -> BlockHash {
+        HashAlgorithm::Sha256.hash(name.as_bytes())
+    }
+
+    fn get_index() -> ChainIndex {
+        let mut index = ChainIndex::new(h("g"));
+        index.insert(h("a"), h("g"), 1);
+        index.insert(h("b"), h("a"), 1);
+        index
+    }
+
+    #[test]
+    fn test_insert_extends_tip() {
+        let index = get_index();
+
+        assert_eq!(*index.tip(), h("b"));
+        assert_eq!(index.tip_height(), 2);
+        assert_eq!(index.height(&h("a")), Some(1));
+        assert_eq!(*index.at_height(0).unwrap(), h("g"));
+    }
+
+    #[test]
+    fn test_weight_saturates() {
+        let mut index = get_index();
+        index.insert(h("c"), h("b"), u64::MAX);
+        index.insert(h("d"), h("c"), u64::MAX);
+
+        assert_eq!(index.weight(&h("d")), Some(u64::MAX));
+    }
+
+    #[test]
+    fn test_side_block_not_canonical() {
+        let mut index = get_index();
+        let height = index.insert(h("c"), h("a"), 1);
+
+        assert_eq!(height, 2);
+        assert_eq!(*index.tip(), h("b"));
+        assert!(!index.is_canonical(&h("c")));
+        assert_eq!(index.children(&h("a")), [h("b"), h("c")]);
+    }
+
+    #[test]
+    fn test_iter_both_directions() {
+        let index = get_index();
+
+        let forward: Vec<&BlockHash> = index.iter().collect();
+        let backward: Vec<&BlockHash> = index.iter().rev().collect();
+
+        assert_eq!(forward, [&h("g"), &h("a"), &h("b")]);
+        assert_eq!(backward, [&h("b"), &h("a"), &h("g")]);
+    }
+
+    #[test]
+    fn test_ancestors() {
+        let mut index = get_index();
+        index.insert(h("c"), h("a"), 1);
+        let c = h("c");
+
+        let walked: Vec<&BlockHash> = index.ancestors(&c).collect();
+
+        assert_eq!(walked, [&h("c"), &h("a"), &h("g")]);
+    }
+
+    #[test]
+    fn test_reorg_to_side_branch() {
+        let mut index = get_index();
+        index.insert(h("c"), h("a"), 1);
+        index.insert(h("d"), h("c"), 5);
+        let d = h("d");
+
+        assert_eq!(index.weight(&d), Some(7));
+        assert_eq!(*index.common_ancestor(&h("b"), &d).unwrap(), h("a"));
+
+        index.set_tip(&d);
+
+        assert_eq!(*index.tip(), h("d"));
+        assert!(index.is_canonical(&h("c")));
+        assert!(!index.is_canonical(&h("b")));
+    }
+
+    #[test]
+    fn test_remove_subtree() {
+        let mut index = get_index();
+        index.insert(h("c"), h("a"), 1);
+        index.insert(h("d"), h("c"), 1);
+
+        let mut removed = index.remove(&h("c"));
+        removed.sort();
+
+        let mut expected = vec![h("c"), h("d")];
+        expected.sort();
+        assert_eq!(removed, expected);
+        assert!(!index.contains(&h("d")));
+        assert_eq!(index.children(&h("a")), [h("b")]);
+    }
+}
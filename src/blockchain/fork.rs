@@ -0,0 +1,33 @@
+use std::collections::HashMap;
+
+use crate::blockchain::Block;
+
+/**
+    Rule deciding which of several competing branches is canonical.
+    Each block contributes a weight and the branch with the highest
+    cumulative weight wins; ties keep the branch seen first.
+ */
+#[derive(Clone, Debug, Default)]
+pub enum ForkChoice {
+    //  Every block weighs 1, so the tallest branch wins
+    #[default]
+    Longest,
+    //  Blocks weigh their proof of work, only with `Consensus::Work`
+    MostWork,
+    //  Blocks weigh whatever their producer is assigned, unknown producers weigh 0.
+    //  Only with `Consensus::Authority`, so producers are proven by their seals
+    AuthorityWeighted(HashMap<String, u64>),
+}
+
+impl ForkChoice {
+    pub fn weight(&self, block: &Block) -> u64 {
+        match self {
+            ForkChoice::Longest => 1,
+            ForkChoice::MostWork => block.work(),
+            ForkChoice::AuthorityWeighted(weights) => weights
+                .get(block.get_producer().as_str())
+                .copied()
+                .unwrap_or(0),
+        }
+    }
+}
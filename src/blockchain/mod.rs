@@ -1,176 +1,846 @@
-use std::borrow::BorrowMut;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::fmt;
 use crate::account::Account;
-use crate::hash::to_sha1;
-use serde::Serialize;
-use crate::transops::{CoinUpdates, get_nonce, Operation, Transaction};
-use crate::utils::vec_to_string;
-
-#[derive(Serialize, Clone)]
-pub struct Block {
-    id: String,
-    previous: String,
-    transactions: Vec<Transaction>
+use crate::amount::Amount;
+use crate::consensus::Consensus;
+use crate::drone::{Drone, DroneError};
+use crate::flight::{FlightError, FlightLog};
+use crate::hash::{Hash32, HashAlgorithm, MerkleProof};
+use crate::multisig::{MultisigPolicy, SigningAuthority};
+use crate::state::{State, StateOverlay};
+use crate::transops::{Operation, SignatureError, Transaction};
+use crate::utils::get_timestamp;
+use crate::waypoint::{BoundingBox, Waypoint, WaypointError};
+
+pub use self::block::{Block, BlockHeader, BLOCK_VERSION};
+pub use self::chain::{Ancestors, ChainIndex};
+pub use self::fork::ForkChoice;
+
+mod block;
+mod chain;
+mod fork;
+
+pub type BlockHash = Hash32;
+
+//  Seconds a block's timestamp may run ahead of the local clock
+pub const MAX_FUTURE_DRIFT: u64 = 2 * 60 * 60;
+
+//  Tokens the faucet account holds at genesis
+const FAUCET_SUPPLY: Amount = Amount::new(100);
+
+/**
+    Parameters fixed when the chain is created.
+ */
+#[derive(Clone, Debug, Default)]
+pub struct GenesisConfig {
+    consensus: Consensus,
+    fork_choice: ForkChoice,
+    //  Used for every block, transaction, merkle and state id
+    hash_algorithm: HashAlgorithm,
+    //  Signed into every operation so it can't be replayed on another chain
+    chain_id: u32,
 }
 
 pub struct Blockchain {
-    coin_db: HashMap<String, u8>,
-    history: HashMap<String, Block>,
-    transaction_db: HashMap<String, Transaction>,
-    faucet_coins: u8
+    //  State at the tip of the canonical chain
+    state: State,
+    blocks: HashMap<BlockHash, Block>,
+    chain: ChainIndex,
+    //  Funded at genesis, pays out faucet grants
+    faucet: Account,
+    //  Tokens the faucet has yet to promise
+    faucet_coins: Amount,
+    //  Signed faucet transfers, in nonce order
+    grants: Vec<Transaction>,
+    config: GenesisConfig
 }
 
-impl Block {
-    pub fn create_block(transactions: Vec<Transaction >, previous: String) -> Self {
-        let id = to_sha1(
-            &vec_to_string(&transactions)
-        );
-        Block {
-            id,
-            previous,
-            transactions
+/**
+    Reason a block was refused by `Blockchain::validate_block`.
+    Checks short-circuit, so only the first failure is reported.
+ */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BlockValidationError {
+    //  `previous` is not a known block
+    UnknownParent(BlockHash),
+    //  Block id already known
+    DuplicateBlock(BlockHash),
+    //  Header height is not one above its parent's
+    WrongHeight { expected: u64, found: u64 },
+    //  Header layout this release doesn't understand
+    UnsupportedVersion(u32),
+    //  Block was hashed with another algorithm than the chain's
+    WrongHashAlgorithm { expected: HashAlgorithm, found: HashAlgorithm },
+    //  Block claims to be older than its parent
+    InvalidTimestamp(BlockHash),
+    //  Block claims a time more than `MAX_FUTURE_DRIFT` ahead of the local clock
+    FutureTimestamp(BlockHash),
+    //  Header merkle root doesn't match the transactions in the body
+    BadMerkleRoot(BlockHash),
+    //  Transaction id doesn't match its contents under the chain's hash algorithm
+    BadTransactionId(Hash32),
+    //  Transaction id already recorded, or repeated within the block
+    ReplayedTransaction(Hash32),
+    //  Transaction carrying an operation or transaction signature that does not verify
+    BadSignature(Hash32),
+    //  A sender in the transaction hasn't signed it
+    MissingSignature { transaction: Hash32, signer: String },
+    //  Account's signing key has been revoked, it can't sign anymore
+    KeyRevoked(String),
+    //  Key rotated or revoked isn't the account's signing key, or
+    //  the new key is malformed or was revoked before
+    InvalidKey { account: String, key: String },
+    //  Multisig policy change doesn't name the account's current
+    //  policy as the one it replaces, or adds a revoked key
+    InvalidPolicy(String),
+    //  Journey leg or position report breaks the rules of `FlightLog`
+    InvalidFlight { drone: String, error: FlightError },
+    //  Waypoint registration breaks the rules of `Waypoint::check` or reuses an id
+    InvalidWaypoint { waypoint: String, error: WaypointError },
+    //  Drone operation by someone other than its owner, or flight
+    //  of a drone that isn't registered or was decommissioned
+    InvalidDrone { drone: String, error: DroneError },
+    //  Operation nonce is not the sender's next one, it was replayed or skips ahead
+    BadNonce { account: String, expected: u64, found: u64 },
+    InsufficientBalance { account: String, balance: Amount, amount: Amount },
+    Overflow { account: String },
+    //  Header state root doesn't match the balances after applying the block
+    StateRootMismatch { expected: Hash32, found: Hash32 },
+    //  Producer is not one of the chain's authorities
+    UnknownSealer(String),
+    //  Producer is an authority but another one should seal this height
+    OutOfTurn { sealer: String, expected: String },
+    //  Seal is not the producer's signature over the block
+    BadSeal(BlockHash),
+    //  Block was mined at a difficulty other than the chain's current target
+    WrongDifficulty { expected: u32, found: u32 },
+    //  Block id doesn't have the leading zero bits its difficulty claims
+    InsufficientWork(BlockHash),
+}
+
+impl fmt::Display for BlockValidationError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BlockValidationError::UnknownParent(previous) =>
+                write!(f, "unknown parent block {}", previous),
+            BlockValidationError::DuplicateBlock(id) =>
+                write!(f, "block {} is already in history", id),
+            BlockValidationError::WrongHeight { expected, found } =>
+                write!(f, "block height is {} but {} was expected", found, expected),
+            BlockValidationError::UnsupportedVersion(version) =>
+                write!(f, "block version {} is not supported", version),
+            BlockValidationError::WrongHashAlgorithm { expected, found } =>
+                write!(f, "block hashed with {:?} but the chain uses {:?}", found, expected),
+            BlockValidationError::InvalidTimestamp(id) =>
+                write!(f, "block {} is older than its parent", id),
+            BlockValidationError::FutureTimestamp(id) =>
+                write!(f, "block {} is too far in the future", id),
+            BlockValidationError::BadMerkleRoot(id) =>
+                write!(f, "block {} merkle root doesn't match its transactions", id),
+            BlockValidationError::BadTransactionId(id) =>
+                write!(f, "transaction {} id doesn't match its contents", id),
+            BlockValidationError::ReplayedTransaction(id) =>
+                write!(f, "transaction {} has already been recorded", id),
+            BlockValidationError::BadSignature(id) =>
+                write!(f, "transaction {} has an invalid signature", id),
+            BlockValidationError::MissingSignature { transaction, signer } =>
+                write!(f, "transaction {} is not signed by sender {}", transaction, signer),
+            BlockValidationError::KeyRevoked(account) =>
+                write!(f, "signing key of account {} has been revoked", account),
+            BlockValidationError::InvalidKey { account, key } =>
+                write!(f, "key {} can't be used to rotate or revoke a key of {}", key, account),
+            BlockValidationError::InvalidPolicy(account) =>
+                write!(f, "multisig policy of account {} can't be changed this way", account),
+            BlockValidationError::InvalidFlight { drone, error } =>
+                write!(f, "flight of drone {} is invalid: {}", drone, error),
+            BlockValidationError::InvalidWaypoint { waypoint, error } =>
+                write!(f, "waypoint {} can't be registered: {}", waypoint, error),
+            BlockValidationError::InvalidDrone { drone, error } =>
+                write!(f, "drone {} is invalid: {}", drone, error),
+            BlockValidationError::BadNonce { account, expected, found } =>
+                write!(
+                    f, "operation from {} has nonce {} but {} was expected",
+                    account, found, expected
+                ),
+            BlockValidationError::InsufficientBalance { account, balance, amount } =>
+                write!(
+                    f, "account {} cannot send {} with a balance of {}",
+                    account, amount, balance
+                ),
+            BlockValidationError::Overflow { account } =>
+                write!(f, "balance of account {} would overflow", account),
+            BlockValidationError::StateRootMismatch { expected, found } =>
+                write!(f, "block state root is {} but the state hashes to {}", found, expected),
+            BlockValidationError::UnknownSealer(sealer) =>
+                write!(f, "block sealer {} is not an authority", sealer),
+            BlockValidationError::OutOfTurn { sealer, expected } =>
+                write!(f, "block sealed by {} but it is {}'s turn", sealer, expected),
+            BlockValidationError::BadSeal(id) =>
+                write!(f, "block {} has an invalid seal", id),
+            BlockValidationError::WrongDifficulty { expected, found } =>
+                write!(f, "block difficulty is {} but {} is required", found, expected),
+            BlockValidationError::InsufficientWork(id) =>
+                write!(f, "block {} does not meet its difficulty", id),
+        }
+    }
+}
+
+impl Error for BlockValidationError {}
+
+/**
+    Reason `Blockchain::get_token_from_faucet` refused a grant.
+    Nothing is changed when a grant is refused.
+ */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FaucetError {
+    //  Faucet holds fewer tokens than requested
+    Exhausted { available: Amount, requested: Amount },
+    //  Grant would overflow the account's balance
+    Overflow { account: String },
+}
+
+impl fmt::Display for FaucetError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FaucetError::Exhausted { available, requested } =>
+                write!(f, "faucet has {} tokens but {} were requested", available, requested),
+            FaucetError::Overflow { account } =>
+                write!(f, "balance of account {} would overflow", account),
         }
     }
+}
+
+impl Error for FaucetError {}
+
+/**
+    Reason `Blockchain::from_genesis` refused a configuration.
+    Fork choice rules that weigh producers or work are only
+    sound when the consensus checks seals or work.
+ */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum GenesisError {
+    //  `AuthorityWeighted` without proof of authority
+    WeightsNeedAuthority,
+    //  `MostWork` without proof of work
+    WorkNeedsMining,
+}
+
+impl fmt::Display for GenesisError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            GenesisError::WeightsNeedAuthority =>
+                write!(f, "authority weighted fork choice needs proof of authority"),
+            GenesisError::WorkNeedsMining =>
+                write!(f, "most work fork choice needs proof of work"),
+        }
+    }
+}
+
+impl Error for GenesisError {}
+
+impl GenesisConfig {
+    pub fn with_consensus(mut self, consensus: Consensus) -> Self {
+        self.consensus = consensus;
+        self
+    }
+
+    pub fn with_fork_choice(mut self, fork_choice: ForkChoice) -> Self {
+        self.fork_choice = fork_choice;
+        self
+    }
+
+    pub fn with_hash_algorithm(mut self, hash_algorithm: HashAlgorithm) -> Self {
+        self.hash_algorithm = hash_algorithm;
+        self
+    }
+
+    pub fn with_chain_id(mut self, chain_id: u32) -> Self {
+        self.chain_id = chain_id;
+        self
+    }
+
+    pub fn get_consensus(&self) -> &Consensus {
+        &self.consensus
+    }
+
+    pub fn get_fork_choice(&self) -> &ForkChoice {
+        &self.fork_choice
+    }
+
+    pub fn get_hash_algorithm(&self) -> HashAlgorithm {
+        self.hash_algorithm
+    }
+
+    pub fn get_chain_id(&self) -> u32 {
+        self.chain_id
+    }
 
-    pub fn get_id(&self) -> String {
-        self.id.clone()
+    //  Whether the fork choice rule can trust what it weighs
+    pub fn check(&self) -> Result<(), GenesisError> {
+        match (&self.fork_choice, &self.consensus) {
+            (ForkChoice::AuthorityWeighted(_), Consensus::Authority(_)) => Ok(()),
+            (ForkChoice::AuthorityWeighted(_), _) => Err(GenesisError::WeightsNeedAuthority),
+            (ForkChoice::MostWork, Consensus::Work(_)) => Ok(()),
+            (ForkChoice::MostWork, _) => Err(GenesisError::WorkNeedsMining),
+            (ForkChoice::Longest, _) => Ok(()),
+        }
     }
 }
 
 impl Blockchain {
     pub fn init() -> Self {
-        let mut account = Account::gen_account();
+        Self::from_genesis(GenesisConfig::default()).expect("default genesis config is valid")
+    }
+
+    pub fn from_genesis(config: GenesisConfig) -> Result<Self, GenesisError> {
+        config.check()?;
+        let account = Account::gen_account();
 
         let operation = Operation::create_operation(
-            account.clone(), account.clone(), 0);
-        if !operation.verify_operation() {
+            &account, &account, Amount::ZERO, 0, config.chain_id);
+        if !operation.verify_operation(config.chain_id) {
             panic!("Operation is not valid");
         }
-        let transaction = Transaction::create_transaction(
-            vec![operation], get_nonce()
-        );
-        let genesis = Block::create_block(
-            vec![transaction.clone()], String::from("")
-        );
-
-        let mut coin_db: HashMap<String, u8> = HashMap::new();
-        coin_db.insert(
-            account.get_id(),
-               account.get_balance()
+        let algorithm = config.hash_algorithm;
+        let mut transaction = Transaction::create_transaction_with(
+            vec![operation], algorithm
         );
+        transaction.sign(&account.get_id(), &account.get_keysig(0), config.chain_id);
 
-        let mut history = HashMap::new();
-        history.insert(genesis.id.clone(), genesis);
+        let mut state = State::new();
+        let mut overlay = state.overlay();
+        overlay.apply_transaction(&transaction)
+            .expect("genesis transaction applies to an empty state");
+        let changes = overlay.into_changes();
+        state.commit(changes);
+        //  The genesis account doubles as the faucet
+        state.set_balance(&account.get_id(), FAUCET_SUPPLY);
 
-        let mut transaction_db = HashMap::new();
-        transaction_db.insert(
-            transaction.get_id().clone(), transaction);
+        let difficulty = match &config.consensus {
+            Consensus::Work(pow) => pow.get_initial_difficulty(),
+            _ => 0,
+        };
+        let genesis = Block::genesis(
+            vec![transaction],
+            algorithm,
+            state.root(algorithm),
+            difficulty
+        );
 
+        let chain = ChainIndex::new(genesis.get_id());
+        let mut blocks = HashMap::new();
+        blocks.insert(genesis.get_id(), genesis);
 
-        Blockchain {
-            coin_db,
-            history,
-            transaction_db,
-            faucet_coins: 100
-        }
+        Ok(Blockchain {
+            state,
+            blocks,
+            chain,
+            faucet: account,
+            faucet_coins: FAUCET_SUPPLY,
+            grants: Vec::new(),
+            config
+        })
     }
 
     pub fn show_coin_database(&self) {
         println!(
             "{}", serde_json::to_string(
-                &self.coin_db
+                self.state.get_balances()
             ).unwrap()
         )
     }
 
+    //  Commitment to the current balances and nonces
+    pub fn state_root(&self) -> Hash32 {
+        self.state.root(self.config.hash_algorithm)
+    }
+
+    pub fn get_state(&self) -> &State {
+        &self.state
+    }
+
+    //  Nonce the next operation sent by `account` must carry
+    pub fn get_next_nonce(&self, account: &str) -> u64 {
+        self.state.get_nonce(account)
+    }
+
+    //  What `account`'s signatures are checked against, `None` if its key was revoked
+    pub fn get_authority(&self, account: &str) -> Option<SigningAuthority> {
+        self.state.get_authority(account)
+    }
+
+    //  Journeys and position reports recorded for `drone`
+    pub fn get_flight_log(&self, drone: &str) -> Option<&FlightLog> {
+        self.state.get_flight_log(drone)
+    }
+
+    pub fn get_waypoint(&self, id: &str) -> Option<&Waypoint> {
+        self.state.get_waypoint(id)
+    }
+
+    //  Registered waypoints run by `operator`, by id
+    pub fn get_waypoints_by_operator(&self, operator: &str) -> Vec<&Waypoint> {
+        self.state.get_waypoints_by_operator(operator)
+    }
+
+    //  Registered waypoints inside `area`, by id
+    pub fn get_waypoints_in(&self, area: &BoundingBox) -> Vec<&Waypoint> {
+        self.state.get_waypoints_in(area)
+    }
+
+    pub fn get_drone(&self, id: &str) -> Option<&Drone> {
+        self.state.get_drone(id)
+    }
+
+    //  Drones `owner` has, decommissioned ones included, by id
+    pub fn get_drones_by_owner(&self, owner: &str) -> Vec<&Drone> {
+        self.state.get_drones_by_owner(owner)
+    }
+
+    //  M-of-N policy `account`'s signatures must meet, if it set one
+    pub fn get_multisig_policy(&self, account: &str) -> Option<&MultisigPolicy> {
+        self.state.get_multisig_policy(account)
+    }
+
+    /**
+        Assembles an unsealed block of `transactions` on top of
+        `parent`, committing to the state it would produce.
+        Mine or seal it as the chain's consensus requires before
+        passing it to `validate_block`.
+     */
+    pub fn build_block(
+        &self, transactions: Vec<Transaction>, parent: &BlockHash
+    ) -> Result<Block, BlockValidationError> {
+        let parent_block = self.blocks.get(parent)
+            .ok_or(BlockValidationError::UnknownParent(*parent))?;
+
+        let mut overlay = self.state_at(parent)
+            .map_err(|(_, e)| e)?;
+        let mut included = self.grants_missing_from(&overlay);
+        included.extend(transactions);
+        let mut block = Block::create_block(included, parent_block);
+        overlay.set_height(block.get_height());
+        overlay.set_timestamp(block.get_timestamp());
+        overlay.apply_transactions(block.get_transactions())?;
+
+        block.set_state_root(overlay.root(self.config.hash_algorithm));
+        Ok(block)
+    }
+
+    /**
+     Checks a header without its body:
+        1. parent is a known block
+        2. height is one above the parent's
+        3. version is supported
+        4. timestamp is not before the parent's nor more than
+           `MAX_FUTURE_DRIFT` ahead of the local clock, so miners
+           can't stretch a retarget interval to lower the difficulty
+        5. producer is allowed to seal it under the chain's consensus,
+           `sealer_key` being the public key the seal was made with,
+           which must be the producer's current unrevoked signing key
+     */
+    pub fn validate_header(
+        &self, header: &BlockHeader, seal: &[u8], sealer_key: &[u8]
+    ) -> Result<(), BlockValidationError> {
+        //  1
+        let parent = header.get_parent();
+        let parent_block = self.blocks.get(&parent)
+            .ok_or(BlockValidationError::UnknownParent(parent))?;
+
+        //  2
+        let expected = parent_block.get_height() + 1;
+        if header.get_height() != expected {
+            return Err(BlockValidationError::WrongHeight {
+                expected, found: header.get_height()
+            });
+        }
+
+        //  3
+        if header.get_version() != BLOCK_VERSION {
+            return Err(BlockValidationError::UnsupportedVersion(header.get_version()));
+        }
+
+        //  4
+        let id = header.hash(self.config.hash_algorithm);
+        if header.get_timestamp() < parent_block.get_timestamp() {
+            return Err(BlockValidationError::InvalidTimestamp(id));
+        }
+        if header.get_timestamp() > get_timestamp().saturating_add(MAX_FUTURE_DRIFT) {
+            return Err(BlockValidationError::FutureTimestamp(id));
+        }
+
+        //  5, seals are made with the producer's signing key as of the parent
+        let difficulty = self.next_difficulty(&parent);
+        let authority = |account: &str| self.state_at(&parent)
+            .ok()
+            .and_then(|state| state.get_authority(account));
+        self.config.consensus.verify_header(header, &id, seal, sealer_key, difficulty, authority)
+    }
+
     /**
      Checks:
-        1. previous exists in history
-        2. block not in history
-        3. block doesn't have conflicting transactions
-        4. verify operations
+        1. previous is a known block
+        2. block not already known
+        3. block hashed with the chain's algorithm
+        4. header is valid, see `validate_header`
+        5. transaction ids and merkle root match the transactions
+        6. block doesn't repeat a transaction
+        7. every sender and every drone being registered signed
+           the transaction and nobody else did
+        8. operation and transaction signatures verify under each
+           sender's signing key or multisig policy, the key must not
+           have been revoked
+        9. transactions not already on the chain
+        10. operations carry their sender's next nonce, senders can
+            cover amounts and receivers don't overflow, key rotations
+            and revocations name the sender's signing key
+        11. journey legs and position reports come from registered,
+            active drones, follow their last ones and aren't later
+            than the block, legs run between registered waypoints
+            and end at an active one
+        12. waypoints and drones are registered by their operator or
+            owner under a new id, only the owner transfers a drone,
+            to a valid address, or decommissions it
+        13. state root matches the resulting state
+
+     Blocks extending the tip are checked in full and applied.
+     Blocks on another branch are stored after checks 1-7 and only
+     applied once the fork choice rule prefers their branch, at
+     which point the chain reorganises onto it.
+
+     Returns the id of the accepted block, or the first failed check.
      */
-    pub fn validate_block(&mut self, mut block: Block) -> bool {
-        let mut is_valid = false;
+    pub fn validate_block(&mut self, block: Block) -> Result<BlockHash, BlockValidationError> {
+        let id = block.get_id();
+        let previous = block.get_previous();
 
         //  1
-        if self.history.contains_key(block.previous.as_str()) {
-            is_valid = true;
-        } else { is_valid = false; }
+        if !self.chain.contains(&previous) {
+            return Err(BlockValidationError::UnknownParent(previous));
+        }
 
         //  2
-        if !self.history.contains_key(
-            block.id.as_str()
-        ) {
-            is_valid = true;
-        } else { is_valid = false; }
+        if self.chain.contains(&id) {
+            return Err(BlockValidationError::DuplicateBlock(id));
+        }
 
         //  3
-        for i in 0..block.transactions.len() {
-            let transaction = block.transactions[i].clone();
-            if !self.transaction_db.contains_key(
-                transaction.get_id().as_str()
-            ) {
-                is_valid = true;
-            } else {
-                is_valid = false;
+        let algorithm = self.config.hash_algorithm;
+        if block.get_hash_algorithm() != algorithm {
+            return Err(BlockValidationError::WrongHashAlgorithm {
+                expected: algorithm, found: block.get_hash_algorithm()
+            });
+        }
+
+        //  4
+        self.validate_header(block.get_header(), block.get_seal(), block.get_sealer_key())?;
+
+        //  5
+        for transaction in block.get_transactions() {
+            if transaction.compute_id(algorithm) != transaction.get_id() {
+                return Err(BlockValidationError::BadTransactionId(transaction.get_id()));
+            }
+        }
+        if block.get_header().get_merkle_root()
+            != Block::compute_merkle_root(block.get_transactions(), algorithm) {
+            return Err(BlockValidationError::BadMerkleRoot(id));
+        }
+
+        //  6
+        let mut seen = HashSet::new();
+        for transaction in block.get_transactions() {
+            let id = transaction.get_id();
+            if !seen.insert(id) {
+                return Err(BlockValidationError::ReplayedTransaction(id));
             }
+        }
+
+        //  7
+        for transaction in block.get_transactions() {
+            transaction.check_signers()
+                .map_err(|e| Self::signature_error(transaction, e))?;
+        }
+
+        let weight = self.config.fork_choice.weight(&block);
 
-            is_valid = transaction.verify_operations();
+        //  8 to 11 against the current state
+        if previous == *self.chain.tip() {
+            let mut overlay = self.state.overlay();
+            Self::connect(&mut overlay, &block, &self.config)?;
+            let changes = overlay.into_changes();
+            self.state.commit(changes);
+            self.chain.insert(id, previous, weight);
+            self.blocks.insert(id, block);
+            return Ok(id);
         }
 
-        //  Add block to history and update balances
-        if is_valid {
-            let b = block.clone();
+        self.chain.insert(id, previous, weight);
+        self.blocks.insert(id, block);
+        if self.chain.weight(&id) > self.chain.weight(self.chain.tip()) {
+            self.reorganize(&id)?;
+        }
+
+        Ok(id)
+    }
 
-            //  update coin db
-            for mut transaction in block.transactions.iter_mut() {
-                for operation in transaction.get_operations() {
-                    operation.update_coin_db(&mut self.coin_db)
+    /**
+        Difficulty required of a block built on `parent`.
+        Under proof of work it is retargeted every `retarget_interval`
+        blocks from the timestamps of the previous interval, otherwise
+        it is always 0. Panics if `parent` is not a known block.
+     */
+    pub fn next_difficulty(&self, parent: &BlockHash) -> u32 {
+        let pow = match &self.config.consensus {
+            Consensus::Work(pow) => pow,
+            _ => return 0,
+        };
+
+        let parent_block = &self.blocks[parent];
+        let difficulty = parent_block.get_header().get_difficulty();
+        let height = parent_block.get_height() + 1;
+        let interval = pow.get_retarget_interval();
+        if interval == 0 || !height.is_multiple_of(interval) {
+            return difficulty;
+        }
+
+        //  Block at `height - interval`, the start of the closing interval
+        let first = self.chain.ancestors(parent)
+            .nth(interval as usize - 1)
+            .unwrap();
+        pow.retarget(
+            difficulty,
+            parent_block.get_timestamp().saturating_sub(self.blocks[first].get_timestamp())
+        )
+    }
+
+    /**
+        Switches the canonical chain to the branch ending at `new_tip`.
+        If a block of the new branch fails, the current branch is
+        kept and the failing block is dropped along with everything
+        built on it.
+     */
+    fn reorganize(&mut self, new_tip: &BlockHash) -> Result<(), BlockValidationError> {
+        match self.state_at(new_tip).map(StateOverlay::into_changes) {
+            Ok(changes) => {
+                self.state.commit(changes);
+                self.chain.set_tip(new_tip);
+                Ok(())
+            }
+            Err((failed, e)) => {
+                for removed in self.chain.remove(&failed) {
+                    self.blocks.remove(&removed);
                 }
+                Err(e)
+            }
+        }
+    }
+
+    /**
+        Overlay on the current state holding the changes that make
+        `id` the tip. Blocks above the common ancestor with the
+        canonical chain are reverted, then `id`'s branch is applied
+        from the bottom up. On failure returns the offending block.
+     */
+    fn state_at(&self, id: &BlockHash) -> Result<
+        StateOverlay<'_>, (BlockHash, BlockValidationError)
+    > {
+        let mut overlay = self.state.overlay();
+
+        let tip = self.chain.tip();
+        let ancestor = self.chain.common_ancestor(tip, id)
+            .expect("all branches share the genesis block");
+
+        for reverted in self.chain.ancestors(tip).take_while(|b| *b != ancestor) {
+            Self::disconnect(&mut overlay, &self.blocks[reverted]);
+        }
+
+        let mut branch: Vec<&BlockHash> = self.chain.ancestors(id)
+            .take_while(|b| *b != ancestor)
+            .collect();
+        branch.reverse();
+        for applied in branch {
+            Self::connect(&mut overlay, &self.blocks[applied], &self.config)
+                .map_err(|e| (*applied, e))?;
+        }
+
+        Ok(overlay)
+    }
+
+    //  Faucet grants a block on top of `overlay` still has to carry
+    fn grants_missing_from(&self, overlay: &StateOverlay) -> Vec<Transaction> {
+        self.grants.iter()
+            .filter(|grant| !overlay.contains_transaction(&grant.get_id()))
+            .cloned()
+            .collect()
+    }
+
+    /**
+        Faucet grants not yet in the branch ending at `parent`.
+        `build_block` puts them first, blocks assembled by hand
+        on `parent` must do the same.
+     */
+    pub fn pending_grants(&self, parent: &BlockHash) -> Vec<Transaction> {
+        self.state_at(parent)
+            .map(|overlay| self.grants_missing_from(&overlay))
+            .unwrap_or_default()
+    }
+
+    fn signature_error(transaction: &Transaction, e: SignatureError) -> BlockValidationError {
+        match e {
+            SignatureError::Missing(signer) => BlockValidationError::MissingSignature {
+                transaction: transaction.get_id(), signer
+            },
+            SignatureError::Revoked(account) => BlockValidationError::KeyRevoked(account),
+            _ => BlockValidationError::BadSignature(transaction.get_id()),
+        }
+    }
+
+    /**
+        Applies a block to the overlay, all or nothing. Each
+        transaction's signatures are checked against the keys and
+        policies in force just before it, so a rotation or policy
+        change takes effect for the transactions after it.
+     */
+    fn connect(
+        overlay: &mut StateOverlay,
+        block: &Block,
+        config: &GenesisConfig
+    ) -> Result<(), BlockValidationError> {
+        let checkpoint = overlay.checkpoint();
+        overlay.set_height(block.get_height());
+        overlay.set_timestamp(block.get_timestamp());
+        for transaction in block.get_transactions() {
+            let applied = transaction
+                .verify_with(config.chain_id, |account| overlay.get_authority(account))
+                .map_err(|e| Self::signature_error(transaction, e))
+                .and_then(|_| overlay.apply_transaction(transaction));
+            if let Err(e) = applied {
+                overlay.rollback(checkpoint);
+                return Err(e);
             }
+        }
+
+        let expected = overlay.root(config.hash_algorithm);
+        let found = block.get_header().get_state_root();
+        if expected != found {
+            overlay.rollback(checkpoint);
+            return Err(BlockValidationError::StateRootMismatch { expected, found });
+        }
+
+        Ok(())
+    }
+
+    //  Undoes `connect` for the block at the tip of the overlay
+    fn disconnect(overlay: &mut StateOverlay, block: &Block) {
+        for transaction in block.get_transactions().iter().rev() {
+            overlay.revert_transaction(transaction);
+        }
+    }
+
+    pub fn get_block(&self, id: &BlockHash) -> Option<&Block> {
+        self.blocks.get(id)
+    }
+
+    //  Canonical block at `height`, genesis being 0
+    pub fn get_block_by_height(&self, height: u64) -> Option<&Block> {
+        self.chain.at_height(height)
+            .map(|id| &self.blocks[id])
+    }
 
-            self.history.insert(b.id.clone(), b);
+    pub fn genesis(&self) -> &Block {
+        &self.blocks[self.chain.genesis()]
+    }
+
+    //  Latest block of the canonical chain
+    pub fn tip(&self) -> &Block {
+        &self.blocks[self.chain.tip()]
+    }
+
+    pub fn height(&self) -> u64 {
+        self.chain.tip_height()
+    }
+
+    //  Canonical blocks from genesis to tip, `.rev()` walks back from the tip
+    pub fn iter(&self) -> impl DoubleEndedIterator<Item = &Block> + '_ {
+        self.chain.iter().map(move |id| &self.blocks[id])
+    }
 
+    /**
+        Finds `tx_id` on the canonical chain and returns the height
+        of its block with a proof that can be checked against that
+        block's header merkle root using `hash::verify_inclusion`.
+     */
+    pub fn prove_transaction(&self, tx_id: &Hash32) -> Option<(u64, MerkleProof)> {
+        if !self.state.contains_transaction(tx_id) {
+            return None;
         }
 
-        is_valid
+        self.iter().rev().find_map(|block| {
+            block.prove_inclusion(tx_id).map(|proof| (block.get_height(), proof))
+        })
+    }
+
+    pub fn get_chain_index(&self) -> &ChainIndex {
+        &self.chain
     }
 
-    pub fn get_history(&self) -> &HashMap<String, Block> {
-        &self.history
+    pub fn get_config(&self) -> &GenesisConfig {
+        &self.config
     }
 
     /**
     On account create:
-        1. coin db updated
-        2. faucet coins and
-        3. account balance after coin allocation
+        1. faucet coins reserved for the grant
+        2. transfer from the faucet signed and queued
+        3. account balance set to what it holds once the grant lands
+
+    Nothing changes until a block carrying the transfer is accepted.
+    Every block `build_block` assembles carries the grants its
+    branch is missing, so grants follow reorgs like any transaction.
      */
-    pub fn get_token_from_faucet(&mut self, account: &mut Account, amount: u8) {
-        let old = account.get_balance();
-        account.update_balance(old + amount);
-        let new_balance = account.get_balance();
+    pub fn get_token_from_faucet(
+        &mut self, account: &mut Account, amount: Amount
+    ) -> Result<(), FaucetError> {
+        let remaining = self.faucet_coins.checked_sub(amount).ok_or(
+            FaucetError::Exhausted { available: self.faucet_coins, requested: amount }
+        )?;
 
-        self.faucet_coins -= amount;
+        let old = self.state.get_balance(&account.get_id());
+        let new_balance = old.checked_add(amount).ok_or(
+            FaucetError::Overflow { account: account.get_id() }
+        )?;
 
-        self.coin_db.insert(
-            account.get_id(), new_balance
+        let chain_id = self.config.chain_id;
+        //  Nonce 0 went to the genesis transaction
+        let nonce = self.grants.len() as u64 + 1;
+        let operation = Operation::create_operation(
+            account, &self.faucet, amount, nonce, chain_id
+        );
+        let mut grant = Transaction::create_transaction_with(
+            vec![operation], self.config.hash_algorithm
         );
+        grant.sign(&self.faucet.get_id(), &self.faucet.get_keysig(0), chain_id);
+
+        self.faucet_coins = remaining;
+        self.grants.push(grant);
+        account.update_balance(new_balance);
+        Ok(())
     }
 
     pub fn print_blockchain(&self) {
         println!(
             "Coin database: {}",
-            serde_json::to_string(&self.coin_db).unwrap()
+            serde_json::to_string(self.state.get_balances()).unwrap()
         );
+        let history: Vec<&Block> = self.iter().collect();
         println!(
             "History: {}",
-            serde_json::to_string(&self.history).unwrap()
+            serde_json::to_string(&history).unwrap()
         );
         println!(
             "Transaction Database: {}",
-            serde_json::to_string(&self.transaction_db).unwrap()
+            serde_json::to_string(self.state.get_transactions()).unwrap()
         );
         println!(
             "Faucet coins: {}",
@@ -179,85 +849,1204 @@ impl Blockchain {
     }
 
     pub fn update_account(&self, account: &mut Account) {
-        let b = self.coin_db.get(
-            account.get_id().as_str()).unwrap();
-        account.update_balance(
-            b.clone()
-        )
+        account.update_balance(self.state.get_balance(&account.get_id()))
     }
 }
 
 #[cfg(test)]
 mod tests {
     use std::borrow::Borrow;
+    use std::collections::HashMap;
     use crate::account::Account;
-    use crate::blockchain::{Block, Blockchain};
-    use crate::transops::{get_nonce, Operation, Transaction};
+    use crate::amount::Amount;
+    use crate::blockchain::{
+        Block, BlockHash, Blockchain, BlockValidationError, FaucetError, ForkChoice, GenesisConfig,
+        GenesisError, MAX_FUTURE_DRIFT
+    };
+    use crate::consensus::{meets_difficulty, Consensus, ProofOfAuthority, ProofOfWork};
+    use crate::drone::DroneError;
+    use crate::flight::{FlightError, Leg, Position};
+    use crate::hash::{verify_inclusion, Hash32, HashAlgorithm};
+    use crate::keysig::{KeyAlgorithm, KeySig};
+    use crate::multisig::{MultisigPolicy, SigningAuthority};
+    use crate::transops::{Operation, OperationKind, Transaction};
+    use crate::utils::get_timestamp;
+    use crate::waypoint::{AltitudeBand, BoundingBox, Waypoint, WaypointStatus};
+
+    fn get_genesis(bc: &Blockchain) -> BlockHash {
+        bc.genesis().get_id()
+    }
 
-    fn get_operation() -> Operation {
-        let account1 = Account::gen_account();
-        let account2 = Account::gen_account();
+    //  Transaction moving `amount` from a sender funded with `funds`
+    fn get_funded_transaction(bc: &mut Blockchain, funds: u64, amount: u64) -> Transaction {
+        let mut sender = Account::gen_account();
+        let receiver = Account::gen_account();
+        bc.get_token_from_faucet(&mut sender, Amount::new(funds)).unwrap();
 
-        Operation::create_operation(account1, account2, 1)
+        let op = Operation::create_operation(
+            &receiver, &sender, Amount::new(amount), 0, bc.get_config().get_chain_id()
+        );
+        let mut trans = Transaction::create_transaction(vec![op]);
+        sender.sign_transaction(&mut trans, bc.get_config().get_chain_id());
+        trans
     }
 
-    fn get_transaction() -> Transaction {
-        let op = get_operation();
-        Transaction::create_transaction(
-            vec![op], get_nonce()
-        )
+    //  Valid block on `parent` holding a single funded transfer
+    fn get_block(bc: &mut Blockchain, parent: &BlockHash) -> Block {
+        let trans = get_funded_transaction(bc, 5, 1);
+        bc.build_block(vec![trans], parent).unwrap()
     }
 
     #[test]
     fn test_create_block() {
-        let trans = get_transaction();
+        let mut bc = Blockchain::init();
+        let trans = get_funded_transaction(&mut bc, 5, 1);
         let block = Block::create_block(
-            vec![trans], "".to_string()
+            vec![trans], bc.genesis()
         );
 
-        assert!(!block.get_id().is_empty())
+        assert_ne!(block.get_id(), Hash32::default());
+        assert_eq!(block.get_height(), 1);
+        assert_eq!(block.get_previous(), get_genesis(&bc));
     }
 
     #[test]
     fn test_blockchain_init() {
         let bc = Blockchain::init();
-        assert!(
-            !bc.get_history().is_empty()
-        )
+        assert_eq!(bc.height(), 0);
+        assert_eq!(bc.tip().get_id(), bc.genesis().get_id());
+        assert_eq!(bc.genesis().get_header().get_state_root(), bc.state_root());
     }
 
     #[test]
     fn test_blockchain_add_block() {
         let mut bc = Blockchain::init();
-        let mut prev = String::from("");
-        let mut i = 0;
-        for x in bc.history.keys() {
-            prev = x.to_string();
-            if i == 0 { break; }
+        let prev = get_genesis(&bc);
+
+        let block = get_block(&mut bc, &prev);
+        let id = block.get_id();
+
+        assert_eq!(bc.validate_block(block), Ok(id));
+        assert_eq!(bc.tip().get_header().get_state_root(), bc.state_root());
+    }
+
+    #[test]
+    fn test_validate_header_without_body() {
+        let mut bc = Blockchain::init();
+        let prev = get_genesis(&bc);
+
+        let block = get_block(&mut bc, &prev);
+        let header = block.get_header().clone();
+
+        assert!(bc.validate_header(&header, block.get_seal(), block.get_sealer_key()).is_ok());
+        assert_eq!(header.hash(HashAlgorithm::Sha256), block.get_id());
+    }
+
+    #[test]
+    fn test_validate_block_unknown_parent() {
+        let mut bc = Blockchain::init();
+        let trans = get_funded_transaction(&mut bc, 5, 1);
+
+        let orphan = Block::create_block(vec![], bc.genesis());
+        let block = Block::create_block(vec![trans], &orphan);
+
+        assert_eq!(
+            bc.validate_block(block),
+            Err(BlockValidationError::UnknownParent(orphan.get_id()))
+        );
+    }
+
+    #[test]
+    fn test_validate_block_duplicate_block() {
+        let mut bc = Blockchain::init();
+        let prev = get_genesis(&bc);
+
+        let block = get_block(&mut bc, &prev);
+        let id = block.get_id();
+        bc.validate_block(block.clone()).unwrap();
+
+        assert_eq!(
+            bc.validate_block(block),
+            Err(BlockValidationError::DuplicateBlock(id))
+        );
+    }
+
+    #[test]
+    fn test_validate_block_timestamp_before_parent() {
+        let mut bc = Blockchain::init();
+        let prev = get_genesis(&bc);
+
+        let mut block = get_block(&mut bc, &prev);
+        block.set_timestamp(bc.genesis().get_timestamp() - 1);
+        let id = block.get_id();
+
+        assert_eq!(
+            bc.validate_block(block),
+            Err(BlockValidationError::InvalidTimestamp(id))
+        );
+    }
+
+    #[test]
+    fn test_validate_block_timestamp_in_future() {
+        let mut bc = Blockchain::init();
+        let prev = get_genesis(&bc);
+
+        let mut block = get_block(&mut bc, &prev);
+        block.set_timestamp(get_timestamp() + MAX_FUTURE_DRIFT + 60);
+        let id = block.get_id();
+        assert_eq!(
+            bc.validate_block(block),
+            Err(BlockValidationError::FutureTimestamp(id))
+        );
+
+        let mut block = get_block(&mut bc, &prev);
+        block.set_timestamp(get_timestamp() + MAX_FUTURE_DRIFT - 60);
+        assert!(bc.validate_block(block).is_ok());
+    }
+
+    #[test]
+    fn test_validate_block_replayed_transaction() {
+        let mut bc = Blockchain::init();
+        let trans = get_funded_transaction(&mut bc, 5, 1);
+        let id = trans.get_id();
+
+        let block = Block::create_block(vec![trans.clone(), trans.clone()], bc.genesis());
+        assert_eq!(
+            bc.validate_block(block),
+            Err(BlockValidationError::ReplayedTransaction(id))
+        );
+
+        let first = bc.build_block(vec![trans.clone()], &get_genesis(&bc)).unwrap();
+        let first = bc.validate_block(first).unwrap();
+        let replay = Block::create_block(vec![trans], bc.get_block(&first).unwrap());
+        assert_eq!(
+            bc.validate_block(replay),
+            Err(BlockValidationError::ReplayedTransaction(id))
+        );
+    }
+
+    #[test]
+    fn test_validate_block_operation_for_other_chain() {
+        let mut bc = Blockchain::from_genesis(GenesisConfig::default().with_chain_id(1)).unwrap();
+        let genesis = get_genesis(&bc);
+        let mut sender = Account::gen_account();
+        bc.get_token_from_faucet(&mut sender, Amount::new(5)).unwrap();
+
+        let op = Operation::create_operation(&Account::gen_account(), &sender, Amount::new(1), 0, 2);
+        let mut trans = Transaction::create_transaction(vec![op]);
+        sender.sign_transaction(&mut trans, 1);
+        let id = trans.get_id();
+        let block = bc.build_block(vec![trans], &genesis).unwrap();
+
+        assert_eq!(bc.validate_block(block), Err(BlockValidationError::BadSignature(id)));
+    }
+
+    //  Transfer of 1 token to a fresh account, signed by `sender` with its signing key
+    fn get_signed_transfer(bc: &Blockchain, sender: &Account) -> Transaction {
+        let op = Operation::create_operation(
+            &Account::gen_account_with(KeyAlgorithm::Ed25519), sender, Amount::new(1),
+            bc.get_next_nonce(&sender.get_id()), 0
+        );
+        let mut trans = Transaction::create_transaction(vec![op]);
+        sender.sign_transaction(&mut trans, 0);
+        trans
+    }
+
+    #[test]
+    fn test_rotate_key() {
+        let mut bc = Blockchain::init();
+        let mut drone = Account::gen_account_with(KeyAlgorithm::Ed25519);
+        bc.get_token_from_faucet(&mut drone, Amount::new(5)).unwrap();
+        let old_key = hex::encode(drone.get_keysig(0).get_public_key());
+        let new_key = KeySig::generate(KeyAlgorithm::Ed25519);
+
+        let rotation = Operation::create_key_rotation(&drone, &new_key, 0, 0);
+        let mut trans = Transaction::create_transaction(vec![rotation]);
+        drone.sign_transaction(&mut trans, 0);
+        let block = bc.build_block(vec![trans], &get_genesis(&bc)).unwrap();
+        let rotated = bc.validate_block(block).unwrap();
+
+        let new_id = hex::encode(new_key.get_public_key());
+        assert_eq!(bc.get_authority(&drone.get_id()), Some(SigningAuthority::Key(new_id.clone())));
+        let revocation = bc.get_state().get_revocation(&old_key).unwrap();
+        assert_eq!(revocation.get_height(), 1);
+        assert_eq!(revocation.get_replaced_by(), Some(new_id.as_str()));
+
+        //  The old key no longer signs for the account
+        let stale = get_signed_transfer(&bc, &drone);
+        let block = bc.build_block(vec![stale.clone()], &rotated).unwrap();
+        assert_eq!(
+            bc.validate_block(block),
+            Err(BlockValidationError::BadSignature(stale.get_id()))
+        );
+
+        //  The id stays, only the key it signs with changes
+        drone.add_key_pair_to_wallet(new_key);
+        drone.set_signing_key(1);
+        let trans = get_signed_transfer(&bc, &drone);
+        let block = bc.build_block(vec![trans], &rotated).unwrap();
+        bc.validate_block(block).unwrap();
+        assert_eq!(bc.get_state().get_balance(&drone.get_id()), Amount::new(4));
+    }
+
+    #[test]
+    fn test_revoke_key() {
+        let mut bc = Blockchain::init();
+        let mut drone = Account::gen_account_with(KeyAlgorithm::Ed25519);
+        bc.get_token_from_faucet(&mut drone, Amount::new(5)).unwrap();
+
+        let revocation = Operation::create_key_revocation(&drone, 0, 0);
+        let mut trans = Transaction::create_transaction(vec![revocation]);
+        drone.sign_transaction(&mut trans, 0);
+        let block = bc.build_block(vec![trans], &get_genesis(&bc)).unwrap();
+        let revoked = bc.validate_block(block).unwrap();
+        assert_eq!(bc.get_authority(&drone.get_id()), None);
+
+        let trans = get_signed_transfer(&bc, &drone);
+        let block = bc.build_block(vec![trans], &revoked).unwrap();
+        assert_eq!(
+            bc.validate_block(block),
+            Err(BlockValidationError::KeyRevoked(drone.get_id()))
+        );
+
+        //  Nor does the key sign for the upper case spelling of the address
+        let alias = drone.get_id().to_uppercase();
+        let kind = OperationKind::Transfer {
+            receiver: Account::gen_account().get_id(), amount: Amount::ZERO
+        };
+        let mut op = Operation::with_kind(alias.clone(), kind, 0);
+        op.sign(drone.get_signing_keysig(), 0);
+        let mut trans = Transaction::create_transaction(vec![op]);
+        trans.sign(&alias, drone.get_signing_keysig(), 0);
+        let id = trans.get_id();
+        let block = bc.build_block(vec![trans], &revoked).unwrap();
+        assert_eq!(bc.validate_block(block), Err(BlockValidationError::BadSignature(id)));
+    }
+
+    //  A rotation on a branch that loses a reorg is undone with it
+    #[test]
+    fn test_reorg_reverts_rotation() {
+        let mut bc = Blockchain::init();
+        let genesis = get_genesis(&bc);
+        let mut drone = Account::gen_account_with(KeyAlgorithm::Ed25519);
+        bc.get_token_from_faucet(&mut drone, Amount::new(5)).unwrap();
+
+        let rotation = Operation::create_key_rotation(
+            &drone, &KeySig::generate(KeyAlgorithm::Ed25519), 0, 0
+        );
+        let mut trans = Transaction::create_transaction(vec![rotation]);
+        drone.sign_transaction(&mut trans, 0);
+        let block = bc.build_block(vec![trans], &genesis).unwrap();
+        bc.validate_block(block).unwrap();
+        let unrotated = Some(SigningAuthority::Address(drone.get_id()));
+        assert_ne!(bc.get_authority(&drone.get_id()), unrotated);
+
+        //  Longer branch where the drone keeps its key
+        let mut parent = genesis;
+        for _ in 0..2 {
+            let mut block = bc.build_block(vec![], &parent).unwrap();
+            block.set_timestamp(block.get_timestamp() + 1);
+            parent = bc.validate_block(block).unwrap();
         }
 
-        let trans = get_transaction();
+        assert_eq!(bc.tip().get_id(), parent);
+        assert_eq!(bc.get_authority(&drone.get_id()), unrotated);
+        let key = hex::encode(drone.get_keysig(0).get_public_key());
+        assert!(bc.get_state().get_revocation(&key).is_none());
+    }
 
+    //  Registration of `drone` by `company`, signed by both
+    fn get_drone_registration(
+        company: &Account, drone: &Account, serial: &str, nonce: u64
+    ) -> Transaction {
+        let registration = Operation::create_drone_registration(company, drone, serial, nonce, 0);
+        let mut trans = Transaction::create_transaction(vec![registration]);
+        company.sign_transaction(&mut trans, 0);
+        drone.sign_transaction(&mut trans, 0);
+        trans
+    }
 
-        let block = Block::create_block(
-            vec![trans],
-            prev
+    //  Signed registration of waypoints run by `operator`, in Kyiv
+    fn get_waypoint_registration(
+        operator: &Account, statuses: &[WaypointStatus], nonce: u64
+    ) -> (Vec<String>, Transaction) {
+        let band = AltitudeBand::new(0, 120_000);
+        let waypoints: Vec<Waypoint> = statuses.iter()
+            .map(|status| Waypoint::new(
+                Account::gen_account().get_id(), operator.get_id(),
+                504_501_000, 305_234_000, band, 4, *status
+            ))
+            .collect();
+        let registrations = waypoints.iter().zip(nonce..)
+            .map(|(waypoint, nonce)| Operation::create_waypoint_registration(operator, waypoint, nonce, 0))
+            .collect();
+        let mut trans = Transaction::create_transaction(registrations);
+        operator.sign_transaction(&mut trans, 0);
+        (waypoints.iter().map(|waypoint| waypoint.get_id().to_string()).collect(), trans)
+    }
+
+    //  Drone flies between two waypoints and reports where it is
+    #[test]
+    fn test_record_flight() {
+        let mut bc = Blockchain::init();
+        let company = Account::gen_account_with(KeyAlgorithm::Ed25519);
+        let drone = Account::gen_account_with(KeyAlgorithm::Ed25519);
+        let statuses = [WaypointStatus::Active, WaypointStatus::Active, WaypointStatus::Closed];
+        let (ids, waypoints) = get_waypoint_registration(&company, &statuses, 1);
+        let (depot, site, closed) = (ids[0].clone(), ids[1].clone(), ids[2].clone());
+        let now = bc.genesis().get_timestamp();
+
+        let leg = Leg::new(depot.clone(), site.clone(), now - 60, now - 10);
+        let position = Position::new(504_501_000, 305_234_000, 0, 0, 0, now - 10);
+        let mut trans = Transaction::create_transaction(vec![
+            Operation::create_journey_leg(&drone, leg.clone(), 0, 0),
+            Operation::create_position_report(&drone, position.clone(), 1, 0),
+        ]);
+        drone.sign_transaction(&mut trans, 0);
+        let registration = get_drone_registration(&company, &drone, "DJI-0001", 0);
+        let block = bc.build_block(vec![registration, waypoints, trans], &get_genesis(&bc)).unwrap();
+        let tip = bc.validate_block(block).unwrap();
+
+        let log = bc.get_flight_log(&drone.get_id()).unwrap();
+        assert_eq!(log.get_legs(), &[leg]);
+        assert_eq!(log.get_location(), Some(site.as_str()));
+        assert_eq!(log.get_last_position(), Some(&position));
+
+        //  The next leg has to start where the drone is
+        let stray = Leg::new(depot.clone(), site.clone(), now - 5, now - 1);
+        let mut trans = Transaction::create_transaction(vec![
+            Operation::create_journey_leg(&drone, stray, 2, 0)
+        ]);
+        drone.sign_transaction(&mut trans, 0);
+        assert_eq!(
+            bc.build_block(vec![trans], &tip).err(),
+            Some(BlockValidationError::InvalidFlight {
+                drone: drone.get_id(),
+                error: FlightError::WrongOrigin {
+                    expected: log.get_location().unwrap().to_string(), found: depot
+                }
+            })
+        );
+
+        //  Nor can it land where drones aren't taken anymore
+        let to_closed = Leg::new(site, closed.clone(), now - 5, now - 1);
+        let mut trans = Transaction::create_transaction(vec![
+            Operation::create_journey_leg(&drone, to_closed, 2, 0)
+        ]);
+        drone.sign_transaction(&mut trans, 0);
+        assert_eq!(
+            bc.build_block(vec![trans], &tip).err(),
+            Some(BlockValidationError::InvalidFlight {
+                drone: drone.get_id(), error: FlightError::ClosedWaypoint(closed)
+            })
+        );
+    }
+
+    //  Drone changes hands, then is retired and can't fly anymore
+    #[test]
+    fn test_drone_registry() {
+        let mut bc = Blockchain::init();
+        let (seller, buyer) = (Account::gen_account(), Account::gen_account());
+        let drone = Account::gen_account_with(KeyAlgorithm::Ed25519);
+
+        let registration = get_drone_registration(&seller, &drone, "DJI-0001", 0);
+        let handover = Operation::create_drone_transfer(&seller, &drone.get_id(), &buyer.get_id(), 1, 0);
+        let mut trans = Transaction::create_transaction(vec![handover]);
+        seller.sign_transaction(&mut trans, 0);
+        let block = bc.build_block(vec![registration, trans], &get_genesis(&bc)).unwrap();
+        let tip = bc.validate_block(block).unwrap();
+
+        let registered = bc.get_drone(&drone.get_id()).unwrap();
+        assert_eq!(registered.get_owner(), buyer.get_id());
+        assert_eq!(registered.get_transponder(), hex::encode(drone.get_keysig(0).get_public_key()));
+        assert_eq!(bc.get_drones_by_owner(&buyer.get_id()), vec![registered]);
+        assert!(bc.get_drones_by_owner(&seller.get_id()).is_empty());
+
+        let retirement = Operation::create_drone_decommission(&buyer, &drone.get_id(), 0, 0);
+        let mut trans = Transaction::create_transaction(vec![retirement]);
+        buyer.sign_transaction(&mut trans, 0);
+        let block = bc.build_block(vec![trans], &tip).unwrap();
+        let tip = bc.validate_block(block).unwrap();
+
+        let position = Position::new(0, 0, 0, 0, 0, bc.genesis().get_timestamp());
+        let mut trans = Transaction::create_transaction(vec![
+            Operation::create_position_report(&drone, position, 0, 0)
+        ]);
+        drone.sign_transaction(&mut trans, 0);
+        assert_eq!(
+            bc.build_block(vec![trans], &tip).err(),
+            Some(BlockValidationError::InvalidDrone {
+                drone: drone.get_id(), error: DroneError::Decommissioned
+            })
+        );
+    }
+
+    //  A drone nobody registered is taken for a spoof
+    #[test]
+    fn test_reject_unregistered_drone() {
+        let bc = Blockchain::init();
+        let drone = Account::gen_account_with(KeyAlgorithm::Ed25519);
+        let position = Position::new(0, 0, 0, 0, 0, bc.genesis().get_timestamp());
+        let mut trans = Transaction::create_transaction(vec![
+            Operation::create_position_report(&drone, position, 0, 0)
+        ]);
+        drone.sign_transaction(&mut trans, 0);
+
+        assert_eq!(
+            bc.build_block(vec![trans], &get_genesis(&bc)).err(),
+            Some(BlockValidationError::InvalidDrone {
+                drone: drone.get_id(), error: DroneError::Unregistered
+            })
+        );
+    }
+
+    //  Nobody can register a drone whose transponder didn't sign
+    #[test]
+    fn test_reject_unsigned_transponder() {
+        let mut bc = Blockchain::init();
+        let genesis = get_genesis(&bc);
+        let company = Account::gen_account_with(KeyAlgorithm::Ed25519);
+        let drone = Account::gen_account_with(KeyAlgorithm::Ed25519);
+
+        let registration = Operation::create_drone_registration(&company, &drone, "DJI-0001", 0, 0);
+        let mut trans = Transaction::create_transaction(vec![registration]);
+        company.sign_transaction(&mut trans, 0);
+        let id = trans.get_id();
+        let block = bc.build_block(vec![trans.clone()], &genesis).unwrap();
+        assert_eq!(
+            bc.validate_block(block),
+            Err(BlockValidationError::MissingSignature { transaction: id, signer: drone.get_id() })
+        );
+
+        //  Signing in the drone's name takes its transponder key
+        trans.sign(&drone.get_id(), company.get_signing_keysig(), 0);
+        let block = bc.build_block(vec![trans], &genesis).unwrap();
+        assert_eq!(bc.validate_block(block), Err(BlockValidationError::BadSignature(id)));
+        assert!(bc.get_drone(&drone.get_id()).is_none());
+    }
+
+    //  Operator registers depots and finds them by operator and area
+    #[test]
+    fn test_waypoint_registry() {
+        let mut bc = Blockchain::init();
+        let operator = Account::gen_account_with(KeyAlgorithm::Ed25519);
+        let band = AltitudeBand::new(0, 120_000);
+        //  Kyiv and Lviv
+        let kyiv = Waypoint::new(
+            Account::gen_account().get_id(), operator.get_id(),
+            504_501_000, 305_234_000, band, 4, WaypointStatus::Active
+        );
+        let lviv = Waypoint::new(
+            Account::gen_account().get_id(), operator.get_id(),
+            498_397_000, 240_297_000, band, 2, WaypointStatus::Closed
+        );
+
+        let mut trans = Transaction::create_transaction(vec![
+            Operation::create_waypoint_registration(&operator, &kyiv, 0, 0),
+            Operation::create_waypoint_registration(&operator, &lviv, 1, 0),
+        ]);
+        operator.sign_transaction(&mut trans, 0);
+        let block = bc.build_block(vec![trans], &get_genesis(&bc)).unwrap();
+        bc.validate_block(block).unwrap();
+
+        assert_eq!(bc.get_waypoint(kyiv.get_id()), Some(&kyiv));
+        let mut both = vec![&kyiv, &lviv];
+        both.sort_by_key(|waypoint| waypoint.get_id());
+        assert_eq!(bc.get_waypoints_by_operator(&operator.get_id()), both);
+        assert!(bc.get_waypoints_by_operator(&Account::gen_account().get_id()).is_empty());
+
+        let around_kyiv = BoundingBox::new(502_000_000, 302_000_000, 507_000_000, 309_000_000).unwrap();
+        assert_eq!(bc.get_waypoints_in(&around_kyiv), vec![&kyiv]);
+    }
+
+    //  Company treasury spending needs two of its three treasurers
+    #[test]
+    fn test_multisig_treasury() {
+        let mut bc = Blockchain::init();
+        let mut company = Account::gen_account_with(KeyAlgorithm::Ed25519);
+        bc.get_token_from_faucet(&mut company, Amount::new(5)).unwrap();
+        let treasurers: Vec<KeySig> = (0..3).map(|_| KeySig::generate(KeyAlgorithm::Ed25519)).collect();
+        let keys: Vec<String> = treasurers.iter().map(|key| hex::encode(key.get_public_key())).collect();
+        let policy = MultisigPolicy::new(2, keys.clone()).unwrap();
+
+        let setup = Operation::create_multisig_setup(&company, policy.clone(), 0, 0);
+        let mut trans = Transaction::create_transaction(vec![setup]);
+        company.sign_transaction(&mut trans, 0);
+        let block = bc.build_block(vec![trans], &get_genesis(&bc)).unwrap();
+        let mut tip = bc.validate_block(block).unwrap();
+        assert_eq!(bc.get_multisig_policy(&company.get_id()), Some(&policy));
+
+        //  The company key alone can't spend anymore
+        let single = get_signed_transfer(&bc, &company);
+        let block = bc.build_block(vec![single.clone()], &tip).unwrap();
+        assert_eq!(
+            bc.validate_block(block),
+            Err(BlockValidationError::BadSignature(single.get_id()))
+        );
+
+        let cosign = |mut op: Operation, signers: &[KeySig]| {
+            signers.iter().for_each(|treasurer| op.cosign(treasurer, 0));
+            let mut trans = Transaction::create_transaction(vec![op]);
+            signers.iter().for_each(|treasurer| trans.cosign(&company.get_id(), treasurer, 0));
+            trans
+        };
+        let payout = Operation::new(
+            company.get_id(), Account::gen_account().get_id(), Amount::new(2), 1
+        );
+        let trans = cosign(payout.clone(), &treasurers[..1]);
+        let block = bc.build_block(vec![trans.clone()], &tip).unwrap();
+        assert_eq!(
+            bc.validate_block(block),
+            Err(BlockValidationError::BadSignature(trans.get_id()))
+        );
+
+        let block = bc.build_block(vec![cosign(payout, &treasurers[1..])], &tip).unwrap();
+        tip = bc.validate_block(block).unwrap();
+        assert_eq!(bc.get_state().get_balance(&company.get_id()), Amount::new(3));
+
+        //  Raising the threshold is approved under the current policy
+        let stricter = MultisigPolicy::new(3, keys).unwrap();
+        let change = Operation::multisig_change(company.get_id(), policy, stricter.clone(), 2);
+        let block = bc.build_block(vec![cosign(change, &treasurers[..2])], &tip).unwrap();
+        bc.validate_block(block).unwrap();
+        assert_eq!(bc.get_multisig_policy(&company.get_id()), Some(&stricter));
+    }
+
+    //  Transponder keys sign alongside the RSA keys of the droneport
+    #[test]
+    fn test_validate_block_mixed_key_algorithms() {
+        let mut bc = Blockchain::init();
+        let genesis = get_genesis(&bc);
+        let mut drone = Account::gen_account_with(KeyAlgorithm::Ed25519);
+        let mut company = Account::gen_account_with(KeyAlgorithm::EcdsaP256);
+        let droneport = Account::gen_account();
+        bc.get_token_from_faucet(&mut drone, Amount::new(5)).unwrap();
+        bc.get_token_from_faucet(&mut company, Amount::new(5)).unwrap();
+
+        let mut trans = Transaction::create_transaction(vec![
+            Operation::create_operation(&droneport, &drone, Amount::new(1), 0, 0),
+            Operation::create_operation(&droneport, &company, Amount::new(2), 0, 0),
+        ]);
+        drone.sign_transaction(&mut trans, 0);
+        company.sign_transaction(&mut trans, 0);
+        let block = bc.build_block(vec![trans], &genesis).unwrap();
+
+        bc.validate_block(block).unwrap();
+        assert_eq!(bc.state.get_balance(&droneport.get_id()), Amount::new(3));
+    }
+
+    #[test]
+    fn test_validate_block_missing_signature() {
+        let mut bc = Blockchain::init();
+        let genesis = get_genesis(&bc);
+        let mut drone = Account::gen_account();
+        let mut company = Account::gen_account();
+        let waypoint = Account::gen_account();
+        bc.get_token_from_faucet(&mut drone, Amount::new(5)).unwrap();
+        bc.get_token_from_faucet(&mut company, Amount::new(5)).unwrap();
+
+        let mut trans = Transaction::create_transaction(vec![
+            Operation::create_operation(&waypoint, &drone, Amount::new(1), 0, 0),
+            Operation::create_operation(&waypoint, &company, Amount::new(2), 0, 0),
+        ]);
+        drone.sign_transaction(&mut trans, 0);
+        let block = bc.build_block(vec![trans.clone()], &genesis).unwrap();
+        assert_eq!(
+            bc.validate_block(block),
+            Err(BlockValidationError::MissingSignature {
+                transaction: trans.get_id(), signer: company.get_id()
+            })
+        );
+
+        company.sign_transaction(&mut trans, 0);
+        let block = bc.build_block(vec![trans], &genesis).unwrap();
+        bc.validate_block(block).unwrap();
+        assert_eq!(bc.state.get_balance(&waypoint.get_id()), Amount::new(3));
+    }
+
+    #[test]
+    fn test_nonce_advances() {
+        let mut bc = Blockchain::init();
+        let mut sender = Account::gen_account();
+        let receiver = Account::gen_account();
+        bc.get_token_from_faucet(&mut sender, Amount::new(5)).unwrap();
+        assert_eq!(bc.get_next_nonce(&sender.get_id()), 0);
+
+        for nonce in 0..2 {
+            let op = Operation::create_operation(&receiver, &sender, Amount::new(1), nonce, 0);
+            let mut trans = Transaction::create_transaction(vec![op]);
+            sender.sign_transaction(&mut trans, 0);
+            let block = bc.build_block(vec![trans], &bc.tip().get_id()).unwrap();
+            bc.validate_block(block).unwrap();
+        }
+
+        assert_eq!(bc.get_next_nonce(&sender.get_id()), 2);
+        assert_eq!(bc.tip().get_header().get_state_root(), bc.state_root());
+    }
+
+    //  Same transfer signed again with a used nonce
+    #[test]
+    fn test_reject_replayed_nonce() {
+        let mut bc = Blockchain::init();
+        let mut sender = Account::gen_account();
+        let receiver = Account::gen_account();
+        bc.get_token_from_faucet(&mut sender, Amount::new(5)).unwrap();
+
+        let first = Operation::create_operation(&receiver, &sender, Amount::new(1), 0, 0);
+        let second = Operation::create_operation(&receiver, &sender, Amount::new(2), 0, 0);
+        let mut first = Transaction::create_transaction(vec![first]);
+        sender.sign_transaction(&mut first, 0);
+        let block = bc.build_block(vec![first], &bc.tip().get_id()).unwrap();
+        bc.validate_block(block).unwrap();
+
+        let mut second = Transaction::create_transaction(vec![second]);
+        sender.sign_transaction(&mut second, 0);
+        let result = bc.build_block(vec![second], &bc.tip().get_id());
+        assert_eq!(result.err(), Some(BlockValidationError::BadNonce {
+            account: sender.get_id(), expected: 1, found: 0
+        }));
+    }
+
+    #[test]
+    fn test_reject_nonce_gap() {
+        let mut bc = Blockchain::init();
+        let mut sender = Account::gen_account();
+        bc.get_token_from_faucet(&mut sender, Amount::new(5)).unwrap();
+
+        let op = Operation::create_operation(&Account::gen_account(), &sender, Amount::new(1), 1, 0);
+        let result = bc.build_block(
+            vec![Transaction::create_transaction(vec![op])], &bc.tip().get_id()
+        );
+
+        assert_eq!(result.err(), Some(BlockValidationError::BadNonce {
+            account: sender.get_id(), expected: 0, found: 1
+        }));
+    }
+
+    #[test]
+    fn test_validate_block_insufficient_balance() {
+        let mut bc = Blockchain::init();
+        let trans = get_funded_transaction(&mut bc, 1, 2);
+
+        assert!(bc.build_block(vec![trans.clone()], &get_genesis(&bc)).is_err());
+
+        let grants = bc.pending_grants(&get_genesis(&bc));
+        let block = Block::create_block([grants, vec![trans]].concat(), bc.genesis());
+        assert!(matches!(
+            bc.validate_block(block),
+            Err(BlockValidationError::InsufficientBalance { balance, amount, .. })
+                if balance == Amount::new(1) && amount == Amount::new(2)
+        ));
+    }
+
+    #[test]
+    fn test_validate_block_state_root_mismatch() {
+        let mut bc = Blockchain::init();
+        let prev = get_genesis(&bc);
+
+        let mut block = get_block(&mut bc, &prev);
+        block.set_state_root(Hash32::default());
+
+        assert!(matches!(
+            bc.validate_block(block),
+            Err(BlockValidationError::StateRootMismatch { .. })
+        ));
+    }
+
+    #[test]
+    fn test_rejected_block_leaves_state_untouched() {
+        let mut bc = Blockchain::init();
+        let good = get_funded_transaction(&mut bc, 5, 1);
+        let bad = get_funded_transaction(&mut bc, 1, 2);
+        let coin_db = bc.state.get_balances().clone();
+
+        let grants = bc.pending_grants(&get_genesis(&bc));
+        let block = Block::create_block([grants, vec![good, bad]].concat(), bc.genesis());
+
+        assert!(bc.validate_block(block).is_err());
+        assert_eq!(bc.state.get_balances(), &coin_db);
+        assert_eq!(bc.height(), 0);
+    }
+
+    #[test]
+    fn test_same_transactions_on_different_parents() {
+        let mut bc = Blockchain::init();
+        let genesis = get_genesis(&bc);
+        let a = get_block(&mut bc, &genesis);
+        let a = bc.validate_block(a).unwrap();
+
+        let trans = get_funded_transaction(&mut bc, 5, 1);
+        let on_genesis = Block::create_block(vec![trans.clone()], bc.genesis());
+        let on_a = Block::create_block(vec![trans], bc.get_block(&a).unwrap());
+
+        assert_ne!(on_genesis.get_id(), on_a.get_id());
+    }
+
+    #[test]
+    fn test_blocks_by_height_and_tip() {
+        let mut bc = Blockchain::init();
+        let genesis = get_genesis(&bc);
+
+        let block = get_block(&mut bc, &genesis);
+        let first = bc.validate_block(block).unwrap();
+        let block = get_block(&mut bc, &first);
+        let second = bc.validate_block(block).unwrap();
+
+        assert_eq!(bc.height(), 2);
+        assert_eq!(bc.tip().get_id(), second);
+        assert_eq!(bc.get_block_by_height(1).unwrap().get_id(), first);
+        assert!(bc.get_block_by_height(3).is_none());
+
+        let forward: Vec<BlockHash> = bc.iter().map(|b| b.get_id()).collect();
+        assert_eq!(forward, [genesis, first, second]);
+        assert_eq!(bc.iter().rev().nth(1).unwrap().get_id(), first);
+        assert_eq!(bc.tip().get_previous(), first);
+    }
+
+    #[test]
+    fn test_prove_transaction() {
+        let mut bc = Blockchain::init();
+        let genesis = get_genesis(&bc);
+        let first = get_block(&mut bc, &genesis);
+        let first = bc.validate_block(first).unwrap();
+
+        let trans = get_funded_transaction(&mut bc, 5, 1);
+        let tx_id = trans.get_id();
+        let block = bc.build_block(vec![trans], &first).unwrap();
+        bc.validate_block(block).unwrap();
+
+        let (height, proof) = bc.prove_transaction(&tx_id).unwrap();
+        let root = bc.get_block_by_height(height).unwrap().get_header().get_merkle_root();
+
+        assert_eq!(height, 2);
+        assert!(verify_inclusion(&root, &proof));
+        assert!(bc.prove_transaction(&Hash32::default()).is_none());
+    }
+
+    #[test]
+    fn test_side_branch_stored_but_not_applied() {
+        let mut bc = Blockchain::init();
+        let genesis = get_genesis(&bc);
+        let a_trans = get_funded_transaction(&mut bc, 5, 1);
+        let b_trans = get_funded_transaction(&mut bc, 5, 1);
+        let side_trans = b_trans.get_id();
+
+        let a = bc.build_block(vec![a_trans], &genesis).unwrap();
+        let a = bc.validate_block(a).unwrap();
+        let b = bc.build_block(vec![b_trans], &genesis).unwrap();
+        let b = bc.validate_block(b).unwrap();
+
+        assert_eq!(bc.tip().get_id(), a);
+        assert!(bc.get_block(&b).is_some());
+        assert!(!bc.state.contains_transaction(&side_trans));
+    }
+
+    #[test]
+    fn test_reorg_to_longer_branch() {
+        let mut bc = Blockchain::init();
+        let genesis = get_genesis(&bc);
+        let a_trans = get_funded_transaction(&mut bc, 5, 1);
+        let b_trans = get_funded_transaction(&mut bc, 5, 1);
+        let c_trans = get_funded_transaction(&mut bc, 5, 1);
+        let sender = a_trans.get_operations()[0].get_sender();
+        let replaced = a_trans.get_id();
+
+        let a = bc.build_block(vec![a_trans], &genesis).unwrap();
+        bc.validate_block(a).unwrap();
+        assert_eq!(bc.state.get_balance(&sender), Amount::new(4));
+
+        let b = bc.build_block(vec![b_trans], &genesis).unwrap();
+        let b = bc.validate_block(b).unwrap();
+        let c = bc.build_block(vec![c_trans], &b).unwrap();
+        let c = bc.validate_block(c).unwrap();
+
+        assert_eq!(bc.tip().get_id(), c);
+        assert_eq!(bc.get_block_by_height(1).unwrap().get_id(), b);
+        assert_eq!(bc.state.get_balance(&sender), Amount::new(5));
+        assert_eq!(bc.get_next_nonce(&sender), 0);
+        assert!(!bc.state.contains_transaction(&replaced));
+        assert_eq!(bc.tip().get_header().get_state_root(), bc.state_root());
+    }
+
+    #[test]
+    fn test_failed_reorg_keeps_current_branch() {
+        let mut bc = Blockchain::init();
+        let genesis = get_genesis(&bc);
+        let overspend = get_funded_transaction(&mut bc, 1, 2);
+        let c_trans = get_funded_transaction(&mut bc, 5, 1);
+
+        let a = get_block(&mut bc, &genesis);
+        let a = bc.validate_block(a).unwrap();
+        let coin_db = bc.state.get_balances().clone();
+
+        let grants = bc.pending_grants(&genesis);
+        let b = Block::create_block([grants, vec![overspend]].concat(), bc.genesis());
+        let b = bc.validate_block(b).unwrap();
+        let c = Block::create_block(vec![c_trans], bc.get_block(&b).unwrap());
+
+        assert!(matches!(
+            bc.validate_block(c.clone()),
+            Err(BlockValidationError::InsufficientBalance { .. })
+        ));
+        assert_eq!(bc.tip().get_id(), a);
+        assert!(bc.get_block(&b).is_none());
+        assert!(bc.get_block(&c.get_id()).is_none());
+        assert_eq!(bc.state.get_balances(), &coin_db);
+    }
+
+    //  A longer branch loses if its extra block comes from an authority without weight
+    #[test]
+    fn test_authority_weighted_fork_choice() {
+        let unweighted = Account::gen_account();
+        let weighted = Account::gen_account();
+        let mut weights = HashMap::new();
+        weights.insert(weighted.get_id(), 5);
+        //  Height 1 belongs to `weighted`, height 2 to `unweighted`
+        let authorities = vec![unweighted.get_id(), weighted.get_id()];
+        let mut bc = Blockchain::from_genesis(
+            GenesisConfig::default()
+                .with_consensus(Consensus::Authority(ProofOfAuthority::new(authorities)))
+                .with_fork_choice(ForkChoice::AuthorityWeighted(weights))
+        ).unwrap();
+        let genesis = get_genesis(&bc);
+        let transactions: Vec<Transaction> = (0..3).map(|_| get_funded_transaction(&mut bc, 5, 1)).collect();
+
+        let mut block = bc.build_block(vec![transactions[0].clone()], &genesis).unwrap();
+        block.seal(&weighted);
+        let first = bc.validate_block(block).unwrap();
+
+        let mut block = bc.build_block(vec![transactions[1].clone()], &genesis).unwrap();
+        block.seal(&weighted);
+        let side = bc.validate_block(block).unwrap();
+        let mut block = bc.build_block(vec![transactions[2].clone()], &side).unwrap();
+        block.seal(&unweighted);
+        bc.validate_block(block).unwrap();
+
+        assert_eq!(bc.tip().get_id(), first);
+        assert_eq!(bc.height(), 1);
+    }
+
+    #[test]
+    fn test_fork_choice_needs_matching_consensus() {
+        let weighted = GenesisConfig::default()
+            .with_fork_choice(ForkChoice::AuthorityWeighted(HashMap::new()));
+        assert_eq!(Blockchain::from_genesis(weighted).err(), Some(GenesisError::WeightsNeedAuthority));
+
+        let mined = GenesisConfig::default().with_fork_choice(ForkChoice::MostWork);
+        assert_eq!(Blockchain::from_genesis(mined).err(), Some(GenesisError::WorkNeedsMining));
+    }
+
+    fn get_authority_chain(authorities: &[&Account]) -> Blockchain {
+        let ids = authorities.iter().map(|a| a.get_id()).collect();
+        Blockchain::from_genesis(
+            GenesisConfig::default()
+                .with_consensus(Consensus::Authority(ProofOfAuthority::new(ids)))
+        ).unwrap()
+    }
+
+    #[test]
+    fn test_authorities_seal_in_turn() {
+        let first = Account::gen_account();
+        let second = Account::gen_account();
+        let mut bc = get_authority_chain(&[&first, &second]);
+        let genesis = get_genesis(&bc);
+
+        //  Height 1 belongs to the second authority
+        let mut block = get_block(&mut bc, &genesis);
+        block.seal(&second);
+        let id = bc.validate_block(block).unwrap();
+
+        let mut block = get_block(&mut bc, &id);
+        block.seal(&first);
+        assert!(bc.validate_block(block).is_ok());
+        assert_eq!(bc.height(), 2);
+    }
+
+    #[test]
+    fn test_reject_non_authority_sealer() {
+        let authority = Account::gen_account();
+        let intruder = Account::gen_account();
+        let mut bc = get_authority_chain(&[&authority]);
+        let genesis = get_genesis(&bc);
+
+        let unsealed = get_block(&mut bc, &genesis);
+        let mut block = unsealed.clone();
+        block.seal(&intruder);
+        assert_eq!(
+            bc.validate_block(block),
+            Err(BlockValidationError::UnknownSealer(intruder.get_id()))
+        );
+
+        assert_eq!(
+            bc.validate_block(unsealed),
+            Err(BlockValidationError::UnknownSealer(String::new()))
         );
+    }
+
+    #[test]
+    fn test_reject_out_of_turn_sealer() {
+        let first = Account::gen_account();
+        let second = Account::gen_account();
+        let mut bc = get_authority_chain(&[&first, &second]);
+        let genesis = get_genesis(&bc);
+
+        let mut block = get_block(&mut bc, &genesis);
+        block.seal(&first);
 
-        assert!(
-            bc.validate_block(block)
+        assert_eq!(
+            bc.validate_block(block),
+            Err(BlockValidationError::OutOfTurn {
+                sealer: first.get_id(),
+                expected: second.get_id()
+            })
         );
     }
 
+    //  Block on `parent` applying `operation`, sealed by `sealer`
+    fn get_sealed_block(
+        bc: &mut Blockchain, sealer: &Account, operation: Operation, parent: &BlockHash
+    ) -> BlockHash {
+        let mut trans = Transaction::create_transaction(vec![operation]);
+        sealer.sign_transaction(&mut trans, 0);
+        let mut block = bc.build_block(vec![trans], parent).unwrap();
+        block.seal(sealer);
+        bc.validate_block(block).unwrap()
+    }
+
+    #[test]
+    fn test_authority_seals_with_rotated_key() {
+        let mut authority = Account::gen_account_with(KeyAlgorithm::Ed25519);
+        let mut bc = get_authority_chain(&[&authority]);
+        let genesis = get_genesis(&bc);
+        bc.get_token_from_faucet(&mut authority, Amount::new(5)).unwrap();
+
+        let new_key = KeySig::generate(KeyAlgorithm::Ed25519);
+        let rotation = Operation::create_key_rotation(&authority, &new_key, 0, 0);
+        let rotated = get_sealed_block(&mut bc, &authority, rotation, &genesis);
+
+        //  The key the address commits to was replaced
+        let mut block = bc.build_block(vec![], &rotated).unwrap();
+        block.seal(&authority);
+        let id = block.get_id();
+        assert_eq!(bc.validate_block(block), Err(BlockValidationError::BadSeal(id)));
+
+        authority.add_key_pair_to_wallet(new_key);
+        authority.set_signing_key(1);
+        let mut block = bc.build_block(vec![], &rotated).unwrap();
+        block.seal(&authority);
+        assert!(bc.validate_block(block).is_ok());
+    }
+
+    #[test]
+    fn test_reject_revoked_sealer() {
+        let mut authority = Account::gen_account_with(KeyAlgorithm::Ed25519);
+        let mut bc = get_authority_chain(&[&authority]);
+        let genesis = get_genesis(&bc);
+        bc.get_token_from_faucet(&mut authority, Amount::new(5)).unwrap();
+
+        let revocation = Operation::create_key_revocation(&authority, 0, 0);
+        let revoked = get_sealed_block(&mut bc, &authority, revocation, &genesis);
+
+        let mut block = bc.build_block(vec![], &revoked).unwrap();
+        block.seal(&authority);
+        assert_eq!(
+            bc.validate_block(block),
+            Err(BlockValidationError::KeyRevoked(authority.get_id()))
+        );
+    }
+
+    fn get_work_chain(difficulty: u32, interval: u64) -> Blockchain {
+        Blockchain::from_genesis(
+            GenesisConfig::default()
+                .with_consensus(Consensus::Work(ProofOfWork::new(difficulty, interval, 60)))
+                .with_fork_choice(ForkChoice::MostWork)
+        ).unwrap()
+    }
+
+    #[test]
+    fn test_mined_block_accepted() {
+        let mut bc = get_work_chain(8, 0);
+        let genesis = get_genesis(&bc);
+
+        let mut block = get_block(&mut bc, &genesis);
+        block.mine(bc.next_difficulty(&genesis));
+
+        assert!(meets_difficulty(&block.get_id(), 8));
+        assert!(bc.validate_block(block).is_ok());
+    }
+
+    #[test]
+    fn test_reject_wrong_difficulty() {
+        let mut bc = get_work_chain(8, 0);
+        let genesis = get_genesis(&bc);
+
+        let mut block = get_block(&mut bc, &genesis);
+        block.mine(2);
+
+        assert_eq!(
+            bc.validate_block(block),
+            Err(BlockValidationError::WrongDifficulty { expected: 8, found: 2 })
+        );
+    }
+
+    #[test]
+    fn test_reject_insufficient_work() {
+        let mut bc = get_work_chain(8, 0);
+        let genesis = get_genesis(&bc);
+
+        let mut block = get_block(&mut bc, &genesis);
+        block.mine(8);
+        while meets_difficulty(&block.get_id(), 8) {
+            block.set_timestamp(block.get_timestamp() + 1);
+        }
+        let id = block.get_id();
+
+        assert_eq!(
+            bc.validate_block(block),
+            Err(BlockValidationError::InsufficientWork(id))
+        );
+    }
+
+    #[test]
+    fn test_difficulty_retargets() {
+        let mut bc = get_work_chain(2, 2);
+        let genesis = get_genesis(&bc);
+        let start = bc.genesis().get_timestamp();
+
+        //  Interval closed instantly, difficulty goes up
+        let mut fast = get_block(&mut bc, &genesis);
+        fast.mine(2);
+        let fast = bc.validate_block(fast).unwrap();
+        assert_eq!(bc.next_difficulty(&fast), 3);
+
+        //  Interval took far longer than 2 * 60 seconds, difficulty goes down
+        let mut slow = get_block(&mut bc, &genesis);
+        slow.set_timestamp(start + 1000);
+        slow.mine(2);
+        let slow = bc.validate_block(slow).unwrap();
+        assert_eq!(bc.next_difficulty(&slow), 1);
+    }
+
+    #[test]
+    fn test_blake3_chain() {
+        let config = GenesisConfig::default().with_hash_algorithm(HashAlgorithm::Blake3);
+        let mut bc = Blockchain::from_genesis(config).unwrap();
+        let genesis = get_genesis(&bc);
+        assert_eq!(bc.genesis().get_hash_algorithm(), HashAlgorithm::Blake3);
+
+        let mut sender = Account::gen_account();
+        bc.get_token_from_faucet(&mut sender, Amount::new(5)).unwrap();
+        let op = Operation::create_operation(&Account::gen_account(), &sender, Amount::new(1), 0, 0);
+        let mut trans = Transaction::create_transaction_with(vec![op], HashAlgorithm::Blake3);
+        sender.sign_transaction(&mut trans, 0);
+
+        let block = bc.build_block(vec![trans], &genesis).unwrap();
+        assert_eq!(block.get_header().hash(HashAlgorithm::Blake3), block.get_id());
+        assert!(bc.validate_block(block).is_ok());
+    }
+
+    #[test]
+    fn test_reject_transaction_hashed_with_other_algorithm() {
+        let config = GenesisConfig::default().with_hash_algorithm(HashAlgorithm::Blake3);
+        let mut bc = Blockchain::from_genesis(config).unwrap();
+        let genesis = get_genesis(&bc);
+
+        let trans = get_funded_transaction(&mut bc, 5, 1);
+        let id = trans.get_id();
+        let block = bc.build_block(vec![trans], &genesis).unwrap();
+
+        assert_eq!(bc.validate_block(block), Err(BlockValidationError::BadTransactionId(id)));
+    }
+
+    //  Nothing the chain stores or prints may carry a private key
+    #[test]
+    fn test_no_private_keys_in_chain() {
+        let mut bc = Blockchain::init();
+        let genesis = get_genesis(&bc);
+        let mut sender = Account::gen_account();
+        let receiver = Account::gen_account();
+        bc.get_token_from_faucet(&mut sender, Amount::new(5)).unwrap();
+        let op = Operation::create_operation(&receiver, &sender, Amount::new(1), 0, 0);
+        let mut trans = Transaction::create_transaction(vec![op]);
+        sender.sign_transaction(&mut trans, 0);
+        let block = bc.build_block(vec![trans], &genesis).unwrap();
+        bc.validate_block(block).unwrap();
+
+        let history: Vec<&Block> = bc.iter().collect();
+        let artifacts = [
+            serde_json::to_string(&history).unwrap(),
+            serde_json::to_string(&bc.state).unwrap(),
+            serde_json::to_string(&sender).unwrap(),
+        ];
+
+        //  First line of base64 key material of each private key
+        let secrets: Vec<String> = [&sender, &receiver].iter()
+            .map(|a| a.get_keysig(0).to_string().lines().nth(1).unwrap().to_string())
+            .collect();
+        for artifact in &artifacts {
+            for needle in ["PRIVATE KEY", secrets[0].as_str(), secrets[1].as_str()] {
+                assert!(!artifact.contains(needle));
+                assert!(!artifact.contains(&hex::encode(needle)));
+            }
+        }
+    }
+
     #[test]
     fn test_get_token_from_faucet() {
         let mut bc = Blockchain::init();
         let mut account = Account::gen_account();
 
-        bc.get_token_from_faucet(&mut account, 5);
+        bc.get_token_from_faucet(&mut account, Amount::new(5)).unwrap();
+        assert_eq!(bc.state.get_balance(&account.get_id()), Amount::ZERO);
+
+        let block = bc.build_block(vec![], &get_genesis(&bc)).unwrap();
+        bc.validate_block(block).unwrap();
 
-        let new_coins = bc.coin_db.get(
+        let new_coins = bc.state.get_balances().get(
             account.get_id().as_str()).unwrap();
-        assert_eq!(new_coins, account.get_balance().borrow())
+        assert_eq!(new_coins, account.get_balance().borrow());
+        assert!(bc.pending_grants(&bc.tip().get_id()).is_empty());
     }
-}
\ No newline at end of file
+
+    //  A grant made after a side branch was built lands on it once it's extended
+    #[test]
+    fn test_faucet_grant_follows_reorg() {
+        let mut bc = Blockchain::init();
+        let genesis = get_genesis(&bc);
+
+        let a = get_block(&mut bc, &genesis);
+        let a = bc.validate_block(a).unwrap();
+        let b = bc.build_block(vec![], &genesis).unwrap();
+        let b = bc.validate_block(b).unwrap();
+        assert_eq!(bc.tip().get_id(), a);
+
+        let mut account = Account::gen_account();
+        bc.get_token_from_faucet(&mut account, Amount::new(7)).unwrap();
+        assert_eq!(bc.pending_grants(&a).len(), 1);
+        assert_eq!(bc.pending_grants(&b).len(), 1);
+
+        let c = bc.build_block(vec![], &b).unwrap();
+        let c = bc.validate_block(c).unwrap();
+        assert_eq!(bc.tip().get_id(), c);
+        assert_eq!(bc.state.get_balance(&account.get_id()), Amount::new(7));
+        assert_eq!(bc.tip().get_header().get_state_root(), bc.state_root());
+    }
+
+    #[test]
+    fn test_faucet_exhausted() {
+        let mut bc = Blockchain::init();
+        let mut account = Account::gen_account();
+
+        assert_eq!(
+            bc.get_token_from_faucet(&mut account, Amount::new(101)),
+            Err(FaucetError::Exhausted {
+                available: Amount::new(100), requested: Amount::new(101)
+            })
+        );
+        assert_eq!(account.get_balance(), Amount::ZERO);
+        assert!(!bc.state.get_balances().contains_key(&account.get_id()));
+
+        bc.get_token_from_faucet(&mut account, Amount::new(100)).unwrap();
+        assert!(bc.get_token_from_faucet(&mut account, Amount::new(1)).is_err());
+    }
+}
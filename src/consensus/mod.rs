@@ -0,0 +1,259 @@
+//  Rules deciding who may produce blocks
+
+use std::collections::BTreeMap;
+
+use crate::blockchain::{BlockHash, BlockHeader, BlockValidationError};
+use crate::hash::Hash32;
+use crate::multisig::SigningAuthority;
+
+/**
+    How blocks are admitted to the chain.
+    Chosen once at genesis.
+ */
+#[derive(Clone, Debug, Default)]
+pub enum Consensus {
+    //  Anyone may append a block
+    #[default]
+    Open,
+    //  Only registered waypoints/droneports may seal blocks
+    Authority(ProofOfAuthority),
+    //  Anyone may append a block whose id meets the difficulty target
+    Work(ProofOfWork),
+}
+
+/**
+    A fixed set of waypoint and droneport authorities,
+    identified by their account ids, that take turns
+    sealing blocks in the order they were registered.
+ */
+#[derive(Clone, Debug)]
+pub struct ProofOfAuthority {
+    authorities: Vec<String>,
+}
+
+/**
+    Mining parameters for open test networks.
+    Difficulty is the number of leading zero bits a block id needs.
+ */
+#[derive(Clone, Debug)]
+pub struct ProofOfWork {
+    initial_difficulty: u32,
+    //  Blocks between difficulty adjustments, 0 never adjusts
+    retarget_interval: u64,
+    //  Desired seconds between blocks
+    target_block_time: u64,
+}
+
+impl Consensus {
+    /**
+        Checks who produced a block from its header, id, seal and
+        the key that made the seal, `difficulty` being the chain's
+        current proof of work target. `authority` looks up what an
+        account's signatures are checked against in the parent state.
+     */
+    pub fn verify_header<F>(
+        &self,
+        header: &BlockHeader,
+        id: &BlockHash,
+        seal: &[u8],
+        sealer_key: &[u8],
+        difficulty: u32,
+        authority: F
+    ) -> Result<(), BlockValidationError>
+    where F: Fn(&str) -> Option<SigningAuthority> {
+        match self {
+            Consensus::Open => Ok(()),
+            Consensus::Authority(poa) =>
+                poa.verify_seal(header, id, seal, sealer_key, authority(&header.get_producer())),
+            Consensus::Work(pow) => pow.verify_work(header, id, difficulty),
+        }
+    }
+}
+
+impl ProofOfAuthority {
+    pub fn new(authorities: Vec<String>) -> Self {
+        ProofOfAuthority { authorities }
+    }
+
+    pub fn get_authorities(&self) -> &[String] {
+        &self.authorities
+    }
+
+    pub fn is_authority(&self, id: &str) -> bool {
+        self.authorities.iter().any(|a| a == id)
+    }
+
+    //  Authority whose turn it is to seal the block at `height`
+    pub fn sealer_at(&self, height: u64) -> Option<&String> {
+        if self.authorities.is_empty() {
+            return None;
+        }
+        self.authorities.get((height % self.authorities.len() as u64) as usize)
+    }
+
+    /**
+     Checks:
+        1. producer is a registered authority
+        2. it is the producer's turn at the header's height
+        3. producer's signing key hasn't been revoked
+        4. seal is a signature over the block id by `sealer_key`,
+           the producer's signing key in `authority`, its on-chain
+           authority as of the parent block
+     */
+    pub fn verify_seal(
+        &self,
+        header: &BlockHeader,
+        id: &BlockHash,
+        seal: &[u8],
+        sealer_key: &[u8],
+        authority: Option<SigningAuthority>
+    ) -> Result<(), BlockValidationError> {
+        let producer = header.get_producer();
+
+        //  1
+        if !self.is_authority(&producer) {
+            return Err(BlockValidationError::UnknownSealer(producer));
+        }
+
+        //  2
+        let expected = self.sealer_at(header.get_height()).unwrap();
+        if *expected != producer {
+            return Err(BlockValidationError::OutOfTurn {
+                sealer: producer,
+                expected: expected.clone(),
+            });
+        }
+
+        //  3
+        let authority = authority.ok_or(BlockValidationError::KeyRevoked(producer))?;
+
+        //  4, a multisig authority has no single key to seal with
+        let verified = authority.verify(
+            id.as_bytes(), &hex::encode(sealer_key), seal, &BTreeMap::new()
+        );
+        if !verified {
+            return Err(BlockValidationError::BadSeal(*id));
+        }
+
+        Ok(())
+    }
+}
+
+impl ProofOfWork {
+    pub fn new(initial_difficulty: u32, retarget_interval: u64, target_block_time: u64) -> Self {
+        ProofOfWork {
+            initial_difficulty,
+            retarget_interval,
+            target_block_time,
+        }
+    }
+
+    pub fn get_initial_difficulty(&self) -> u32 {
+        self.initial_difficulty
+    }
+
+    pub fn get_retarget_interval(&self) -> u64 {
+        self.retarget_interval
+    }
+
+    pub fn get_target_block_time(&self) -> u64 {
+        self.target_block_time
+    }
+
+    /**
+        Adjusts `difficulty` after an interval that took `elapsed`
+        seconds. Each bit doubles the work, so it only moves by one
+        when blocks came more than twice as fast or slow as targeted.
+        `elapsed` comes from miner set timestamps, so nothing here
+        may overflow.
+     */
+    pub fn retarget(&self, difficulty: u32, elapsed: u64) -> u32 {
+        let expected = self.retarget_interval.saturating_mul(self.target_block_time);
+
+        if elapsed.saturating_mul(2) < expected {
+            difficulty.saturating_add(1)
+        } else if elapsed > expected.saturating_mul(2) {
+            difficulty.saturating_sub(1)
+        } else {
+            difficulty
+        }
+    }
+
+    /**
+     Checks:
+        1. block was mined at the required difficulty
+        2. block id meets that difficulty
+     */
+    pub fn verify_work(
+        &self, header: &BlockHeader, id: &BlockHash, difficulty: u32
+    ) -> Result<(), BlockValidationError> {
+        //  1
+        if header.get_difficulty() != difficulty {
+            return Err(BlockValidationError::WrongDifficulty {
+                expected: difficulty,
+                found: header.get_difficulty(),
+            });
+        }
+
+        //  2
+        if !meets_difficulty(id, difficulty) {
+            return Err(BlockValidationError::InsufficientWork(*id));
+        }
+
+        Ok(())
+    }
+}
+
+//  Whether `id` starts with at least `difficulty` zero bits
+pub fn meets_difficulty(id: &Hash32, difficulty: u32) -> bool {
+    id.leading_zero_bits() >= difficulty
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::hash::Hash32;
+    use super::{meets_difficulty, ProofOfAuthority, ProofOfWork};
+
+    #[test]
+    fn test_meets_difficulty() {
+        let mut bytes = [0xffu8; 32];
+        bytes[0] = 0x0f;
+        let id = Hash32::new(bytes);
+
+        assert!(meets_difficulty(&id, 4));
+        assert!(!meets_difficulty(&id, 5));
+        assert!(meets_difficulty(&id, 0));
+    }
+
+    #[test]
+    fn test_retarget() {
+        //  10 blocks every 60 seconds, 600 seconds per interval
+        let pow = ProofOfWork::new(4, 10, 60);
+
+        assert_eq!(pow.retarget(4, 100), 5);
+        assert_eq!(pow.retarget(4, 600), 4);
+        assert_eq!(pow.retarget(4, 1300), 3);
+        assert_eq!(pow.retarget(0, 1300), 0);
+
+        //  Timestamps near the end of time or huge parameters don't overflow
+        assert_eq!(pow.retarget(4, u64::MAX), 3);
+        assert_eq!(pow.retarget(u32::MAX, 0), u32::MAX);
+        assert_eq!(ProofOfWork::new(4, u64::MAX, u64::MAX).retarget(4, u64::MAX), 4);
+    }
+
+    #[test]
+    fn test_sealer_at_rotates() {
+        let poa = ProofOfAuthority::new(vec!["a".to_string(), "b".to_string()]);
+
+        assert_eq!(poa.sealer_at(1).unwrap(), "b");
+        assert_eq!(poa.sealer_at(2).unwrap(), "a");
+        assert!(poa.is_authority("a"));
+        assert!(!poa.is_authority("c"));
+    }
+
+    #[test]
+    fn test_no_authorities() {
+        let poa = ProofOfAuthority::new(vec![]);
+        assert!(poa.sealer_at(1).is_none());
+    }
+}
@@ -0,0 +1,164 @@
+//  Registry of drones, their transponders and owning companies
+
+use std::error::Error;
+use std::fmt;
+use serde::Serialize;
+
+use crate::address::Address;
+use crate::encoding::Encoder;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+pub enum DroneStatus {
+    Active,
+    //  Retired for good, its flights are no longer accepted
+    Decommissioned,
+}
+
+/**
+    Drone known to the chain. Its id is the address of its
+    transponder key, so only the transponder signs for it and
+    another drone can't claim its identity.
+ */
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct Drone {
+    id: String,
+    //  Manufacturer serial number, unique across the registry
+    serial: String,
+    //  Hex encoded tagged public key of the transponder
+    transponder: String,
+    //  Company account the drone belongs to
+    owner: String,
+    status: DroneStatus,
+}
+
+//  Why a drone operation or flight was refused
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DroneError {
+    //  Only the owner registers, transfers or decommissions a drone
+    NotTheOwner(String),
+    //  Transponder key the drone's id doesn't commit to
+    InvalidTransponder(String),
+    EmptySerial,
+    AlreadyRegistered(String),
+    //  Another drone has the serial number
+    SerialTaken(String),
+    //  Drone handed over to something that isn't an address
+    InvalidOwner(String),
+    Unregistered,
+    Decommissioned,
+}
+
+impl fmt::Display for DroneError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            DroneError::NotTheOwner(sender) => write!(f, "sent by {}, not the owner", sender),
+            DroneError::InvalidTransponder(key) =>
+                write!(f, "transponder key {} doesn't match the drone id", key),
+            DroneError::EmptySerial => write!(f, "serial number is empty"),
+            DroneError::AlreadyRegistered(id) => write!(f, "drone {} is already registered", id),
+            DroneError::SerialTaken(serial) =>
+                write!(f, "serial number {} belongs to another drone", serial),
+            DroneError::InvalidOwner(owner) => write!(f, "new owner {} is not a valid address", owner),
+            DroneError::Unregistered => write!(f, "drone is not registered"),
+            DroneError::Decommissioned => write!(f, "drone is decommissioned"),
+        }
+    }
+}
+
+impl Error for DroneError {}
+
+impl DroneStatus {
+    pub fn get_tag(&self) -> u8 {
+        match self {
+            DroneStatus::Active => 0,
+            DroneStatus::Decommissioned => 1,
+        }
+    }
+}
+
+impl Drone {
+    //  Newly registered, active drone
+    pub fn new(id: String, serial: String, transponder: String, owner: String) -> Self {
+        Drone { id, serial, transponder, owner, status: DroneStatus::Active }
+    }
+
+    pub fn get_id(&self) -> &str {
+        &self.id
+    }
+
+    pub fn get_serial(&self) -> &str {
+        &self.serial
+    }
+
+    pub fn get_transponder(&self) -> &str {
+        &self.transponder
+    }
+
+    pub fn get_owner(&self) -> &str {
+        &self.owner
+    }
+
+    pub fn get_status(&self) -> DroneStatus {
+        self.status
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.status == DroneStatus::Active
+    }
+
+    pub fn set_owner(&mut self, owner: String) {
+        self.owner = owner;
+    }
+
+    pub fn set_status(&mut self, status: DroneStatus) {
+        self.status = status;
+    }
+
+    //  Rules that hold for a drone on its own
+    pub fn check(&self) -> Result<(), DroneError> {
+        if self.serial.is_empty() {
+            return Err(DroneError::EmptySerial);
+        }
+        if !Address::is_address_of(&self.id, &self.transponder) {
+            return Err(DroneError::InvalidTransponder(self.transponder.clone()));
+        }
+        Ok(())
+    }
+
+    //  Part of the state root
+    pub fn encode_fields(&self, encoder: &mut Encoder) {
+        encoder
+            .put_str(&self.id)
+            .put_str(&self.serial)
+            .put_str(&self.transponder)
+            .put_str(&self.owner)
+            .put_u8(self.status.get_tag());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::account::Account;
+    use crate::keysig::{KeyAlgorithm, KeySig};
+    use super::{Drone, DroneError};
+
+    #[test]
+    fn test_check_drone() {
+        let transponder = KeySig::generate(KeyAlgorithm::Ed25519);
+        let id = Account::address_of(&transponder);
+        let key = hex::encode(transponder.get_public_key());
+        let owner = Account::gen_account().get_id();
+
+        let drone = Drone::new(id.clone(), "DJI-0001".to_string(), key.clone(), owner.clone());
+        assert_eq!(drone.check(), Ok(()));
+        assert!(drone.is_active());
+
+        let unnamed = Drone::new(id.clone(), String::new(), key.clone(), owner.clone());
+        assert_eq!(unnamed.check(), Err(DroneError::EmptySerial));
+
+        //  Spoofing another drone's id with a different transponder
+        let other = hex::encode(KeySig::generate(KeyAlgorithm::Ed25519).get_public_key());
+        let spoofed = Drone::new(id, "DJI-0001".to_string(), other.clone(), owner);
+        assert_eq!(spoofed.check(), Err(DroneError::InvalidTransponder(other)));
+    }
+}
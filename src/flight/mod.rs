@@ -0,0 +1,364 @@
+//  Journeys and position reports recorded for each drone
+
+use std::error::Error;
+use std::fmt;
+use serde::Serialize;
+
+use crate::address::Address;
+use crate::encoding::Encoder;
+
+//  Latitudes and longitudes are in units of 1e-7 degrees
+pub const MAX_LATITUDE: i32 = 900_000_000;
+pub const MAX_LONGITUDE: i32 = 1_800_000_000;
+
+//  Headings are in hundredths of a degree clockwise from north
+pub const FULL_CIRCLE: u16 = 36_000;
+
+/**
+    Completed journey between two waypoints. Times are unix
+    seconds, like block timestamps.
+ */
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct Leg {
+    from_waypoint: String,
+    to_waypoint: String,
+    departed_at: u64,
+    arrived_at: u64,
+}
+
+/**
+    Where a drone was at `timestamp`. Altitude is in millimetres
+    above sea level and speed in centimetres per second, so
+    reports encode the same on every platform.
+ */
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct Position {
+    lat: i32,
+    lon: i32,
+    alt: i32,
+    heading: u16,
+    speed: u32,
+    timestamp: u64,
+}
+
+/**
+    Everything the chain recorded about one drone's flights,
+    oldest first. Each entry must follow the previous ones, so
+    reverting a block only takes entries off the end.
+ */
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize)]
+pub struct FlightLog {
+    legs: Vec<Leg>,
+    positions: Vec<Position>,
+}
+
+//  Why a journey leg or position report was refused
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum FlightError {
+    //  Only the drone itself reports its flights
+    NotTheDrone(String),
+    InvalidWaypoint(String),
+    //  Waypoint isn't in the registry
+    UnknownWaypoint(String),
+    //  Leg ends at a waypoint that no longer takes drones
+    ClosedWaypoint(String),
+    SameWaypoint,
+    ArrivedBeforeDeparture,
+    //  Leg doesn't start where the previous one ended
+    WrongOrigin { expected: String, found: String },
+    //  Leg departs before the previous one arrived, or report isn't newer than the last
+    OutOfOrder,
+    //  Time later than the block recording it
+    InFuture { timestamp: u64, block_timestamp: u64 },
+    OutOfRange,
+}
+
+impl fmt::Display for FlightError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FlightError::NotTheDrone(sender) => write!(f, "reported by {}, not the drone", sender),
+            FlightError::InvalidWaypoint(waypoint) =>
+                write!(f, "waypoint {} is not a valid address", waypoint),
+            FlightError::UnknownWaypoint(waypoint) =>
+                write!(f, "waypoint {} is not registered", waypoint),
+            FlightError::ClosedWaypoint(waypoint) => write!(f, "waypoint {} is closed", waypoint),
+            FlightError::SameWaypoint => write!(f, "leg starts and ends at the same waypoint"),
+            FlightError::ArrivedBeforeDeparture => write!(f, "leg arrives before it departs"),
+            FlightError::WrongOrigin { expected, found } =>
+                write!(f, "leg departs from {} but the drone is at {}", found, expected),
+            FlightError::OutOfOrder => write!(f, "flight is older than the last one recorded"),
+            FlightError::InFuture { timestamp, block_timestamp } =>
+                write!(f, "time {} is after the block time {}", timestamp, block_timestamp),
+            FlightError::OutOfRange =>
+                write!(f, "position or heading is out of range"),
+        }
+    }
+}
+
+impl Error for FlightError {}
+
+impl Leg {
+    pub fn new(from_waypoint: String, to_waypoint: String, departed_at: u64, arrived_at: u64) -> Self {
+        Leg { from_waypoint, to_waypoint, departed_at, arrived_at }
+    }
+
+    pub fn get_from_waypoint(&self) -> &str {
+        &self.from_waypoint
+    }
+
+    pub fn get_to_waypoint(&self) -> &str {
+        &self.to_waypoint
+    }
+
+    pub fn get_departed_at(&self) -> u64 {
+        self.departed_at
+    }
+
+    pub fn get_arrived_at(&self) -> u64 {
+        self.arrived_at
+    }
+
+    //  Rules that hold for a leg on its own
+    pub fn check(&self) -> Result<(), FlightError> {
+        for waypoint in [&self.from_waypoint, &self.to_waypoint] {
+            if !Address::is_valid(waypoint) {
+                return Err(FlightError::InvalidWaypoint(waypoint.clone()));
+            }
+        }
+        if self.from_waypoint == self.to_waypoint {
+            return Err(FlightError::SameWaypoint);
+        }
+        if self.arrived_at < self.departed_at {
+            return Err(FlightError::ArrivedBeforeDeparture);
+        }
+        Ok(())
+    }
+
+    pub fn encode_fields(&self, encoder: &mut Encoder) {
+        encoder
+            .put_str(&self.from_waypoint)
+            .put_str(&self.to_waypoint)
+            .put_u64(self.departed_at)
+            .put_u64(self.arrived_at);
+    }
+}
+
+impl Position {
+    pub fn new(lat: i32, lon: i32, alt: i32, heading: u16, speed: u32, timestamp: u64) -> Self {
+        Position { lat, lon, alt, heading, speed, timestamp }
+    }
+
+    pub fn get_lat(&self) -> i32 {
+        self.lat
+    }
+
+    pub fn get_lon(&self) -> i32 {
+        self.lon
+    }
+
+    pub fn get_alt(&self) -> i32 {
+        self.alt
+    }
+
+    pub fn get_heading(&self) -> u16 {
+        self.heading
+    }
+
+    pub fn get_speed(&self) -> u32 {
+        self.speed
+    }
+
+    pub fn get_timestamp(&self) -> u64 {
+        self.timestamp
+    }
+
+    //  Rules that hold for a report on its own
+    pub fn check(&self) -> Result<(), FlightError> {
+        if self.lat.unsigned_abs() > MAX_LATITUDE as u32
+            || self.lon.unsigned_abs() > MAX_LONGITUDE as u32
+            || self.heading >= FULL_CIRCLE {
+            return Err(FlightError::OutOfRange);
+        }
+        Ok(())
+    }
+
+    pub fn encode_fields(&self, encoder: &mut Encoder) {
+        encoder
+            .put_u32(self.lat as u32)
+            .put_u32(self.lon as u32)
+            .put_u32(self.alt as u32)
+            .put_u32(self.heading as u32)
+            .put_u32(self.speed)
+            .put_u64(self.timestamp);
+    }
+}
+
+impl FlightLog {
+    pub fn is_empty(&self) -> bool {
+        self.legs.is_empty() && self.positions.is_empty()
+    }
+
+    pub fn get_legs(&self) -> &[Leg] {
+        &self.legs
+    }
+
+    pub fn get_positions(&self) -> &[Position] {
+        &self.positions
+    }
+
+    //  Waypoint the drone last arrived at, `None` before its first leg
+    pub fn get_location(&self) -> Option<&str> {
+        self.legs.last().map(|leg| leg.to_waypoint.as_str())
+    }
+
+    pub fn get_last_position(&self) -> Option<&Position> {
+        self.positions.last()
+    }
+
+    /**
+        Records `leg` in a block made at `block_timestamp`. It must
+        depart from where the last leg arrived, no earlier than it
+        arrived, and must have arrived by the time of the block.
+     */
+    pub fn add_leg(&mut self, leg: Leg, block_timestamp: u64) -> Result<(), FlightError> {
+        leg.check()?;
+        if leg.arrived_at > block_timestamp {
+            return Err(FlightError::InFuture { timestamp: leg.arrived_at, block_timestamp });
+        }
+        if let Some(last) = self.legs.last() {
+            if leg.from_waypoint != last.to_waypoint {
+                return Err(FlightError::WrongOrigin {
+                    expected: last.to_waypoint.clone(),
+                    found: leg.from_waypoint,
+                });
+            }
+            if leg.departed_at < last.arrived_at {
+                return Err(FlightError::OutOfOrder);
+            }
+        }
+
+        self.legs.push(leg);
+        Ok(())
+    }
+
+    //  Records `position`, which must be newer than the last one and not after the block
+    pub fn add_position(&mut self, position: Position, block_timestamp: u64) -> Result<(), FlightError> {
+        position.check()?;
+        if position.timestamp > block_timestamp {
+            return Err(FlightError::InFuture { timestamp: position.timestamp, block_timestamp });
+        }
+        if self.positions.last().is_some_and(|last| position.timestamp <= last.timestamp) {
+            return Err(FlightError::OutOfOrder);
+        }
+
+        self.positions.push(position);
+        Ok(())
+    }
+
+    //  Undoes `add_leg`
+    pub fn remove_leg(&mut self) {
+        self.legs.pop();
+    }
+
+    //  Undoes `add_position`
+    pub fn remove_position(&mut self) {
+        self.positions.pop();
+    }
+
+    //  Part of the state root
+    pub fn encode_fields(&self, encoder: &mut Encoder) {
+        encoder.put_u32(self.legs.len() as u32);
+        for leg in &self.legs {
+            leg.encode_fields(encoder);
+        }
+        encoder.put_u32(self.positions.len() as u32);
+        for position in &self.positions {
+            position.encode_fields(encoder);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::account::Account;
+    use super::{FlightError, FlightLog, Leg, Position};
+
+    fn get_waypoints(n: usize) -> Vec<String> {
+        (0..n).map(|_| Account::gen_account().get_id()).collect()
+    }
+
+    #[test]
+    fn test_check_leg() {
+        let waypoints = get_waypoints(2);
+        let (a, b) = (&waypoints[0], &waypoints[1]);
+
+        assert_eq!(Leg::new(a.clone(), b.clone(), 10, 20).check(), Ok(()));
+        assert_eq!(Leg::new(a.clone(), a.clone(), 10, 20).check(), Err(FlightError::SameWaypoint));
+        assert_eq!(
+            Leg::new(a.clone(), b.clone(), 20, 10).check(),
+            Err(FlightError::ArrivedBeforeDeparture)
+        );
+        assert_eq!(
+            Leg::new(a.clone(), "waypoint".to_string(), 10, 20).check(),
+            Err(FlightError::InvalidWaypoint("waypoint".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_check_position() {
+        //  Kyiv, 150 m up, heading east at 12 m/s
+        assert_eq!(Position::new(504_501_000, 305_234_000, 150_000, 9_000, 1_200, 10).check(), Ok(()));
+        assert_eq!(Position::new(-900_000_001, 0, 0, 0, 0, 10).check(), Err(FlightError::OutOfRange));
+        assert_eq!(Position::new(0, 1_800_000_001, 0, 0, 0, 10).check(), Err(FlightError::OutOfRange));
+        assert_eq!(Position::new(0, 0, 0, 36_000, 0, 10).check(), Err(FlightError::OutOfRange));
+        //  Has no positive counterpart, so can't be compared by its absolute value
+        assert_eq!(Position::new(i32::MIN, 0, 0, 0, 0, 10).check(), Err(FlightError::OutOfRange));
+        assert_eq!(Position::new(0, i32::MIN, 0, 0, 0, 10).check(), Err(FlightError::OutOfRange));
+    }
+
+    //  Legs chain from waypoint to waypoint
+    #[test]
+    fn test_add_legs() {
+        let waypoints = get_waypoints(3);
+        let mut log = FlightLog::default();
+
+        log.add_leg(Leg::new(waypoints[0].clone(), waypoints[1].clone(), 10, 20), 100).unwrap();
+        assert_eq!(log.get_location(), Some(waypoints[1].as_str()));
+
+        assert_eq!(
+            log.add_leg(Leg::new(waypoints[0].clone(), waypoints[2].clone(), 30, 40), 100),
+            Err(FlightError::WrongOrigin {
+                expected: waypoints[1].clone(), found: waypoints[0].clone()
+            })
+        );
+        assert_eq!(
+            log.add_leg(Leg::new(waypoints[1].clone(), waypoints[2].clone(), 15, 40), 100),
+            Err(FlightError::OutOfOrder)
+        );
+        assert_eq!(
+            log.add_leg(Leg::new(waypoints[1].clone(), waypoints[2].clone(), 30, 140), 100),
+            Err(FlightError::InFuture { timestamp: 140, block_timestamp: 100 })
+        );
+
+        log.add_leg(Leg::new(waypoints[1].clone(), waypoints[2].clone(), 20, 40), 100).unwrap();
+        log.remove_leg();
+        assert_eq!(log.get_location(), Some(waypoints[1].as_str()));
+    }
+
+    #[test]
+    fn test_add_positions() {
+        let mut log = FlightLog::default();
+
+        log.add_position(Position::new(0, 0, 0, 0, 0, 10), 100).unwrap();
+        assert_eq!(
+            log.add_position(Position::new(0, 0, 0, 0, 0, 10), 100),
+            Err(FlightError::OutOfOrder)
+        );
+        assert_eq!(
+            log.add_position(Position::new(0, 0, 0, 0, 0, 101), 100),
+            Err(FlightError::InFuture { timestamp: 101, block_timestamp: 100 })
+        );
+        log.add_position(Position::new(1, 1, 0, 0, 0, 11), 100).unwrap();
+        assert_eq!(log.get_last_position().unwrap().get_timestamp(), 11);
+    }
+}
@@ -0,0 +1,275 @@
+use serde::Serialize;
+
+use crate::hash::{ChainHasher, Hash32, HashAlgorithm};
+
+//  Domain separation from RFC 6962, so a leaf can't pass for a node
+const LEAF_PREFIX: u8 = 0x00;
+const NODE_PREFIX: u8 = 0x01;
+
+/**
+    Binary hash tree over a list of leaves laid out as in RFC 6962.
+    Leaves and parents are hashed under different prefixes, and a
+    node without a sibling moves up a level unchanged.
+ */
+#[derive(Clone, Debug)]
+pub struct MerkleTree {
+    algorithm: HashAlgorithm,
+    leaves: Vec<Hash32>,
+    //  Leaf hashes first, root last
+    levels: Vec<Vec<Hash32>>,
+}
+
+/**
+    Evidence that `leaf` is at `index` in a tree of `size` leaves
+    with a given root, made of the sibling hashes from the leaf up.
+    Which side each sibling is on follows from the index and size.
+    It names the hash algorithm so it can be checked without the
+    chain's config.
+ */
+#[derive(Serialize, Clone, Debug, PartialEq, Eq)]
+pub struct MerkleProof {
+    algorithm: HashAlgorithm,
+    leaf: Hash32,
+    index: u64,
+    size: u64,
+    path: Vec<Hash32>,
+}
+
+impl MerkleTree {
+    pub fn new(leaves: Vec<Hash32>, algorithm: HashAlgorithm) -> Self {
+        let hashed: Vec<Hash32> = leaves.iter().map(|leaf| hash_leaf(algorithm, leaf)).collect();
+        let mut levels = vec![hashed];
+
+        while levels.last().unwrap().len() > 1 {
+            let next = levels.last().unwrap()
+                .chunks(2)
+                .map(|pair| match pair {
+                    [left, right] => hash_pair(algorithm, left, right),
+                    [single] => *single,
+                    _ => unreachable!(),
+                })
+                .collect();
+            levels.push(next);
+        }
+
+        MerkleTree { algorithm, leaves, levels }
+    }
+
+    //  No leaves hash the empty input
+    pub fn root(&self) -> Hash32 {
+        match self.levels.last().unwrap().first() {
+            Some(root) => *root,
+            None => self.algorithm.hash(&[]),
+        }
+    }
+
+    pub fn get_leaves(&self) -> &[Hash32] {
+        &self.leaves
+    }
+
+    //  Proof for the first occurrence of `leaf`, if it is in the tree
+    pub fn prove_inclusion(&self, leaf: &Hash32) -> Option<MerkleProof> {
+        let first = self.leaves.iter().position(|l| l == leaf)?;
+        let mut index = first;
+        let mut path = Vec::new();
+
+        for level in &self.levels[..self.levels.len() - 1] {
+            //  A lone last node has no sibling to record
+            if let Some(sibling) = level.get(index ^ 1) {
+                path.push(*sibling);
+            }
+            index /= 2;
+        }
+
+        Some(MerkleProof {
+            algorithm: self.algorithm,
+            leaf: *leaf,
+            index: first as u64,
+            size: self.leaves.len() as u64,
+            path,
+        })
+    }
+}
+
+impl MerkleProof {
+    pub fn get_algorithm(&self) -> HashAlgorithm {
+        self.algorithm
+    }
+
+    pub fn get_leaf(&self) -> &Hash32 {
+        &self.leaf
+    }
+
+    pub fn get_index(&self) -> u64 {
+        self.index
+    }
+
+    //  Number of leaves in the tree the proof was made from
+    pub fn get_size(&self) -> u64 {
+        self.size
+    }
+
+    pub fn get_path(&self) -> &[Hash32] {
+        &self.path
+    }
+}
+
+pub fn merkle_root(leaves: &[Hash32], algorithm: HashAlgorithm) -> Hash32 {
+    MerkleTree::new(leaves.to_vec(), algorithm).root()
+}
+
+/**
+    Whether folding `proof` from its leaf up ends at `root`,
+    following the inclusion check of RFC 9162 section 2.1.3.2.
+    The path must be exactly as long as the index and size need.
+ */
+pub fn verify_inclusion(root: &Hash32, proof: &MerkleProof) -> bool {
+    if proof.index >= proof.size {
+        return false;
+    }
+
+    let mut index = proof.index;
+    //  Index of the last node on the current level
+    let mut last = proof.size - 1;
+    let mut hash = hash_leaf(proof.algorithm, &proof.leaf);
+
+    for sibling in &proof.path {
+        if last == 0 {
+            return false;
+        }
+        if index & 1 == 1 || index == last {
+            hash = hash_pair(proof.algorithm, sibling, &hash);
+            //  Skip the levels this node was carried up without a sibling
+            while index & 1 == 0 && index != 0 {
+                index /= 2;
+                last /= 2;
+            }
+        } else {
+            hash = hash_pair(proof.algorithm, &hash, sibling);
+        }
+        index /= 2;
+        last /= 2;
+    }
+
+    last == 0 && hash == *root
+}
+
+fn hash_leaf(algorithm: HashAlgorithm, leaf: &Hash32) -> Hash32 {
+    let mut data = Vec::with_capacity(33);
+    data.push(LEAF_PREFIX);
+    data.extend_from_slice(leaf.as_bytes());
+    algorithm.hash(&data)
+}
+
+fn hash_pair(algorithm: HashAlgorithm, left: &Hash32, right: &Hash32) -> Hash32 {
+    let mut data = Vec::with_capacity(65);
+    data.push(NODE_PREFIX);
+    data.extend_from_slice(left.as_bytes());
+    data.extend_from_slice(right.as_bytes());
+    algorithm.hash(&data)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::hash::{ChainHasher, Hash32, HashAlgorithm};
+    use super::{hash_leaf, hash_pair, merkle_root, verify_inclusion, MerkleProof, MerkleTree};
+
+    fn get_leaves(n: usize) -> Vec<Hash32> {
+        (0..n).map(|i| HashAlgorithm::Sha256.hash(&i.to_be_bytes())).collect()
+    }
+
+    #[test]
+    fn test_merkle_root() {
+        let algorithm = HashAlgorithm::Sha256;
+        let leaves = get_leaves(3);
+        let hashed: Vec<Hash32> = leaves.iter().map(|l| hash_leaf(algorithm, l)).collect();
+        let left = hash_pair(algorithm, &hashed[0], &hashed[1]);
+
+        assert_eq!(merkle_root(&leaves, algorithm), hash_pair(algorithm, &left, &hashed[2]));
+        assert_eq!(merkle_root(&leaves[..1], algorithm), hashed[0]);
+        assert_eq!(merkle_root(&[], algorithm), algorithm.hash(&[]));
+        assert_ne!(merkle_root(&leaves, HashAlgorithm::Blake3), merkle_root(&leaves, algorithm));
+    }
+
+    #[test]
+    fn test_prove_and_verify_every_leaf() {
+        for algorithm in [HashAlgorithm::Sha256, HashAlgorithm::Blake3] {
+            for n in 1..8 {
+                let leaves = get_leaves(n);
+                let tree = MerkleTree::new(leaves.clone(), algorithm);
+                let root = tree.root();
+
+                for (i, leaf) in leaves.iter().enumerate() {
+                    let proof = tree.prove_inclusion(leaf).unwrap();
+                    assert_eq!((proof.get_index(), proof.get_size()), (i as u64, n as u64));
+                    assert!(verify_inclusion(&root, &proof));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_proof_is_logarithmic() {
+        let tree = MerkleTree::new(get_leaves(16), HashAlgorithm::Sha256);
+        let proof = tree.prove_inclusion(&tree.get_leaves()[5]).unwrap();
+
+        assert_eq!(proof.get_path().len(), 4);
+    }
+
+    #[test]
+    fn test_reject_wrong_root_or_leaf() {
+        let algorithm = HashAlgorithm::Sha256;
+        let leaves = get_leaves(5);
+        let tree = MerkleTree::new(leaves.clone(), algorithm);
+        let mut proof = tree.prove_inclusion(&leaves[2]).unwrap();
+
+        assert!(!verify_inclusion(&merkle_root(&leaves[..4], algorithm), &proof));
+
+        proof.leaf = leaves[3];
+        assert!(!verify_inclusion(&tree.root(), &proof));
+
+        //  Same path claimed for another position
+        proof.leaf = leaves[2];
+        proof.index = 3;
+        assert!(!verify_inclusion(&tree.root(), &proof));
+        proof.index = 2;
+        proof.size = 4;
+        assert!(!verify_inclusion(&tree.root(), &proof));
+    }
+
+    //  Repeating the last leaf used to give the same root as the shorter list
+    #[test]
+    fn test_duplicated_leaf_changes_root() {
+        let algorithm = HashAlgorithm::Sha256;
+        let leaves = get_leaves(3);
+        let padded = [leaves.clone(), vec![leaves[2]]].concat();
+
+        assert_ne!(merkle_root(&leaves, algorithm), merkle_root(&padded, algorithm));
+
+        let tree = MerkleTree::new(leaves.clone(), algorithm);
+        let proof = MerkleTree::new(padded.clone(), algorithm)
+            .prove_inclusion(&padded[3])
+            .unwrap();
+        assert!(!verify_inclusion(&tree.root(), &proof));
+    }
+
+    //  An interior node passed off as a leaf of a smaller tree
+    #[test]
+    fn test_reject_interior_node_as_leaf() {
+        let algorithm = HashAlgorithm::Sha256;
+        let leaves = get_leaves(4);
+        let tree = MerkleTree::new(leaves.clone(), algorithm);
+        let hashed: Vec<Hash32> = leaves.iter().map(|l| hash_leaf(algorithm, l)).collect();
+        let left = hash_pair(algorithm, &hashed[0], &hashed[1]);
+        let right = hash_pair(algorithm, &hashed[2], &hashed[3]);
+
+        let forged = MerkleProof { algorithm, leaf: left, index: 0, size: 2, path: vec![right] };
+        assert!(!verify_inclusion(&tree.root(), &forged));
+    }
+
+    #[test]
+    fn test_missing_leaf_has_no_proof() {
+        let tree = MerkleTree::new(get_leaves(3), HashAlgorithm::Sha256);
+        assert!(tree.prove_inclusion(&Hash32::default()).is_none());
+    }
+}
@@ -1,26 +1,175 @@
-use openssl::sha::Sha1;
+use std::fmt;
+use std::str::FromStr;
+
+use openssl::sha::sha256;
+use serde::{Serialize, Serializer};
+
+pub use self::merkle::{merkle_root, verify_inclusion, MerkleProof, MerkleTree};
+
+mod merkle;
+
+/**
+    Fixed size digest used for block, transaction,
+    merkle and state ids. Shown as lowercase hex.
+ */
+#[derive(Clone, Copy, Default, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct Hash32([u8; 32]);
+
+/**
+    A hash function the chain can be configured with.
+ */
+pub trait ChainHasher {
+    fn hash(&self, data: &[u8]) -> Hash32;
+}
+
+pub struct Sha256Hasher;
+
+pub struct Blake3Hasher;
 
 /**
-    Applies SHA1 to provided vector of a generic type.
-    Returns ASCII form of hashed value
+    Hash function recorded in the genesis configuration.
+    Every id on the chain is computed with it.
  */
-pub fn to_sha1(data: &String) -> String {
-    let mut hasher = Sha1::new();
+#[derive(Serialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum HashAlgorithm {
+    #[default]
+    Sha256,
+    Blake3,
+}
+
+impl Hash32 {
+    pub fn new(bytes: [u8; 32]) -> Self {
+        Hash32(bytes)
+    }
+
+    pub fn as_bytes(&self) -> &[u8; 32] {
+        &self.0
+    }
+
+    pub fn to_hex(&self) -> String {
+        hex::encode(self.0)
+    }
+
+    pub fn from_hex(s: &str) -> Result<Self, hex::FromHexError> {
+        let mut bytes = [0u8; 32];
+        hex::decode_to_slice(s, &mut bytes)?;
+        Ok(Hash32(bytes))
+    }
+
+    pub fn leading_zero_bits(&self) -> u32 {
+        let mut zeros = 0;
+        for byte in self.0 {
+            zeros += byte.leading_zeros();
+            if byte != 0 {
+                break;
+            }
+        }
+        zeros
+    }
+}
 
-    hasher.update(data.as_bytes());
-    let res = hasher.finish();
-    hex::encode(res)
+impl fmt::Display for Hash32 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.to_hex())
+    }
+}
+
+impl fmt::Debug for Hash32 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Hash32({})", self.to_hex())
+    }
+}
+
+impl FromStr for Hash32 {
+    type Err = hex::FromHexError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Self::from_hex(s)
+    }
+}
+
+impl Serialize for Hash32 {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error> where S: Serializer {
+        serializer.serialize_str(&self.to_hex())
+    }
+}
+
+impl ChainHasher for Sha256Hasher {
+    fn hash(&self, data: &[u8]) -> Hash32 {
+        Hash32(sha256(data))
+    }
+}
+
+impl ChainHasher for Blake3Hasher {
+    fn hash(&self, data: &[u8]) -> Hash32 {
+        Hash32(*blake3::hash(data).as_bytes())
+    }
+}
+
+impl HashAlgorithm {
+    pub fn hasher(&self) -> &'static dyn ChainHasher {
+        match self {
+            HashAlgorithm::Sha256 => &Sha256Hasher,
+            HashAlgorithm::Blake3 => &Blake3Hasher,
+        }
+    }
+}
+
+impl ChainHasher for HashAlgorithm {
+    fn hash(&self, data: &[u8]) -> Hash32 {
+        self.hasher().hash(data)
+    }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::hash::to_sha1;
+    use crate::hash::{Blake3Hasher, ChainHasher, Hash32, HashAlgorithm, Sha256Hasher};
 
     #[test]
-    fn test_to_sha1() {
-        let data = String::from("Hello World");
-        let hashed = to_sha1(&data);
+    fn test_sha256() {
+        let hashed = Sha256Hasher.hash(b"Hello World");
 
-        assert_eq!(hashed, "0a4d55a8d778e5022fab701977c5d840bbc486d0");
+        assert_eq!(
+            hashed.to_hex(),
+            "a591a6d40bf420404a011733cfb7b190d62c65bf0bcda32b57b277d9ad9f146e"
+        );
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_blake3() {
+        let hashed = Blake3Hasher.hash(b"Hello World");
+
+        assert_eq!(
+            hashed.to_hex(),
+            "41f8394111eb713a22165c46c90ab8f0fd9399c92028fd6d288944b23ff5bf76"
+        );
+    }
+
+    #[test]
+    fn test_algorithm_dispatch() {
+        assert_eq!(HashAlgorithm::default(), HashAlgorithm::Sha256);
+        assert_eq!(HashAlgorithm::Sha256.hash(b"abc"), Sha256Hasher.hash(b"abc"));
+        assert_eq!(HashAlgorithm::Blake3.hash(b"abc"), Blake3Hasher.hash(b"abc"));
+    }
+
+    #[test]
+    fn test_hex_round_trip() {
+        let hashed = Sha256Hasher.hash(b"Hello World");
+
+        assert_eq!(hashed.to_hex().parse::<Hash32>().unwrap(), hashed);
+        assert_eq!(hashed.to_string(), hashed.to_hex());
+        assert!(Hash32::from_hex("abcd").is_err());
+        assert!(Hash32::from_hex(&"zz".repeat(32)).is_err());
+    }
+
+    #[test]
+    fn test_leading_zero_bits() {
+        let mut bytes = [0xffu8; 32];
+        assert_eq!(Hash32::new(bytes).leading_zero_bits(), 0);
+
+        bytes[0] = 0;
+        bytes[1] = 0x0f;
+        assert_eq!(Hash32::new(bytes).leading_zero_bits(), 12);
+        assert_eq!(Hash32::default().leading_zero_bits(), 256);
+    }
+}
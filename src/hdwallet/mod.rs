@@ -0,0 +1,408 @@
+//  Deterministic key derivation for drone fleets
+
+use openssl::{
+    hash::MessageDigest,
+    pkcs5::pbkdf2_hmac,
+    pkey::PKey,
+    rand::rand_bytes,
+    sign::Signer,
+};
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+
+use crate::account::Account;
+use crate::hash::{ChainHasher, HashAlgorithm};
+use crate::keysig::{KeyAlgorithm, KeySig};
+
+const ENTROPY_LEN: usize = 16;
+const SEED_ITERATIONS: u32 = 2048;
+
+//  Indices at or above this are hardened, the only kind supported
+pub const HARDENED: u32 = 0x8000_0000;
+
+//  First path component of the company's own key
+pub const COMPANY_BRANCH: u32 = 0;
+//  First path component of drone keys, the second is the drone serial number
+pub const DRONE_BRANCH: u32 = 1;
+
+/**
+    Backup words: every byte of the entropy, then one checksum
+    byte, is spelled as a consonant followed by a two letter rhyme.
+    This is our own word list, not BIP-39.
+ */
+const ONSETS: [&str; 16] = [
+    "b", "d", "f", "g", "h", "j", "k", "l", "m", "n", "p", "r", "s", "t", "v", "z"
+];
+const RHYMES: [&str; 16] = [
+    "ab", "ad", "ak", "al", "am", "an", "ar", "as", "ed", "el", "em", "en", "et", "ik", "im", "ol"
+];
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum HdError {
+    //  Not a word of the list, or the wrong number of words
+    InvalidMnemonic,
+    //  Words are valid but the checksum disagrees, likely a typo
+    BadChecksum,
+    InvalidPath(String),
+    UnsupportedAlgorithm(KeyAlgorithm),
+    //  Serial numbers must fit a hardened index
+    SerialOutOfRange(u32),
+}
+
+impl fmt::Display for HdError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            HdError::InvalidMnemonic => write!(f, "mnemonic is not valid"),
+            HdError::BadChecksum => write!(f, "mnemonic checksum does not match"),
+            HdError::InvalidPath(path) => write!(f, "derivation path {} is not valid", path),
+            HdError::UnsupportedAlgorithm(algorithm) =>
+                write!(f, "{} keys can't be derived", algorithm),
+            HdError::SerialOutOfRange(serial) =>
+                write!(f, "serial number {} is out of range", serial),
+        }
+    }
+}
+
+impl Error for HdError {}
+
+/**
+    Backup phrase for a master seed. Writing it down is enough
+    to re-provision every key derived from the seed.
+ */
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Mnemonic {
+    entropy: [u8; ENTROPY_LEN],
+}
+
+impl Mnemonic {
+    pub fn generate() -> Self {
+        let mut entropy = [0u8; ENTROPY_LEN];
+        rand_bytes(&mut entropy).unwrap();
+        Mnemonic { entropy }
+    }
+
+    pub fn from_entropy(entropy: [u8; ENTROPY_LEN]) -> Self {
+        Mnemonic { entropy }
+    }
+
+    fn checksum(entropy: &[u8]) -> u8 {
+        HashAlgorithm::Sha256.hash(entropy).as_bytes()[0]
+    }
+
+    fn word(byte: u8) -> String {
+        format!("{}{}", ONSETS[(byte >> 4) as usize], RHYMES[(byte & 0x0f) as usize])
+    }
+
+    fn byte(word: &str) -> Option<u8> {
+        let onset = ONSETS.iter().position(|o| word.get(..1) == Some(*o))?;
+        let rhyme = RHYMES.iter().position(|r| word.get(1..) == Some(*r))?;
+        Some((onset << 4 | rhyme) as u8)
+    }
+
+    pub fn from_phrase(phrase: &str) -> Result<Self, HdError> {
+        let bytes = phrase.split_whitespace()
+            .map(|word| Self::byte(&word.to_lowercase()))
+            .collect::<Option<Vec<u8>>>()
+            .ok_or(HdError::InvalidMnemonic)?;
+        if bytes.len() != ENTROPY_LEN + 1 {
+            return Err(HdError::InvalidMnemonic);
+        }
+
+        let (entropy, checksum) = bytes.split_at(ENTROPY_LEN);
+        if checksum[0] != Self::checksum(entropy) {
+            return Err(HdError::BadChecksum);
+        }
+        Ok(Mnemonic { entropy: entropy.try_into().unwrap() })
+    }
+
+    pub fn to_phrase(&self) -> String {
+        self.entropy.iter()
+            .chain([Self::checksum(&self.entropy)].iter())
+            .map(|byte| Self::word(*byte))
+            .collect::<Vec<String>>()
+            .join(" ")
+    }
+
+    //  Stretches the phrase and an optional passphrase into a 64 byte seed
+    pub fn to_seed(&self, passphrase: &str) -> [u8; 64] {
+        let mut seed = [0u8; 64];
+        pbkdf2_hmac(
+            self.to_phrase().as_bytes(),
+            format!("mnemonic{}", passphrase).as_bytes(),
+            SEED_ITERATIONS as usize,
+            MessageDigest::sha512(),
+            &mut seed
+        ).unwrap();
+        seed
+    }
+}
+
+impl fmt::Display for Mnemonic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.to_phrase())
+    }
+}
+
+/**
+    Hardened derivation path such as `m/1'/4021'`.
+    Every index is stored with the hardened bit set.
+ */
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct DerivationPath(Vec<u32>);
+
+impl DerivationPath {
+    //  Path of the company's own key, `m/0'`
+    pub fn company() -> Self {
+        DerivationPath(vec![COMPANY_BRANCH | HARDENED])
+    }
+
+    //  Path of a drone's key, `m/1'/<serial>'`
+    pub fn drone(serial: u32) -> Result<Self, HdError> {
+        if serial >= HARDENED {
+            return Err(HdError::SerialOutOfRange(serial));
+        }
+        Ok(DerivationPath(vec![DRONE_BRANCH | HARDENED, serial | HARDENED]))
+    }
+
+    pub fn get_indices(&self) -> &[u32] {
+        &self.0
+    }
+}
+
+impl FromStr for DerivationPath {
+    type Err = HdError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || HdError::InvalidPath(s.to_string());
+        let mut parts = s.split('/');
+        if parts.next() != Some("m") {
+            return Err(invalid());
+        }
+
+        parts
+            .map(|part| {
+                let index: u32 = part.strip_suffix('\'').ok_or_else(invalid)?
+                    .parse().map_err(|_| invalid())?;
+                if index >= HARDENED {
+                    return Err(invalid());
+                }
+                Ok(index | HARDENED)
+            })
+            .collect::<Result<Vec<u32>, HdError>>()
+            .map(DerivationPath)
+    }
+}
+
+impl fmt::Display for DerivationPath {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "m")?;
+        for index in &self.0 {
+            write!(f, "/{}'", index & !HARDENED)?;
+        }
+        Ok(())
+    }
+}
+
+/**
+    Master secret from which a company derives its own key and
+    the key of every drone it owns, following SLIP-10 with
+    hardened derivation only.
+ */
+#[derive(Clone)]
+pub struct HdWallet {
+    algorithm: KeyAlgorithm,
+    seed: Vec<u8>,
+}
+
+impl HdWallet {
+    //  RSA has no deterministic derivation and is refused
+    pub fn from_seed(seed: &[u8], algorithm: KeyAlgorithm) -> Result<Self, HdError> {
+        if algorithm == KeyAlgorithm::Rsa2048 {
+            return Err(HdError::UnsupportedAlgorithm(algorithm));
+        }
+        Ok(HdWallet { algorithm, seed: seed.to_vec() })
+    }
+
+    pub fn from_mnemonic(
+        mnemonic: &Mnemonic, passphrase: &str, algorithm: KeyAlgorithm
+    ) -> Result<Self, HdError> {
+        Self::from_seed(&mnemonic.to_seed(passphrase), algorithm)
+    }
+
+    pub fn get_algorithm(&self) -> KeyAlgorithm {
+        self.algorithm
+    }
+
+    fn hmac_sha512(key: &[u8], data: &[u8]) -> ([u8; 32], [u8; 32]) {
+        let key = PKey::hmac(key).unwrap();
+        let mut signer = Signer::new(MessageDigest::sha512(), &key).unwrap();
+        signer.update(data).unwrap();
+        let digest = signer.sign_to_vec().unwrap();
+        (digest[..32].try_into().unwrap(), digest[32..].try_into().unwrap())
+    }
+
+    /**
+        Derives the key at `path`. For P-256 a secret outside the
+        curve order is rehashed until it fits, as SLIP-10 specifies.
+     */
+    pub fn derive(&self, path: &DerivationPath) -> KeySig {
+        let curve_key: &[u8] = match self.algorithm {
+            KeyAlgorithm::EcdsaP256 => b"Nist256p1 seed",
+            _ => b"ed25519 seed",
+        };
+        let (mut secret, mut chain_code) = Self::hmac_sha512(curve_key, &self.seed);
+        //  Only reachable for P-256, with odds of about 2^-32
+        while KeySig::from_secret(self.algorithm, &secret).is_err() {
+            (secret, chain_code) = Self::hmac_sha512(curve_key, &[&secret[..], &chain_code[..]].concat());
+        }
+
+        for index in path.get_indices() {
+            let mut data = [&[0u8][..], &secret[..], &index.to_be_bytes()[..]].concat();
+            loop {
+                let (child, child_chain_code) = Self::hmac_sha512(&chain_code, &data);
+                if KeySig::from_secret(self.algorithm, &child).is_ok() {
+                    (secret, chain_code) = (child, child_chain_code);
+                    break;
+                }
+                data = [&[1u8][..], &child_chain_code[..], &index.to_be_bytes()[..]].concat();
+            }
+        }
+
+        KeySig::from_secret(self.algorithm, &secret).unwrap()
+    }
+
+    pub fn company_key(&self) -> KeySig {
+        self.derive(&DerivationPath::company())
+    }
+
+    pub fn drone_key(&self, serial: u32) -> Result<KeySig, HdError> {
+        Ok(self.derive(&DerivationPath::drone(serial)?))
+    }
+
+    //  Company account holding only its own key, drone keys are added per serial
+    pub fn company_account(&self) -> Account {
+        Account::from_wallets(vec![self.company_key()]).unwrap()
+    }
+
+    //  Account a drone signs with, re-creatable from the backup alone
+    pub fn drone_account(&self, serial: u32) -> Result<Account, HdError> {
+        Ok(Account::from_wallets(vec![self.drone_key(serial)?]).unwrap())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::keysig::KeyAlgorithm;
+    use super::{DerivationPath, HdError, HdWallet, Mnemonic, HARDENED};
+
+    fn get_wallet(algorithm: KeyAlgorithm) -> HdWallet {
+        HdWallet::from_mnemonic(&Mnemonic::from_entropy([7; 16]), "", algorithm).unwrap()
+    }
+
+    #[test]
+    fn test_mnemonic_round_trip() {
+        let mnemonic = Mnemonic::generate();
+        let phrase = mnemonic.to_phrase();
+
+        assert_eq!(phrase.split(' ').count(), 17);
+        assert_eq!(Mnemonic::from_phrase(&phrase).unwrap(), mnemonic);
+        assert_eq!(Mnemonic::from_phrase(&phrase.to_uppercase()).unwrap(), mnemonic);
+    }
+
+    #[test]
+    fn test_mnemonic_typos() {
+        let phrase = Mnemonic::from_entropy([0; 16]).to_phrase();
+        let words: Vec<&str> = phrase.split(' ').collect();
+        assert_eq!(words[0], "bab");
+
+        //  Swap the first word for another valid one
+        let typo = std::iter::once("bad").chain(words[1..].iter().copied())
+            .collect::<Vec<&str>>().join(" ");
+        assert_eq!(Mnemonic::from_phrase(&typo).unwrap_err(), HdError::BadChecksum);
+        assert_eq!(Mnemonic::from_phrase("bab bab").unwrap_err(), HdError::InvalidMnemonic);
+        assert_eq!(
+            Mnemonic::from_phrase(&phrase.replacen("bab", "bxb", 1)).unwrap_err(),
+            HdError::InvalidMnemonic
+        );
+    }
+
+    #[test]
+    fn test_derivation_path() {
+        let path: DerivationPath = "m/1'/4021'".parse().unwrap();
+
+        assert_eq!(path, DerivationPath::drone(4021).unwrap());
+        assert_eq!(path.to_string(), "m/1'/4021'");
+        assert_eq!(path.get_indices(), &[1 | HARDENED, 4021 | HARDENED]);
+        assert_eq!(DerivationPath::company().to_string(), "m/0'");
+        for invalid in ["1'/2'", "m/1", "m/x'", "m/2147483648'"] {
+            assert_eq!(
+                invalid.parse::<DerivationPath>().unwrap_err(),
+                HdError::InvalidPath(invalid.to_string())
+            );
+        }
+        assert_eq!(DerivationPath::drone(HARDENED).unwrap_err(), HdError::SerialOutOfRange(HARDENED));
+    }
+
+    //  SLIP-10 test vector 1 for ed25519, must not change between releases
+    #[test]
+    fn test_slip10_vector() {
+        let seed = hex::decode("000102030405060708090a0b0c0d0e0f").unwrap();
+        let wallet = HdWallet::from_seed(&seed, KeyAlgorithm::Ed25519).unwrap();
+        let expected = [
+            ("m", "2b4be7f19ee27bbf30c667b642d5f4aa69fd169872f8fc3059c08ebae2eb19e7"),
+            ("m/0'", "68e0fe46dfb67e368c75379acec591dad19df3cde26e63b93a8e704f1dade7a3"),
+        ];
+
+        for (path, secret) in expected {
+            let keysig = wallet.derive(&path.parse().unwrap());
+            let reference = crate::keysig::KeySig::from_secret(
+                KeyAlgorithm::Ed25519, &hex::decode(secret).unwrap().try_into().unwrap()
+            ).unwrap();
+            assert_eq!(keysig.get_public_key(), reference.get_public_key());
+        }
+    }
+
+    //  The whole fleet comes back from the backup phrase
+    #[test]
+    fn test_reprovision_fleet() {
+        for algorithm in [KeyAlgorithm::Ed25519, KeyAlgorithm::EcdsaP256] {
+            let wallet = get_wallet(algorithm);
+            let restored = HdWallet::from_mnemonic(
+                &Mnemonic::from_phrase(&Mnemonic::from_entropy([7; 16]).to_phrase()).unwrap(),
+                "",
+                algorithm
+            ).unwrap();
+
+            for serial in [0, 1, 4021] {
+                let key = wallet.drone_key(serial).unwrap();
+                assert_eq!(key.get_algorithm(), algorithm);
+                assert_eq!(key.get_public_key(), restored.drone_key(serial).unwrap().get_public_key());
+            }
+            assert_ne!(
+                wallet.drone_key(1).unwrap().get_public_key(),
+                wallet.drone_key(2).unwrap().get_public_key()
+            );
+            assert_eq!(wallet.company_account().get_id(), restored.company_account().get_id());
+        }
+    }
+
+    #[test]
+    fn test_passphrase_changes_keys() {
+        let mnemonic = Mnemonic::from_entropy([7; 16]);
+        let other = HdWallet::from_mnemonic(&mnemonic, "hangar", KeyAlgorithm::Ed25519).unwrap();
+
+        assert_ne!(
+            get_wallet(KeyAlgorithm::Ed25519).company_account().get_id(),
+            other.company_account().get_id()
+        );
+    }
+
+    #[test]
+    fn test_reject_rsa() {
+        assert!(matches!(
+            HdWallet::from_seed(&[0; 64], KeyAlgorithm::Rsa2048),
+            Err(HdError::UnsupportedAlgorithm(KeyAlgorithm::Rsa2048))
+        ));
+    }
+}
@@ -1,70 +1,376 @@
 use openssl::{
+    base64,
+    bn::{BigNum, BigNumContext},
+    ec::{EcGroup, EcKey, EcPoint},
     hash::MessageDigest,
-    pkey::{PKey, Private},
+    nid::Nid,
+    pkey::{HasPublic, Id, PKey, PKeyRef, Private, Public},
     rsa::Rsa,
     sign::{Signer, Verifier},
 };
+use std::error::Error;
+use std::fmt;
 use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
-use serde::de::{Error, MapAccess, SeqAccess, Visitor};
 use serde::ser::SerializeStruct;
 
 extern crate openssl;
 
+/**
+    Signature schemes a key can use. The tag is the first byte
+    of every public key, so a verifier knows how to check a
+    signature from the account id alone.
+ */
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum KeyAlgorithm {
+    #[default]
+    Rsa2048,
+    //  Small keys and signatures, suited to drone transponders
+    Ed25519,
+    EcdsaP256,
+}
+
+impl KeyAlgorithm {
+    pub fn get_tag(&self) -> u8 {
+        match self {
+            KeyAlgorithm::Rsa2048 => 1,
+            KeyAlgorithm::Ed25519 => 2,
+            KeyAlgorithm::EcdsaP256 => 3,
+        }
+    }
+
+    pub fn from_tag(tag: u8) -> Option<Self> {
+        match tag {
+            1 => Some(KeyAlgorithm::Rsa2048),
+            2 => Some(KeyAlgorithm::Ed25519),
+            3 => Some(KeyAlgorithm::EcdsaP256),
+            _ => None,
+        }
+    }
+
+    //  Algorithm of an imported key, only the exact key types above are accepted
+    fn of_key<T: HasPublic>(key: &PKeyRef<T>) -> Result<Self, KeyError> {
+        match key.id() {
+            Id::RSA if key.bits() == 2048 => Ok(KeyAlgorithm::Rsa2048),
+            Id::ED25519 => Ok(KeyAlgorithm::Ed25519),
+            Id::EC if key.ec_key()?.group().curve_name() == Some(Nid::X9_62_PRIME256V1) =>
+                Ok(KeyAlgorithm::EcdsaP256),
+            _ => Err(KeyError::UnsupportedAlgorithm),
+        }
+    }
+}
+
+impl fmt::Display for KeyAlgorithm {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            KeyAlgorithm::Rsa2048 => "rsa-2048",
+            KeyAlgorithm::Ed25519 => "ed25519",
+            KeyAlgorithm::EcdsaP256 => "ecdsa-p256",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+//  Why a key could not be imported
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum KeyError {
+    Malformed,
+    UnsupportedAlgorithm,
+    //  Stored public key doesn't belong to the stored private key
+    MismatchedPublicKey,
+}
+
+impl fmt::Display for KeyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            KeyError::Malformed => write!(f, "key is malformed"),
+            KeyError::UnsupportedAlgorithm =>
+                write!(f, "key is not rsa-2048, ed25519 or ecdsa-p256"),
+            KeyError::MismatchedPublicKey =>
+                write!(f, "public key does not match the private key"),
+        }
+    }
+}
+
+impl Error for KeyError {}
+
+impl From<openssl::error::ErrorStack> for KeyError {
+    fn from(_: openssl::error::ErrorStack) -> Self {
+        KeyError::Malformed
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct KeySig {
-    keypair: Rsa<Private>,
+    algorithm: KeyAlgorithm,
+    keypair: PKey<Private>,
 }
 
 impl KeySig {
-    //  Get public key
+    /**
+        Get public key: the algorithm tag followed by the
+        DER encoded SubjectPublicKeyInfo.
+     */
     pub fn get_public_key(&self) -> Vec<u8> {
-        self.keypair.clone()
-            .public_key_to_pem().unwrap()
+        let mut public_key = vec![self.algorithm.get_tag()];
+        public_key.extend(self.keypair.public_key_to_der().unwrap());
+        public_key
     }
-    //  Generate key pairs and signature
+
+    //  Public half of the key, safe to hand to verifiers
+    pub fn get_verifying_key(&self) -> VerifyingKey {
+        VerifyingKey::from_bytes(&self.get_public_key()).unwrap()
+    }
+
+    pub fn get_algorithm(&self) -> KeyAlgorithm {
+        self.algorithm
+    }
+
+    //  Generate an RSA-2048 key pair
     pub fn new() -> Self {
-        //  Generate keypair
-        let keypair = Rsa::generate(2048).unwrap();
+        Self::generate(KeyAlgorithm::Rsa2048)
+    }
+
+    //  Generate a key pair for `algorithm`
+    pub fn generate(algorithm: KeyAlgorithm) -> Self {
+        let keypair = match algorithm {
+            KeyAlgorithm::Rsa2048 => PKey::from_rsa(Rsa::generate(2048).unwrap()),
+            KeyAlgorithm::Ed25519 => PKey::generate_ed25519(),
+            KeyAlgorithm::EcdsaP256 => {
+                let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1).unwrap();
+                PKey::from_ec_key(EcKey::generate(&group).unwrap())
+            }
+        }.unwrap();
+
+        KeySig { algorithm, keypair }
+    }
+
+    fn from_key(keypair: PKey<Private>) -> Result<Self, KeyError> {
+        Ok(KeySig { algorithm: KeyAlgorithm::of_key(&keypair)?, keypair })
+    }
+
+    /**
+        Key whose private scalar or seed is exactly `secret`, used
+        for deterministic derivation. RSA keys can't be made this
+        way, and a P-256 secret must lie within the curve order.
+     */
+    pub fn from_secret(algorithm: KeyAlgorithm, secret: &[u8; 32]) -> Result<Self, KeyError> {
+        let keypair = match algorithm {
+            KeyAlgorithm::Rsa2048 => return Err(KeyError::UnsupportedAlgorithm),
+            KeyAlgorithm::Ed25519 => PKey::private_key_from_raw_bytes(secret, Id::ED25519)?,
+            KeyAlgorithm::EcdsaP256 => {
+                let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1)?;
+                let mut ctx = BigNumContext::new()?;
+                let mut order = BigNum::new()?;
+                group.order(&mut order, &mut ctx)?;
+
+                let scalar = BigNum::from_slice(secret)?;
+                if scalar.num_bits() == 0 || scalar >= order {
+                    return Err(KeyError::Malformed);
+                }
+                let mut point = EcPoint::new(&group)?;
+                point.mul_generator(&group, &scalar, &ctx)?;
+                PKey::from_ec_key(EcKey::from_private_components(&group, &scalar, &point)?)?
+            }
+        };
+
+        Ok(KeySig { algorithm, keypair })
+    }
+
+    //  Import a PEM private key, either in its algorithm's traditional format or PKCS#8
+    pub fn from_pem(pem: &[u8]) -> Result<Self, KeyError> {
+        Self::from_key(PKey::private_key_from_pem(pem)?)
+    }
+
+    //  Import a DER private key, either in its algorithm's traditional format or PKCS#8
+    pub fn from_der(der: &[u8]) -> Result<Self, KeyError> {
+        Self::from_key(PKey::private_key_from_der(der)?)
+    }
+
+    pub fn from_pkcs8_der(der: &[u8]) -> Result<Self, KeyError> {
+        Self::from_key(PKey::private_key_from_pkcs8(der)?)
+    }
 
-        KeySig { keypair: keypair }
+    /**
+        Private key in the traditional PEM format of its algorithm,
+        PKCS#1 for RSA and SEC1 for ECDSA. Ed25519 only has PKCS#8.
+     */
+    pub fn to_pem(&self) -> Vec<u8> {
+        match self.algorithm {
+            KeyAlgorithm::Rsa2048 => self.keypair.rsa().unwrap().private_key_to_pem(),
+            KeyAlgorithm::Ed25519 => self.keypair.private_key_to_pem_pkcs8(),
+            KeyAlgorithm::EcdsaP256 => self.keypair.ec_key().unwrap().private_key_to_pem(),
+        }.unwrap()
+    }
+
+    //  DER counterpart of `to_pem`
+    pub fn to_der(&self) -> Vec<u8> {
+        self.keypair.private_key_to_der().unwrap()
+    }
+
+    pub fn to_pkcs8_pem(&self) -> Vec<u8> {
+        self.keypair.private_key_to_pem_pkcs8().unwrap()
+    }
+
+    pub fn to_pkcs8_der(&self) -> Vec<u8> {
+        pem_to_der(&self.to_pkcs8_pem())
     }
 
     //  Sign data
     pub fn sign(&self, data: &[u8]) -> Vec<u8> {
-        let keypair = PKey::from_rsa(self.keypair.clone()).unwrap();
-        let mut signer = Signer::new(MessageDigest::sha256(), &keypair).unwrap();
-        signer.update(data).unwrap();
-        signer.sign_to_vec().unwrap()
+        let mut signer = match self.algorithm {
+            KeyAlgorithm::Ed25519 => Signer::new_without_digest(&self.keypair),
+            _ => Signer::new(MessageDigest::sha256(), &self.keypair),
+        }.unwrap();
+        signer.sign_oneshot_to_vec(data).unwrap()
     }
 
-    pub fn to_string(&self) -> String {
-        let public_key = self.keypair.public_key_to_pem().unwrap();
-        let private_key = self.keypair.private_key_to_pem().unwrap();
+    pub fn verify(&self, data: &[u8], signature: &[u8]) -> bool {
+        verify_with_key(self.algorithm, &self.keypair, data, signature)
+    }
+}
 
-        format!(
-            "{}\n{}",
-            String::from_utf8(private_key.clone()).unwrap(),
-            String::from_utf8(public_key.clone()).unwrap()
-        )
+//  Body of a single PEM block, which is base64 of the DER encoding
+fn pem_to_der(pem: &[u8]) -> Vec<u8> {
+    let body: String = String::from_utf8_lossy(pem)
+        .lines()
+        .filter(|line| !line.starts_with("-----"))
+        .collect();
+    base64::decode_block(&body).unwrap()
+}
+
+/**
+    Public key only, what a verifier holds for an account.
+    Serialized as the hex of its tagged bytes, the account id.
+ */
+#[derive(Debug, Clone)]
+pub struct VerifyingKey {
+    algorithm: KeyAlgorithm,
+    key: PKey<Public>,
+}
+
+impl VerifyingKey {
+    //  From a tagged public key, as returned by `KeySig::get_public_key`
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, KeyError> {
+        let (tag, der) = bytes.split_first().ok_or(KeyError::Malformed)?;
+        let algorithm = KeyAlgorithm::from_tag(*tag).ok_or(KeyError::UnsupportedAlgorithm)?;
+        let key = Self::from_der(der)?;
+
+        //  The tag has to agree with the key it labels
+        if key.algorithm != algorithm {
+            return Err(KeyError::UnsupportedAlgorithm);
+        }
+        Ok(key)
+    }
+
+    //  From a key id, the hex of the tagged public key
+    pub fn from_hex(id: &str) -> Result<Self, KeyError> {
+        Self::from_bytes(&hex::decode(id).map_err(|_| KeyError::Malformed)?)
+    }
+
+    //  From a PEM SubjectPublicKeyInfo, without tag
+    pub fn from_pem(pem: &[u8]) -> Result<Self, KeyError> {
+        Self::from_key(PKey::public_key_from_pem(pem)?)
+    }
+
+    //  From a DER SubjectPublicKeyInfo, without tag
+    pub fn from_der(der: &[u8]) -> Result<Self, KeyError> {
+        Self::from_key(PKey::public_key_from_der(der)?)
     }
 
-    pub fn verify(&self, data: &[u8], signature: &Vec<u8>) -> bool {
-        let keypair = PKey::from_rsa(self.keypair.clone()).unwrap();
-        let mut verifier = Verifier::new(MessageDigest::sha256(), &keypair).unwrap();
-        verifier.update(data).unwrap();
-        verifier.verify(signature).unwrap()
+    fn from_key(key: PKey<Public>) -> Result<Self, KeyError> {
+        Ok(VerifyingKey { algorithm: KeyAlgorithm::of_key(&key)?, key })
+    }
+
+    pub fn get_algorithm(&self) -> KeyAlgorithm {
+        self.algorithm
+    }
+
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![self.algorithm.get_tag()];
+        bytes.extend(self.to_der());
+        bytes
+    }
+
+    pub fn to_hex(&self) -> String {
+        hex::encode(self.to_bytes())
+    }
+
+    pub fn to_pem(&self) -> Vec<u8> {
+        self.key.public_key_to_pem().unwrap()
+    }
+
+    pub fn to_der(&self) -> Vec<u8> {
+        self.key.public_key_to_der().unwrap()
+    }
+
+    pub fn verify(&self, data: &[u8], signature: &[u8]) -> bool {
+        verify_with_key(self.algorithm, &self.key, data, signature)
+    }
+}
+
+impl PartialEq for VerifyingKey {
+    fn eq(&self, other: &Self) -> bool {
+        self.to_bytes() == other.to_bytes()
     }
 }
 
-impl ToString for KeySig {
-    fn to_string(&self) -> String {
+impl Eq for VerifyingKey {}
+
+impl Serialize for VerifyingKey {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error> where S: Serializer {
+        serializer.serialize_str(&self.to_hex())
+    }
+}
+
+impl<'de> Deserialize<'de> for VerifyingKey {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error> where D: Deserializer<'de> {
+        let id = String::deserialize(deserializer)?;
+        VerifyingKey::from_hex(&id).map_err(de::Error::custom)
+    }
+}
+
+/**
+    Verifies `signature` over `data` using only a tagged
+    public key, as returned by `KeySig::get_public_key`.
+    Malformed keys, unknown tags and keys that don't match
+    their tag fail verification.
+ */
+pub fn verify_with_public_key(public_key: &[u8], data: &[u8], signature: &[u8]) -> bool {
+    match VerifyingKey::from_bytes(public_key) {
+        Ok(key) => key.verify(data, signature),
+        Err(_) => false,
+    }
+}
+
+fn verify_with_key<T: HasPublic>(
+    algorithm: KeyAlgorithm, key: &PKeyRef<T>, data: &[u8], signature: &[u8]
+) -> bool {
+    let verifier = match algorithm {
+        KeyAlgorithm::Ed25519 => Verifier::new_without_digest(key),
+        _ => Verifier::new(MessageDigest::sha256(), key),
+    };
+    match verifier {
+        Ok(mut verifier) => verifier.verify_oneshot(signature, data).unwrap_or(false),
+        Err(_) => false,
+    }
+}
+
+impl Default for KeySig {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Display for KeySig {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         let public_key = self.keypair.public_key_to_pem().unwrap();
-        let private_key = self.keypair.private_key_to_pem().unwrap();
+        let private_key = self.to_pem();
 
-        format!(
+        write!(
+            f,
             "{}\n{}",
-            hex::encode(private_key),
-            hex::encode(public_key)
+            String::from_utf8(private_key).unwrap(),
+            String::from_utf8(public_key).unwrap()
         )
     }
 }
@@ -72,21 +378,55 @@ impl ToString for KeySig {
 impl Serialize for KeySig {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error> where S: Serializer {
         let pub_key = hex::encode(self.get_public_key());
-        let priv_key = hex::encode(
-            self.keypair.private_key_to_pem().unwrap()
-        );
+        let priv_key = hex::encode(self.to_pem());
 
-        let mut state = serializer.serialize_struct("KeySig", 2)?;
+        let mut state = serializer.serialize_struct("KeySig", 3)?;
+        state.serialize_field("algorithm", &self.algorithm)?;
         state.serialize_field("public key", &pub_key)?;
         state.serialize_field("private key", &priv_key)?;
         state.end()
     }
 }
 
+//  Field layout written by `Serialize for KeySig`
+#[derive(Deserialize)]
+struct KeySigFields {
+    algorithm: KeyAlgorithm,
+    #[serde(rename = "public key")]
+    public_key: String,
+    #[serde(rename = "private key")]
+    private_key: String,
+}
+
+/**
+    Rebuilds the key pair from the stored private key and
+    refuses records whose algorithm or public key disagree with it.
+ */
+impl<'de> Deserialize<'de> for KeySig {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error> where D: Deserializer<'de> {
+        let fields = KeySigFields::deserialize(deserializer)?;
+        let pem = hex::decode(&fields.private_key).map_err(de::Error::custom)?;
+        let keysig = KeySig::from_pem(&pem).map_err(de::Error::custom)?;
+
+        if keysig.algorithm != fields.algorithm {
+            return Err(de::Error::custom(KeyError::UnsupportedAlgorithm));
+        }
+        if hex::encode(keysig.get_public_key()) != fields.public_key {
+            return Err(de::Error::custom(KeyError::MismatchedPublicKey));
+        }
+        Ok(keysig)
+    }
+}
+
 #[cfg(test)]
 mod tests {
-    use serde_test::{assert_tokens, Token};
-    use super::KeySig;
+    use openssl::pkey::PKey;
+    use openssl::rsa::Rsa;
+    use super::{verify_with_public_key, KeyAlgorithm, KeyError, KeySig, VerifyingKey};
+
+    const ALGORITHMS: [KeyAlgorithm; 3] = [
+        KeyAlgorithm::Rsa2048, KeyAlgorithm::Ed25519, KeyAlgorithm::EcdsaP256
+    ];
 
     #[test]
     fn test_to_string() {
@@ -99,22 +439,159 @@ mod tests {
 
     #[test]
     fn test_sign_and_verify() {
-        let keysig = KeySig::new();
+        for algorithm in ALGORITHMS {
+            let keysig = KeySig::generate(algorithm);
 
-        let data = b"Hello World";
-        let signature = keysig.sign(data);
+            let data = b"Hello World";
+            let signature = keysig.sign(data);
 
-        assert!(keysig.verify(data, &signature));
+            assert!(keysig.verify(data, &signature));
+            assert!(!keysig.verify(b"Hello", &signature));
+        }
+    }
+
+    #[test]
+    fn test_verify_with_public_key() {
+        for algorithm in ALGORITHMS {
+            let keysig = KeySig::generate(algorithm);
+            let signature = keysig.sign(b"Hello World");
+            let public_key = keysig.get_public_key();
+
+            assert!(verify_with_public_key(&public_key, b"Hello World", &signature));
+            assert!(!verify_with_public_key(&public_key, b"Hello", &signature));
+            assert!(!verify_with_public_key(b"not a key", b"Hello World", &signature));
+        }
+    }
+
+    //  A key presented under another algorithm's tag is refused
+    #[test]
+    fn test_reject_mismatched_tag() {
+        let keysig = KeySig::generate(KeyAlgorithm::EcdsaP256);
+        let signature = keysig.sign(b"Hello World");
+        let mut public_key = keysig.get_public_key();
+
+        for tag in [KeyAlgorithm::Rsa2048.get_tag(), KeyAlgorithm::Ed25519.get_tag(), 0] {
+            public_key[0] = tag;
+            assert!(!verify_with_public_key(&public_key, b"Hello World", &signature));
+        }
     }
 
     #[test]
     fn test_get_public_key() {
-        let keysig = KeySig::new();
-        let pub_key = keysig.get_public_key();
-        let str_pub_key = String::from_utf8(
-            pub_key).unwrap();
+        for algorithm in ALGORITHMS {
+            let keysig = KeySig::generate(algorithm);
+            let pub_key = keysig.get_public_key();
+
+            assert_eq!(KeyAlgorithm::from_tag(pub_key[0]), Some(algorithm));
+            assert!(pub_key.len() > 1)
+        }
+    }
+
+    #[test]
+    fn test_compact_signatures() {
+        let ed25519 = KeySig::generate(KeyAlgorithm::Ed25519);
+        let p256 = KeySig::generate(KeyAlgorithm::EcdsaP256);
+
+        assert_eq!(ed25519.sign(b"Hello World").len(), 64);
+        assert!(p256.sign(b"Hello World").len() <= 72);
+        assert!(KeySig::new().sign(b"Hello World").len() == 256);
+    }
+
+    #[test]
+    fn test_from_secret() {
+        let secret = [7u8; 32];
+        let keysig = KeySig::from_secret(KeyAlgorithm::EcdsaP256, &secret).unwrap();
+
+        assert_eq!(
+            keysig.get_public_key(),
+            KeySig::from_secret(KeyAlgorithm::EcdsaP256, &secret).unwrap().get_public_key()
+        );
+        assert!(keysig.verify(b"Hello World", &keysig.sign(b"Hello World")));
+        for outside_order in [[0u8; 32], [0xff; 32]] {
+            assert_eq!(
+                KeySig::from_secret(KeyAlgorithm::EcdsaP256, &outside_order).unwrap_err(),
+                KeyError::Malformed
+            );
+        }
+        assert_eq!(
+            KeySig::from_secret(KeyAlgorithm::Rsa2048, &secret).unwrap_err(),
+            KeyError::UnsupportedAlgorithm
+        );
+    }
+
+    #[test]
+    fn test_serde_round_trip() {
+        for algorithm in ALGORITHMS {
+            let keysig = KeySig::generate(algorithm);
+            let json = serde_json::to_string(&keysig).unwrap();
+            let restored: KeySig = serde_json::from_str(&json).unwrap();
 
-        assert!(!str_pub_key.is_empty())
+            assert_eq!(restored.get_algorithm(), algorithm);
+            assert_eq!(restored.get_public_key(), keysig.get_public_key());
+            assert!(keysig.verify(b"Hello World", &restored.sign(b"Hello World")));
+        }
     }
 
+    #[test]
+    fn test_deserialize_rejects_mismatched_public_key() {
+        let keysig = KeySig::generate(KeyAlgorithm::Ed25519);
+        let other = KeySig::generate(KeyAlgorithm::Ed25519);
+        let mut json = serde_json::to_value(&keysig).unwrap();
+        json["public key"] = hex::encode(other.get_public_key()).into();
+
+        assert!(serde_json::from_value::<KeySig>(json).is_err());
+    }
+
+    #[test]
+    fn test_import_export() {
+        for algorithm in ALGORITHMS {
+            let keysig = KeySig::generate(algorithm);
+            let imported = [
+                KeySig::from_pem(&keysig.to_pem()).unwrap(),
+                KeySig::from_der(&keysig.to_der()).unwrap(),
+                KeySig::from_pem(&keysig.to_pkcs8_pem()).unwrap(),
+                KeySig::from_pkcs8_der(&keysig.to_pkcs8_der()).unwrap(),
+            ];
+
+            for restored in imported {
+                assert_eq!(restored.get_algorithm(), algorithm);
+                assert_eq!(restored.get_public_key(), keysig.get_public_key());
+            }
+        }
+    }
+
+    #[test]
+    fn test_import_rejects_unsupported_keys() {
+        let rsa_1024 = PKey::from_rsa(Rsa::generate(1024).unwrap()).unwrap();
+
+        assert_eq!(
+            KeySig::from_pem(&rsa_1024.private_key_to_pem_pkcs8().unwrap()).unwrap_err(),
+            KeyError::UnsupportedAlgorithm
+        );
+        assert_eq!(
+            VerifyingKey::from_pem(&rsa_1024.public_key_to_pem().unwrap()).unwrap_err(),
+            KeyError::UnsupportedAlgorithm
+        );
+        assert_eq!(KeySig::from_pem(b"not a key").unwrap_err(), KeyError::Malformed);
+    }
+
+    //  Verifiers only ever hold the public half
+    #[test]
+    fn test_verifying_key() {
+        for algorithm in ALGORITHMS {
+            let keysig = KeySig::generate(algorithm);
+            let signature = keysig.sign(b"Hello World");
+            let verifying_key = keysig.get_verifying_key();
+
+            assert_eq!(verifying_key.to_bytes(), keysig.get_public_key());
+            assert_eq!(VerifyingKey::from_pem(&verifying_key.to_pem()).unwrap(), verifying_key);
+            assert_eq!(VerifyingKey::from_der(&verifying_key.to_der()).unwrap(), verifying_key);
+            assert!(verifying_key.verify(b"Hello World", &signature));
+            assert!(!verifying_key.verify(b"Hello", &signature));
+
+            let json = serde_json::to_string(&verifying_key).unwrap();
+            assert_eq!(json, format!("\"{}\"", hex::encode(keysig.get_public_key())));
+            assert_eq!(serde_json::from_str::<VerifyingKey>(&json).unwrap(), verifying_key);
+        }
+    }
 }
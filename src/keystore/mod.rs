@@ -0,0 +1,539 @@
+//  Passphrase protected storage for wallet keys
+
+use openssl::{
+    pkcs5::scrypt,
+    rand::rand_bytes,
+    symm::{decrypt_aead, encrypt_aead, Cipher},
+};
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use serde::{Deserialize, Serialize};
+
+use crate::account::Account;
+use crate::hash::{ChainHasher, HashAlgorithm};
+use crate::keysig::{KeyAlgorithm, KeySig};
+
+//  Record layout produced by this release
+pub const KEYSTORE_VERSION: u8 = 1;
+
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 12;
+const TAG_LEN: usize = 16;
+const KEY_LEN: usize = 32;
+
+//  Highest costs accepted from a record, about 4 GiB of memory at most
+const MAX_LOG_N: u8 = 20;
+const MAX_R: u32 = 32;
+const MAX_P: u32 = 16;
+
+/**
+    Cost of deriving the encryption key from a passphrase.
+    Memory used is about 128 * r * 2^log_n bytes.
+ */
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ScryptParams {
+    log_n: u8,
+    r: u32,
+    p: u32,
+}
+
+impl ScryptParams {
+    pub fn new(log_n: u8, r: u32, p: u32) -> Self {
+        ScryptParams { log_n, r, p }
+    }
+
+    //  Records are read from disk, so their costs are bounded before any work is done
+    fn derive_key(&self, passphrase: &str, salt: &[u8]) -> Result<[u8; KEY_LEN], KeystoreError> {
+        if self.log_n > MAX_LOG_N || self.r > MAX_R || self.p > MAX_P {
+            return Err(KeystoreError::Corrupt);
+        }
+        let n = 1u64 << self.log_n;
+        //  Headroom over the 128 * r * (n + p) bytes scrypt needs
+        let maxmem = (n + self.p as u64 + 2).checked_mul(256)
+            .and_then(|bytes| bytes.checked_mul(self.r as u64))
+            .ok_or(KeystoreError::Corrupt)?;
+        let mut key = [0u8; KEY_LEN];
+        scrypt(
+            passphrase.as_bytes(), salt, n, self.r as u64, self.p as u64, maxmem, &mut key
+        ).map_err(|_| KeystoreError::Corrupt)?;
+        Ok(key)
+    }
+}
+
+impl Default for ScryptParams {
+    fn default() -> Self {
+        ScryptParams { log_n: 15, r: 8, p: 1 }
+    }
+}
+
+/**
+    One key as stored on disk. The private key is PKCS#8 DER
+    sealed with AES-256-GCM, the public key is authenticated
+    alongside it so records can't be swapped between files.
+ */
+#[derive(Serialize, Deserialize)]
+struct EncryptedKey {
+    version: u8,
+    algorithm: KeyAlgorithm,
+    //  Account id of the key
+    public_key: String,
+    scrypt: ScryptParams,
+    salt: String,
+    nonce: String,
+    ciphertext: String,
+    tag: String,
+}
+
+impl EncryptedKey {
+    fn seal(keysig: &KeySig, passphrase: &str, params: ScryptParams) -> Result<Self, KeystoreError> {
+        let mut salt = [0u8; SALT_LEN];
+        let mut nonce = [0u8; NONCE_LEN];
+        rand_bytes(&mut salt).map_err(|_| KeystoreError::Corrupt)?;
+        rand_bytes(&mut nonce).map_err(|_| KeystoreError::Corrupt)?;
+
+        let public_key = keysig.get_public_key();
+        let key = params.derive_key(passphrase, &salt)?;
+        let mut tag = [0u8; TAG_LEN];
+        let ciphertext = encrypt_aead(
+            Cipher::aes_256_gcm(), &key, Some(&nonce), &public_key, &keysig.to_pkcs8_der(), &mut tag
+        ).map_err(|_| KeystoreError::Corrupt)?;
+
+        Ok(EncryptedKey {
+            version: KEYSTORE_VERSION,
+            algorithm: keysig.get_algorithm(),
+            public_key: hex::encode(public_key),
+            scrypt: params,
+            salt: hex::encode(salt),
+            nonce: hex::encode(nonce),
+            ciphertext: hex::encode(ciphertext),
+            tag: hex::encode(tag),
+        })
+    }
+
+    fn open(&self, passphrase: &str) -> Result<KeySig, KeystoreError> {
+        let decode = |field: &str| hex::decode(field).map_err(|_| KeystoreError::Corrupt);
+        if self.version != KEYSTORE_VERSION {
+            return Err(KeystoreError::Corrupt);
+        }
+
+        let public_key = decode(&self.public_key)?;
+        let key = self.scrypt.derive_key(passphrase, &decode(&self.salt)?)?;
+        //  A failed tag check means the passphrase is wrong or the record was altered
+        let der = decrypt_aead(
+            Cipher::aes_256_gcm(),
+            &key,
+            Some(&decode(&self.nonce)?),
+            &public_key,
+            &decode(&self.ciphertext)?,
+            &decode(&self.tag)?
+        ).map_err(|_| KeystoreError::WrongPassphrase(self.public_key.clone()))?;
+
+        let keysig = KeySig::from_pkcs8_der(&der).map_err(|_| KeystoreError::Corrupt)?;
+        if keysig.get_public_key() != public_key || keysig.get_algorithm() != self.algorithm {
+            return Err(KeystoreError::Corrupt);
+        }
+        Ok(keysig)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum KeystoreError {
+    Io(io::ErrorKind),
+    NotFound(String),
+    AlreadyStored(String),
+    //  The key is stored but has not been unlocked
+    Locked(String),
+    WrongPassphrase(String),
+    //  An empty passphrase would leave the key effectively in plaintext
+    EmptyPassphrase,
+    //  Refused to write a record that would expose a private key
+    Plaintext,
+    Corrupt,
+}
+
+impl fmt::Display for KeystoreError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            KeystoreError::Io(kind) => write!(f, "keystore i/o failed: {}", kind),
+            KeystoreError::NotFound(id) => write!(f, "key {} is not in the keystore", id),
+            KeystoreError::AlreadyStored(id) => write!(f, "key {} is already stored", id),
+            KeystoreError::Locked(id) => write!(f, "key {} is locked", id),
+            KeystoreError::WrongPassphrase(id) => write!(f, "wrong passphrase for key {}", id),
+            KeystoreError::EmptyPassphrase => write!(f, "passphrase must not be empty"),
+            KeystoreError::Plaintext => write!(f, "refusing to write a plaintext private key"),
+            KeystoreError::Corrupt => write!(f, "keystore record is corrupt"),
+        }
+    }
+}
+
+impl Error for KeystoreError {}
+
+impl From<io::Error> for KeystoreError {
+    fn from(error: io::Error) -> Self {
+        KeystoreError::Io(error.kind())
+    }
+}
+
+/**
+    Directory of encrypted wallet keys, one file per key.
+    Keys are addressed by their hex encoded public key, their
+    key id, and only held in memory between `unlock` and `lock`.
+ */
+pub struct Keystore {
+    dir: PathBuf,
+    scrypt: ScryptParams,
+    unlocked: HashMap<String, KeySig>,
+}
+
+impl Keystore {
+    //  Opens the keystore at `dir`, creating the directory if needed
+    pub fn open(dir: impl AsRef<Path>) -> Result<Self, KeystoreError> {
+        fs::create_dir_all(dir.as_ref())?;
+
+        Ok(Keystore {
+            dir: dir.as_ref().to_path_buf(),
+            scrypt: ScryptParams::default(),
+            unlocked: HashMap::new(),
+        })
+    }
+
+    //  Cost used for keys stored from now on, existing keys keep theirs
+    pub fn with_scrypt(mut self, scrypt: ScryptParams) -> Self {
+        self.scrypt = scrypt;
+        self
+    }
+
+    //  File of key `id`, named by its hash since ids can outgrow file names
+    fn path(&self, id: &str) -> PathBuf {
+        self.dir.join(format!("{}.json", HashAlgorithm::Sha256.hash(id.as_bytes()).to_hex()))
+    }
+
+    fn read(&self, id: &str) -> Result<EncryptedKey, KeystoreError> {
+        let contents = match fs::read(self.path(id)) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == io::ErrorKind::NotFound =>
+                return Err(KeystoreError::NotFound(id.to_string())),
+            Err(e) => return Err(e.into()),
+        };
+        let record: EncryptedKey = serde_json::from_slice(&contents)
+            .map_err(|_| KeystoreError::Corrupt)?;
+
+        if record.public_key != id {
+            return Err(KeystoreError::Corrupt);
+        }
+        Ok(record)
+    }
+
+    fn write(&self, id: &str, record: &EncryptedKey) -> Result<(), KeystoreError> {
+        let contents = serde_json::to_vec_pretty(record).map_err(|_| KeystoreError::Corrupt)?;
+        //  Last line of defence, nothing PEM shaped may reach the disk
+        if String::from_utf8_lossy(&contents).contains("PRIVATE KEY") {
+            return Err(KeystoreError::Plaintext);
+        }
+
+        //  Written aside and renamed so a crash never leaves half a key.
+        //  The temporary name is random so one left by a crash can't block later writes.
+        let path = self.path(id);
+        let mut suffix = [0u8; 8];
+        rand_bytes(&mut suffix).map_err(|_| KeystoreError::Corrupt)?;
+        let tmp = path.with_extension(format!("{}.tmp", hex::encode(suffix)));
+        let mut options = fs::OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::OpenOptionsExt;
+            options.mode(0o600);
+        }
+        let written = options.open(&tmp)
+            .and_then(|mut file| io::Write::write_all(&mut file, &contents))
+            .and_then(|_| fs::rename(&tmp, &path));
+        if written.is_err() {
+            let _ = fs::remove_file(&tmp);
+        }
+        Ok(written?)
+    }
+
+    /**
+        Encrypts `keysig` under `passphrase` and writes it to disk.
+        The key is left locked. Returns its key id.
+     */
+    pub fn store(&mut self, keysig: &KeySig, passphrase: &str) -> Result<String, KeystoreError> {
+        if passphrase.is_empty() {
+            return Err(KeystoreError::EmptyPassphrase);
+        }
+        let id = hex::encode(keysig.get_public_key());
+        if self.path(&id).exists() {
+            return Err(KeystoreError::AlreadyStored(id));
+        }
+
+        self.write(&id, &EncryptedKey::seal(keysig, passphrase, self.scrypt)?)?;
+        Ok(id)
+    }
+
+    //  Stores every key in the account's wallet, the first is the key its address commits to
+    pub fn store_account(
+        &mut self, account: &Account, passphrase: &str
+    ) -> Result<Vec<String>, KeystoreError> {
+        account.get_wallets().iter()
+            .map(|keysig| self.store(keysig, passphrase))
+            .collect()
+    }
+
+    //  Ids of all stored keys, sorted
+    pub fn list(&self) -> Result<Vec<String>, KeystoreError> {
+        let mut ids = Vec::new();
+        for entry in fs::read_dir(&self.dir)? {
+            let path = entry?.path();
+            if path.extension().is_some_and(|ext| ext == "json") {
+                let record: EncryptedKey = serde_json::from_slice(&fs::read(&path)?)
+                    .map_err(|_| KeystoreError::Corrupt)?;
+                ids.push(record.public_key);
+            }
+        }
+        ids.sort();
+        Ok(ids)
+    }
+
+    pub fn is_unlocked(&self, id: &str) -> bool {
+        self.unlocked.contains_key(id)
+    }
+
+    //  Decrypts key `id` and keeps it in memory until it is locked
+    pub fn unlock(&mut self, id: &str, passphrase: &str) -> Result<&KeySig, KeystoreError> {
+        let keysig = self.read(id)?.open(passphrase)?;
+        Ok(self.unlocked.entry(id.to_string()).or_insert(keysig))
+    }
+
+    //  Forgets the decrypted key, the file is untouched
+    pub fn lock(&mut self, id: &str) {
+        self.unlocked.remove(id);
+    }
+
+    pub fn lock_all(&mut self) {
+        self.unlocked.clear();
+    }
+
+    //  Unlocked key `id`
+    pub fn get_keysig(&self, id: &str) -> Result<&KeySig, KeystoreError> {
+        self.unlocked.get(id).ok_or_else(|| KeystoreError::Locked(id.to_string()))
+    }
+
+    /**
+        Account whose wallet holds the keys `ids`, in order.
+        Every key must be unlocked, the balance starts at zero
+        until `Blockchain::update_account` is called.
+     */
+    pub fn load_account(&self, ids: &[String]) -> Result<Account, KeystoreError> {
+        let wallets = ids.iter()
+            .map(|id| self.get_keysig(id).cloned())
+            .collect::<Result<Vec<KeySig>, KeystoreError>>()?;
+        Account::from_wallets(wallets).ok_or(KeystoreError::Corrupt)
+    }
+
+    //  Removes key `id` from disk and memory
+    pub fn delete(&mut self, id: &str) -> Result<(), KeystoreError> {
+        match fs::remove_file(self.path(id)) {
+            Ok(()) => {},
+            Err(e) if e.kind() == io::ErrorKind::NotFound =>
+                return Err(KeystoreError::NotFound(id.to_string())),
+            Err(e) => return Err(e.into()),
+        }
+        self.lock(id);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::PathBuf;
+    use openssl::rand::rand_bytes;
+    use crate::account::Account;
+    use crate::keysig::{KeyAlgorithm, KeySig};
+    use super::{Keystore, KeystoreError, ScryptParams};
+
+    //  Cheap enough for tests, far too weak for real keys
+    const TEST_SCRYPT: ScryptParams = ScryptParams { log_n: 4, r: 8, p: 1 };
+
+    struct TempDir(PathBuf);
+
+    impl TempDir {
+        fn new() -> Self {
+            let mut suffix = [0u8; 8];
+            rand_bytes(&mut suffix).unwrap();
+            TempDir(std::env::temp_dir().join(format!("keystore-{}", hex::encode(suffix))))
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.0);
+        }
+    }
+
+    fn get_keystore(dir: &TempDir) -> Keystore {
+        Keystore::open(&dir.0).unwrap().with_scrypt(TEST_SCRYPT)
+    }
+
+    #[test]
+    fn test_store_and_unlock() {
+        let dir = TempDir::new();
+        let mut keystore = get_keystore(&dir);
+        let keysig = KeySig::generate(KeyAlgorithm::Ed25519);
+        let id = keystore.store(&keysig, "hunter2").unwrap();
+
+        assert_eq!(id, hex::encode(keysig.get_public_key()));
+        assert!(!keystore.is_unlocked(&id));
+        assert_eq!(keystore.get_keysig(&id).unwrap_err(), KeystoreError::Locked(id.clone()));
+
+        //  A fresh handle, as after a restart
+        let mut keystore = get_keystore(&dir);
+        let unlocked = keystore.unlock(&id, "hunter2").unwrap();
+        assert!(keysig.verify(b"Hello World", &unlocked.sign(b"Hello World")));
+
+        keystore.lock(&id);
+        assert!(!keystore.is_unlocked(&id));
+    }
+
+    #[test]
+    fn test_default_scrypt() {
+        let key = ScryptParams::default().derive_key("hunter2", &[0u8; 16]).unwrap();
+
+        assert_ne!(key, ScryptParams::default().derive_key("hunter3", &[0u8; 16]).unwrap());
+    }
+
+    #[test]
+    fn test_wrong_passphrase() {
+        let dir = TempDir::new();
+        let mut keystore = get_keystore(&dir);
+        let id = keystore.store(&KeySig::generate(KeyAlgorithm::EcdsaP256), "hunter2").unwrap();
+
+        assert_eq!(
+            keystore.unlock(&id, "hunter3").unwrap_err(),
+            KeystoreError::WrongPassphrase(id.clone())
+        );
+        assert!(!keystore.is_unlocked(&id));
+    }
+
+    #[test]
+    fn test_list_and_delete() {
+        let dir = TempDir::new();
+        let mut keystore = get_keystore(&dir);
+        let mut ids = vec![
+            keystore.store(&KeySig::generate(KeyAlgorithm::Ed25519), "hunter2").unwrap(),
+            keystore.store(&KeySig::generate(KeyAlgorithm::EcdsaP256), "hunter2").unwrap(),
+        ];
+        ids.sort();
+        assert_eq!(keystore.list().unwrap(), ids);
+
+        keystore.unlock(&ids[0], "hunter2").unwrap();
+        keystore.delete(&ids[0]).unwrap();
+        assert!(!keystore.is_unlocked(&ids[0]));
+        assert_eq!(keystore.list().unwrap(), vec![ids[1].clone()]);
+        assert_eq!(
+            keystore.delete(&ids[0]).unwrap_err(),
+            KeystoreError::NotFound(ids[0].clone())
+        );
+    }
+
+    #[test]
+    fn test_no_plaintext_on_disk() {
+        let dir = TempDir::new();
+        let mut keystore = get_keystore(&dir);
+        let keysig = KeySig::generate(KeyAlgorithm::EcdsaP256);
+        let id = keystore.store(&keysig, "hunter2").unwrap();
+
+        assert_eq!(keystore.store(&keysig, "hunter2").unwrap_err(), KeystoreError::AlreadyStored(id));
+        assert_eq!(
+            keystore.store(&KeySig::generate(KeyAlgorithm::Ed25519), "").unwrap_err(),
+            KeystoreError::EmptyPassphrase
+        );
+
+        let secrets = [keysig.to_pkcs8_der(), keysig.to_der(), keysig.to_pem()];
+        for entry in fs::read_dir(&dir.0).unwrap() {
+            let contents = fs::read(entry.unwrap().path()).unwrap();
+            let text = String::from_utf8_lossy(&contents);
+            assert!(!text.contains("PRIVATE KEY"));
+            for secret in &secrets {
+                assert!(!text.contains(&hex::encode(secret)));
+                assert!(!contents.windows(secret.len()).any(|w| w == secret.as_slice()));
+            }
+        }
+    }
+
+    #[test]
+    fn test_tampered_record() {
+        let dir = TempDir::new();
+        let mut keystore = get_keystore(&dir);
+        let id = keystore.store(&KeySig::generate(KeyAlgorithm::Ed25519), "hunter2").unwrap();
+        let other = KeySig::generate(KeyAlgorithm::Ed25519);
+
+        //  Relabel the record with another public key, keeping the file name
+        let path = keystore.path(&id);
+        let contents = fs::read_to_string(&path).unwrap()
+            .replace(&id, &hex::encode(other.get_public_key()));
+        fs::write(&path, contents).unwrap();
+
+        assert_eq!(keystore.unlock(&id, "hunter2").unwrap_err(), KeystoreError::Corrupt);
+    }
+
+    //  A record asking for huge scrypt costs is refused instead of derived
+    #[test]
+    fn test_excessive_scrypt_params() {
+        let dir = TempDir::new();
+        let mut keystore = get_keystore(&dir);
+        let id = keystore.store(&KeySig::generate(KeyAlgorithm::Ed25519), "hunter2").unwrap();
+        let path = keystore.path(&id);
+        let original = fs::read_to_string(&path).unwrap();
+
+        let costs = [
+            ("\"log_n\": 4", "\"log_n\": 63"),
+            ("\"r\": 8", "\"r\": 4294967295"),
+            ("\"p\": 1", "\"p\": 17"),
+        ];
+        for (field, value) in costs {
+            assert!(original.contains(field));
+            fs::write(&path, original.replace(field, value)).unwrap();
+            assert_eq!(keystore.unlock(&id, "hunter2").unwrap_err(), KeystoreError::Corrupt);
+        }
+    }
+
+    //  A temporary file left by a crash doesn't block storing the key again
+    #[test]
+    fn test_store_over_stale_temp_file() {
+        let dir = TempDir::new();
+        let mut keystore = get_keystore(&dir);
+        let keysig = KeySig::generate(KeyAlgorithm::Ed25519);
+        let id = hex::encode(keysig.get_public_key());
+        fs::write(keystore.path(&id).with_extension("tmp"), b"half a key").unwrap();
+
+        assert_eq!(keystore.store(&keysig, "hunter2").unwrap(), id);
+        assert!(keystore.unlock(&id, "hunter2").is_ok());
+    }
+
+    #[test]
+    fn test_store_and_load_account() {
+        let dir = TempDir::new();
+        let mut keystore = get_keystore(&dir);
+        let mut account = Account::gen_account_with(KeyAlgorithm::Ed25519);
+        account.add_key_pair_to_wallet(KeySig::generate(KeyAlgorithm::EcdsaP256));
+        let ids = keystore.store_account(&account, "hunter2").unwrap();
+
+        assert_eq!(ids[0], hex::encode(account.get_keysig(0).get_public_key()));
+        assert_eq!(keystore.load_account(&ids).err(), Some(KeystoreError::Locked(ids[0].clone())));
+
+        for id in &ids {
+            keystore.unlock(id, "hunter2").unwrap();
+        }
+        let loaded = keystore.load_account(&ids).unwrap();
+        assert_eq!(loaded.get_id(), account.get_id());
+        assert_eq!(
+            loaded.get_keysig(1).get_public_key(),
+            account.get_keysig(1).get_public_key()
+        );
+    }
+}
@@ -0,0 +1,17 @@
+pub mod account;
+pub mod address;
+pub mod amount;
+pub mod blockchain;
+pub mod consensus;
+pub mod drone;
+pub mod encoding;
+pub mod flight;
+pub mod hash;
+pub mod hdwallet;
+pub mod keysig;
+pub mod keystore;
+pub mod multisig;
+pub mod state;
+pub mod transops;
+pub mod utils;
+pub mod waypoint;
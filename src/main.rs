@@ -1,47 +1,47 @@
-use crate::account::Account;
-use crate::blockchain::{Block, Blockchain};
-use crate::transops::{get_nonce, Operation, Transaction};
-
-mod account;
-mod blockchain;
-mod hash;
-mod keysig;
-mod transops;
-mod utils;
+use baby_blockchain::account::Account;
+use baby_blockchain::amount::Amount;
+use baby_blockchain::blockchain::Blockchain;
+use baby_blockchain::keysig::KeyAlgorithm;
+use baby_blockchain::transops::{Operation, Transaction};
 
 fn main() {
     let mut bc = Blockchain::init();
     //  Genesis block to create
-    let mut gen_block = String::from("");
-    let i = 0;
-    for x in bc.get_history().keys() {
-        gen_block = x.to_string();
-        if i == 0 { break; }
-    }
+    let gen_block = bc.tip().get_id();
     bc.print_blockchain();
 
     //  Create account
     let mut account1 = Account::gen_account();
-    let mut account2 = Account::gen_account();
-    bc.get_token_from_faucet(&mut account1, 10);
-    bc.get_token_from_faucet(&mut account2, 10);
+    //  Drone transponders use compact Ed25519 keys
+    let mut account2 = Account::gen_account_with(KeyAlgorithm::Ed25519);
+    bc.get_token_from_faucet(&mut account1, Amount::new(10))
+        .expect("faucet has enough tokens");
+    bc.get_token_from_faucet(&mut account2, Amount::new(10))
+        .expect("faucet has enough tokens");
     print_separator();
     bc.print_blockchain();
 
     // Simulate sending drones from account1 to account2
+    let chain_id = bc.get_config().get_chain_id();
     let operation = Operation::create_operation(
-        account2.clone(), account1.clone(), 4
+        &account2, &account1, Amount::new(4), bc.get_next_nonce(&account1.get_id()), chain_id
     );
     let op2 = Operation::create_operation(
-        account1.clone(), account2.clone(), 6
+        &account1, &account2, Amount::new(6), bc.get_next_nonce(&account2.get_id()), chain_id
     );
-    let transaction = Transaction::create_transaction(
-        vec![operation, op2], get_nonce());
-
-    let block = Block::create_block(
-        vec![transaction], gen_block
+    let mut transaction = Transaction::create_transaction(
+        vec![operation, op2]);
+    //  Both senders co-sign the transaction
+    account1.sign_transaction(&mut transaction, chain_id);
+    account2.sign_transaction(&mut transaction, chain_id);
+
+    let block = bc.build_block(
+        vec![transaction], &gen_block
     );
-    bc.validate_block(block);
+    match block.and_then(|block| bc.validate_block(block)) {
+        Ok(id) => println!("Block {} accepted", id),
+        Err(e) => println!("Block rejected: {}", e),
+    }
 
     //  If it's valid it should be added to the chain
     bc.update_account(&mut account1);
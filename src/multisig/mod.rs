@@ -0,0 +1,227 @@
+//  M-of-N spending policies for shared accounts
+
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt;
+use serde::Serialize;
+
+use crate::address::Address;
+use crate::encoding::Encoder;
+use crate::keysig::VerifyingKey;
+
+/**
+    Account spending policy: any `threshold` of `keys` must sign.
+    Keys are hex encoded tagged public keys, re-encoded from the
+    parsed key and kept sorted and without duplicates so equal
+    policies encode the same and one key can't count twice.
+ */
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct MultisigPolicy {
+    threshold: u32,
+    keys: Vec<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum PolicyError {
+    //  Threshold is zero or above the number of keys
+    BadThreshold { threshold: u32, keys: usize },
+    MalformedKey(String),
+}
+
+impl fmt::Display for PolicyError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PolicyError::BadThreshold { threshold, keys } =>
+                write!(f, "threshold {} is not between 1 and {} keys", threshold, keys),
+            PolicyError::MalformedKey(key) => write!(f, "key {} is malformed", key),
+        }
+    }
+}
+
+impl Error for PolicyError {}
+
+/**
+    What an account's signatures are checked against: the key
+    its address commits to, the key it rotated to, or a
+    multisig policy once it has one.
+ */
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum SigningAuthority {
+    //  Account that never rotated, any key hashing to the address signs for it
+    Address(String),
+    Key(String),
+    Multisig(MultisigPolicy),
+}
+
+impl MultisigPolicy {
+    pub fn new(threshold: u32, keys: Vec<String>) -> Result<Self, PolicyError> {
+        //  Hex case and trailing DER bytes would otherwise spell one key many ways
+        let mut keys = keys.into_iter()
+            .map(|key| match VerifyingKey::from_hex(&key) {
+                Ok(parsed) => Ok(parsed.to_hex()),
+                Err(_) => Err(PolicyError::MalformedKey(key)),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        keys.sort();
+        keys.dedup();
+        if threshold == 0 || threshold as usize > keys.len() {
+            return Err(PolicyError::BadThreshold { threshold, keys: keys.len() });
+        }
+
+        Ok(MultisigPolicy { threshold, keys })
+    }
+
+    pub fn get_threshold(&self) -> u32 {
+        self.threshold
+    }
+
+    pub fn get_keys(&self) -> &[String] {
+        &self.keys
+    }
+
+    /**
+        Whether at least `threshold` distinct policy keys have a
+        valid signature over `payload` in `signatures`, which maps
+        keys to signatures. Signatures by other keys don't count.
+     */
+    pub fn is_satisfied(&self, payload: &[u8], signatures: &BTreeMap<String, Vec<u8>>) -> bool {
+        let valid = self.keys.iter()
+            .filter(|key| match (signatures.get(*key), VerifyingKey::from_hex(key)) {
+                (Some(signature), Ok(key)) => key.verify(payload, signature),
+                _ => false,
+            })
+            .count();
+        valid >= self.threshold as usize
+    }
+
+    //  Part of the encoding of operations and the state root
+    pub fn encode_fields(&self, encoder: &mut Encoder) {
+        encoder.put_u32(self.threshold).put_u32(self.keys.len() as u32);
+        for key in &self.keys {
+            encoder.put_str(key);
+        }
+    }
+}
+
+impl SigningAuthority {
+    /**
+        Checks a signer's approval of `payload`: `signature` under
+        a single key, or enough of `cosignatures` under a policy.
+        `public_key` is the hex encoded key the signer presents,
+        only looked at while the address stands for the key.
+     */
+    pub fn verify(
+        &self,
+        payload: &[u8],
+        public_key: &str,
+        signature: &[u8],
+        cosignatures: &BTreeMap<String, Vec<u8>>
+    ) -> bool {
+        let verify_with = |key: &str| VerifyingKey::from_hex(key)
+            .map(|key| key.verify(payload, signature))
+            .unwrap_or(false);
+        match self {
+            SigningAuthority::Address(address) =>
+                Address::is_address_of(address, public_key) && verify_with(public_key),
+            SigningAuthority::Key(key) => verify_with(key),
+            SigningAuthority::Multisig(policy) => policy.is_satisfied(payload, cosignatures),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use crate::keysig::{KeyAlgorithm, KeySig};
+    use super::{MultisigPolicy, PolicyError, SigningAuthority};
+
+    fn get_keys(n: usize) -> Vec<KeySig> {
+        (0..n).map(|_| KeySig::generate(KeyAlgorithm::Ed25519)).collect()
+    }
+
+    fn get_ids(keys: &[KeySig]) -> Vec<String> {
+        keys.iter().map(|key| hex::encode(key.get_public_key())).collect()
+    }
+
+    #[test]
+    fn test_new_policy() {
+        let ids = get_ids(&get_keys(3));
+        let mut reversed = ids.clone();
+        reversed.reverse();
+
+        let policy = MultisigPolicy::new(2, reversed).unwrap();
+        let mut sorted = ids.clone();
+        sorted.sort();
+        assert_eq!(policy.get_keys(), sorted.as_slice());
+
+        let duplicated = vec![ids[0].clone(), ids[0].clone()];
+        assert_eq!(
+            MultisigPolicy::new(2, duplicated),
+            Err(PolicyError::BadThreshold { threshold: 2, keys: 1 })
+        );
+        assert_eq!(
+            MultisigPolicy::new(0, ids.clone()),
+            Err(PolicyError::BadThreshold { threshold: 0, keys: 3 })
+        );
+        assert_eq!(
+            MultisigPolicy::new(1, vec!["not a key".to_string()]),
+            Err(PolicyError::MalformedKey("not a key".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_key_spelled_twice() {
+        let keys = get_keys(2);
+        let ids = get_ids(&keys);
+
+        let shouted = vec![ids[0].clone(), ids[0].to_uppercase()];
+        assert_eq!(
+            MultisigPolicy::new(2, shouted),
+            Err(PolicyError::BadThreshold { threshold: 2, keys: 1 })
+        );
+
+        //  Keys are stored in canonical form, so cosigners use that
+        let policy = MultisigPolicy::new(1, vec![ids[1].to_uppercase()]).unwrap();
+        assert_eq!(policy.get_keys(), &ids[1..]);
+        let mut signatures = BTreeMap::new();
+        signatures.insert(ids[1].clone(), keys[1].sign(b"payout"));
+        assert!(policy.is_satisfied(b"payout", &signatures));
+
+        //  DER parsing ignores trailing bytes, it's still the same key
+        let padded = format!("{}00", ids[0]);
+        assert_eq!(
+            MultisigPolicy::new(2, vec![ids[0].clone(), padded]),
+            Err(PolicyError::BadThreshold { threshold: 2, keys: 1 })
+        );
+    }
+
+    #[test]
+    fn test_threshold() {
+        let keys = get_keys(3);
+        let ids = get_ids(&keys);
+        let authority = SigningAuthority::Multisig(MultisigPolicy::new(2, ids.clone()).unwrap());
+        let mut signatures = BTreeMap::new();
+
+        signatures.insert(ids[0].clone(), keys[0].sign(b"payout"));
+        assert!(!authority.verify(b"payout", "", &[], &signatures));
+
+        //  A second signature from the same key doesn't count twice
+        signatures.insert(ids[1].clone(), keys[0].sign(b"payout"));
+        assert!(!authority.verify(b"payout", "", &[], &signatures));
+
+        signatures.insert(ids[1].clone(), keys[1].sign(b"payout"));
+        assert!(authority.verify(b"payout", "", &[], &signatures));
+        assert!(!authority.verify(b"other payout", "", &[], &signatures));
+    }
+
+    #[test]
+    fn test_outside_keys_dont_count() {
+        let keys = get_keys(3);
+        let ids = get_ids(&keys);
+        let policy = MultisigPolicy::new(1, ids[..2].to_vec()).unwrap();
+        let mut signatures = BTreeMap::new();
+
+        signatures.insert(ids[2].clone(), keys[2].sign(b"payout"));
+        assert!(!policy.is_satisfied(b"payout", &signatures));
+    }
+}
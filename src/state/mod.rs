@@ -0,0 +1,1489 @@
+//  Balances, nonces and recorded transactions the chain is built on
+
+use std::collections::HashMap;
+use serde::Serialize;
+
+use crate::address::Address;
+use crate::amount::Amount;
+use crate::blockchain::BlockValidationError;
+use crate::drone::{Drone, DroneError, DroneStatus};
+use crate::encoding::Encoder;
+use crate::flight::{FlightError, FlightLog, Leg};
+use crate::hash::{ChainHasher, Hash32, HashAlgorithm};
+use crate::keysig::VerifyingKey;
+use crate::multisig::{MultisigPolicy, SigningAuthority};
+use crate::transops::{Operation, OperationKind, Transaction};
+use crate::waypoint::{BoundingBox, Waypoint, WaypointError, WaypointStatus};
+
+//  Encoding tag of the balances and nonces hashed into a state root
+const STATE_ROOT_TAG: u8 = 5;
+
+/**
+    Committed state at the tip of the canonical chain.
+    It is only changed by committing a `StateOverlay`,
+    so a failed block or reorg never leaves it half applied.
+ */
+#[derive(Serialize, Clone, Default)]
+pub struct State {
+    balances: HashMap<String, Amount>,
+    //  Nonce each account's next operation must carry
+    nonces: HashMap<String, u64>,
+    //  Signing key of each account that rotated or revoked the key its address commits to
+    signing_keys: HashMap<String, String>,
+    revocations: HashMap<String, KeyRevocation>,
+    //  Multisig policy of each account that set one
+    policies: HashMap<String, MultisigPolicy>,
+    //  Journeys and position reports of each drone that made any
+    flights: HashMap<String, FlightLog>,
+    //  Waypoint registry by id
+    waypoints: HashMap<String, Waypoint>,
+    //  Drone registry by id
+    drones: HashMap<String, Drone>,
+    transactions: HashMap<Hash32, Transaction>,
+}
+
+/**
+    Record of a retired key. Signatures made with it are
+    refused from the operation that revoked it onwards.
+ */
+#[derive(Serialize, Clone, Debug, PartialEq, Eq)]
+pub struct KeyRevocation {
+    account: String,
+    //  Height of the block that revoked the key
+    height: u64,
+    //  Key that took over, `None` if the account was left without one
+    replaced_by: Option<String>,
+}
+
+/**
+    Pending changes on top of a `State`. Reads fall through to the
+    base for anything not changed. Every write is journaled so
+    changes can be rolled back to a checkpoint, which is how
+    transactions and blocks are applied all or nothing.
+ */
+pub struct StateOverlay<'a> {
+    base: &'a State,
+    balances: HashMap<String, Amount>,
+    nonces: HashMap<String, u64>,
+    signing_keys: HashMap<String, String>,
+    //  `None` marks a revocation, policy, registration or transaction removed by a revert
+    revocations: HashMap<String, Option<KeyRevocation>>,
+    policies: HashMap<String, Option<MultisigPolicy>>,
+    flights: HashMap<String, FlightLog>,
+    waypoints: HashMap<String, Option<Waypoint>>,
+    drones: HashMap<String, Option<Drone>>,
+    transactions: HashMap<Hash32, Option<Transaction>>,
+    journal: Vec<Change>,
+    //  Height of the block being applied, recorded in revocations
+    height: u64,
+    //  Time of the block being applied, flights can't be reported ahead of it
+    timestamp: u64,
+}
+
+/**
+    Changes taken out of an overlay, ready to commit.
+ */
+#[derive(Default)]
+pub struct StateChanges {
+    balances: HashMap<String, Amount>,
+    nonces: HashMap<String, u64>,
+    signing_keys: HashMap<String, String>,
+    revocations: HashMap<String, Option<KeyRevocation>>,
+    policies: HashMap<String, Option<MultisigPolicy>>,
+    flights: HashMap<String, FlightLog>,
+    waypoints: HashMap<String, Option<Waypoint>>,
+    drones: HashMap<String, Option<Drone>>,
+    transactions: HashMap<Hash32, Option<Transaction>>,
+}
+
+//  Overlay entry as it was before a write, `None` if there was none
+enum Change {
+    Balance(String, Option<Amount>),
+    Nonce(String, Option<u64>),
+    SigningKey(String, Option<String>),
+    Revocation(String, Option<Option<KeyRevocation>>),
+    Policy(String, Option<Option<MultisigPolicy>>),
+    Flight(String, Option<FlightLog>),
+    Waypoint(String, Option<Option<Waypoint>>),
+    Drone(String, Option<Option<Drone>>),
+    Transaction(Hash32, Option<Option<Transaction>>),
+}
+
+impl KeyRevocation {
+    pub fn get_account(&self) -> &str {
+        &self.account
+    }
+
+    pub fn get_height(&self) -> u64 {
+        self.height
+    }
+
+    pub fn get_replaced_by(&self) -> Option<&str> {
+        self.replaced_by.as_deref()
+    }
+}
+
+impl State {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn get_balance(&self, account: &str) -> Amount {
+        self.balances.get(account).copied().unwrap_or_default()
+    }
+
+    pub fn get_nonce(&self, account: &str) -> u64 {
+        self.nonces.get(account).copied().unwrap_or(0)
+    }
+
+    pub fn get_revocation(&self, key: &str) -> Option<&KeyRevocation> {
+        self.revocations.get(key)
+    }
+
+    pub fn get_multisig_policy(&self, account: &str) -> Option<&MultisigPolicy> {
+        self.policies.get(account)
+    }
+
+    /**
+        What the account's signatures are checked against: its
+        multisig policy if it set one, otherwise the key it rotated
+        to, otherwise any key its address commits to. `None` once
+        its signing key is revoked.
+     */
+    pub fn get_authority(&self, account: &str) -> Option<SigningAuthority> {
+        if let Some(policy) = self.get_multisig_policy(account) {
+            return Some(SigningAuthority::Multisig(policy.clone()));
+        }
+        match self.signing_keys.get(account) {
+            Some(key) if self.revocations.contains_key(key) => None,
+            Some(key) if !Address::is_address_of(account, key) =>
+                Some(SigningAuthority::Key(key.clone())),
+            _ => Some(SigningAuthority::Address(account.to_string())),
+        }
+    }
+
+    pub fn get_flight_log(&self, drone: &str) -> Option<&FlightLog> {
+        self.flights.get(drone)
+    }
+
+    pub fn get_waypoint(&self, id: &str) -> Option<&Waypoint> {
+        self.waypoints.get(id)
+    }
+
+    //  Waypoints run by `operator`, by id
+    pub fn get_waypoints_by_operator(&self, operator: &str) -> Vec<&Waypoint> {
+        self.find_waypoints(|waypoint| waypoint.get_operator() == operator)
+    }
+
+    //  Waypoints inside `area`, by id
+    pub fn get_waypoints_in(&self, area: &BoundingBox) -> Vec<&Waypoint> {
+        self.find_waypoints(|waypoint| area.contains(waypoint.get_lat(), waypoint.get_lon()))
+    }
+
+    fn find_waypoints<F: Fn(&Waypoint) -> bool>(&self, filter: F) -> Vec<&Waypoint> {
+        let mut waypoints: Vec<&Waypoint> = self.waypoints.values()
+            .filter(|waypoint| filter(waypoint))
+            .collect();
+        waypoints.sort_by_key(|waypoint| waypoint.get_id());
+        waypoints
+    }
+
+    pub fn get_drone(&self, id: &str) -> Option<&Drone> {
+        self.drones.get(id)
+    }
+
+    //  Drones `owner` has, decommissioned ones included, by id
+    pub fn get_drones_by_owner(&self, owner: &str) -> Vec<&Drone> {
+        let mut drones: Vec<&Drone> = self.drones.values()
+            .filter(|drone| drone.get_owner() == owner)
+            .collect();
+        drones.sort_by_key(|drone| drone.get_id());
+        drones
+    }
+
+    pub fn get_transaction(&self, id: &Hash32) -> Option<&Transaction> {
+        self.transactions.get(id)
+    }
+
+    pub fn contains_transaction(&self, id: &Hash32) -> bool {
+        self.transactions.contains_key(id)
+    }
+
+    pub fn get_balances(&self) -> &HashMap<String, Amount> {
+        &self.balances
+    }
+
+    pub fn get_transactions(&self) -> &HashMap<Hash32, Transaction> {
+        &self.transactions
+    }
+
+    //  Sets a balance outside of any block, used by the faucet
+    pub(crate) fn set_balance(&mut self, account: &str, balance: Amount) {
+        self.balances.insert(account.to_string(), balance);
+    }
+
+    pub fn overlay(&self) -> StateOverlay<'_> {
+        StateOverlay {
+            base: self,
+            balances: HashMap::new(),
+            nonces: HashMap::new(),
+            signing_keys: HashMap::new(),
+            revocations: HashMap::new(),
+            policies: HashMap::new(),
+            flights: HashMap::new(),
+            waypoints: HashMap::new(),
+            drones: HashMap::new(),
+            transactions: HashMap::new(),
+            journal: Vec::new(),
+            height: 0,
+            timestamp: 0,
+        }
+    }
+
+    pub fn commit(&mut self, changes: StateChanges) {
+        self.balances.extend(changes.balances);
+        self.nonces.extend(changes.nonces);
+        self.signing_keys.extend(changes.signing_keys);
+        for (key, revocation) in changes.revocations {
+            match revocation {
+                Some(revocation) => self.revocations.insert(key, revocation),
+                None => self.revocations.remove(&key),
+            };
+        }
+        for (account, policy) in changes.policies {
+            match policy {
+                Some(policy) => self.policies.insert(account, policy),
+                None => self.policies.remove(&account),
+            };
+        }
+        for (drone, log) in changes.flights {
+            match log.is_empty() {
+                true => self.flights.remove(&drone),
+                false => self.flights.insert(drone, log),
+            };
+        }
+        for (id, waypoint) in changes.waypoints {
+            match waypoint {
+                Some(waypoint) => self.waypoints.insert(id, waypoint),
+                None => self.waypoints.remove(&id),
+            };
+        }
+        for (id, drone) in changes.drones {
+            match drone {
+                Some(drone) => self.drones.insert(id, drone),
+                None => self.drones.remove(&id),
+            };
+        }
+        for (id, transaction) in changes.transactions {
+            match transaction {
+                Some(transaction) => self.transactions.insert(id, transaction),
+                None => self.transactions.remove(&id),
+            };
+        }
+    }
+
+    /**
+        Hash of every account's balance and next nonce, sorted by
+        account so the order is stable. Accounts with neither are
+        left out, so a missing entry and a zero one hash the same.
+        Rotated signing keys and revocations follow, sorted the same
+        way, skipping accounts whose signing key is back to the one
+        their address commits to, then multisig policies by account,
+        the flight logs of drones, leaving out empty ones, and
+        registered waypoints and drones by id.
+     */
+    pub fn root(&self, algorithm: HashAlgorithm) -> Hash32 {
+        let mut accounts: Vec<&String> = self.balances.keys().chain(self.nonces.keys()).collect();
+        accounts.sort();
+        accounts.dedup();
+
+        let entries: Vec<(&String, Amount, u64)> = accounts.into_iter()
+            .map(|a| (
+                a,
+                self.balances.get(a).copied().unwrap_or_default(),
+                self.nonces.get(a).copied().unwrap_or(0)
+            ))
+            .filter(|(_, balance, nonce)| !balance.is_zero() || *nonce != 0)
+            .collect();
+
+        let mut encoder = Encoder::versioned(STATE_ROOT_TAG);
+        encoder.put_u32(entries.len() as u32);
+        for (account, balance, nonce) in entries {
+            encoder.put_str(account).put_u64(balance.get_tokens()).put_u64(nonce);
+        }
+
+        let mut rotated: Vec<(&String, &String)> = self.signing_keys.iter()
+            .filter(|(account, key)| !Address::is_address_of(account, key))
+            .collect();
+        rotated.sort();
+        encoder.put_u32(rotated.len() as u32);
+        for (account, key) in rotated {
+            encoder.put_str(account).put_str(key);
+        }
+
+        let mut revoked: Vec<(&String, &KeyRevocation)> = self.revocations.iter().collect();
+        revoked.sort_by_key(|(key, _)| *key);
+        encoder.put_u32(revoked.len() as u32);
+        for (key, revocation) in revoked {
+            encoder
+                .put_str(key)
+                .put_str(&revocation.account)
+                .put_u64(revocation.height)
+                .put_str(revocation.replaced_by.as_deref().unwrap_or(""));
+        }
+
+        let mut policies: Vec<(&String, &MultisigPolicy)> = self.policies.iter().collect();
+        policies.sort_by_key(|(account, _)| *account);
+        encoder.put_u32(policies.len() as u32);
+        for (account, policy) in policies {
+            encoder.put_str(account);
+            policy.encode_fields(&mut encoder);
+        }
+
+        let mut flights: Vec<(&String, &FlightLog)> = self.flights.iter()
+            .filter(|(_, log)| !log.is_empty())
+            .collect();
+        flights.sort_by_key(|(drone, _)| *drone);
+        encoder.put_u32(flights.len() as u32);
+        for (drone, log) in flights {
+            encoder.put_str(drone);
+            log.encode_fields(&mut encoder);
+        }
+
+        let mut waypoints: Vec<&Waypoint> = self.waypoints.values().collect();
+        waypoints.sort_by_key(|waypoint| waypoint.get_id());
+        encoder.put_u32(waypoints.len() as u32);
+        for waypoint in waypoints {
+            encoder.put_str(waypoint.get_id()).put_str(waypoint.get_operator());
+            waypoint.encode_fields(&mut encoder);
+        }
+
+        let mut drones: Vec<&Drone> = self.drones.values().collect();
+        drones.sort_by_key(|drone| drone.get_id());
+        encoder.put_u32(drones.len() as u32);
+        for drone in drones {
+            drone.encode_fields(&mut encoder);
+        }
+        algorithm.hash(&encoder.finish())
+    }
+}
+
+impl<'a> StateOverlay<'a> {
+    pub fn get_balance(&self, account: &str) -> Amount {
+        self.balances.get(account).copied()
+            .unwrap_or_else(|| self.base.get_balance(account))
+    }
+
+    pub fn get_nonce(&self, account: &str) -> u64 {
+        self.nonces.get(account).copied()
+            .unwrap_or_else(|| self.base.get_nonce(account))
+    }
+
+    fn get_revocation(&self, key: &str) -> Option<&KeyRevocation> {
+        match self.revocations.get(key) {
+            Some(revocation) => revocation.as_ref(),
+            None => self.base.get_revocation(key),
+        }
+    }
+
+    //  Key recorded by a rotation or revocation, see `State::get_authority`
+    fn get_signing_key(&self, account: &str) -> Option<&String> {
+        self.signing_keys.get(account)
+            .or_else(|| self.base.signing_keys.get(account))
+    }
+
+    pub fn get_multisig_policy(&self, account: &str) -> Option<&MultisigPolicy> {
+        match self.policies.get(account) {
+            Some(policy) => policy.as_ref(),
+            None => self.base.get_multisig_policy(account),
+        }
+    }
+
+    //  See `State::get_authority`
+    pub fn get_authority(&self, account: &str) -> Option<SigningAuthority> {
+        if let Some(policy) = self.get_multisig_policy(account) {
+            return Some(SigningAuthority::Multisig(policy.clone()));
+        }
+        match self.get_signing_key(account) {
+            Some(key) if self.get_revocation(key).is_some() => None,
+            Some(key) if !Address::is_address_of(account, key) =>
+                Some(SigningAuthority::Key(key.clone())),
+            _ => Some(SigningAuthority::Address(account.to_string())),
+        }
+    }
+
+    pub fn get_flight_log(&self, drone: &str) -> Option<&FlightLog> {
+        match self.flights.get(drone) {
+            Some(log) => Some(log),
+            None => self.base.get_flight_log(drone),
+        }
+    }
+
+    pub fn get_waypoint(&self, id: &str) -> Option<&Waypoint> {
+        match self.waypoints.get(id) {
+            Some(waypoint) => waypoint.as_ref(),
+            None => self.base.get_waypoint(id),
+        }
+    }
+
+    pub fn get_drone(&self, id: &str) -> Option<&Drone> {
+        match self.drones.get(id) {
+            Some(drone) => drone.as_ref(),
+            None => self.base.get_drone(id),
+        }
+    }
+
+    //  Whether a registered drone has `serial`, serials never change once registered
+    fn is_serial_taken(&self, serial: &str) -> bool {
+        self.drones.values().flatten().any(|drone| drone.get_serial() == serial)
+            || self.base.drones.values().any(|drone| {
+                drone.get_serial() == serial && !self.drones.contains_key(drone.get_id())
+            })
+    }
+
+    //  Height recorded in revocations made from now on
+    pub fn set_height(&mut self, height: u64) {
+        self.height = height;
+    }
+
+    //  Time of the block flights are recorded in from now on
+    pub fn set_timestamp(&mut self, timestamp: u64) {
+        self.timestamp = timestamp;
+    }
+
+    pub fn contains_transaction(&self, id: &Hash32) -> bool {
+        match self.transactions.get(id) {
+            Some(transaction) => transaction.is_some(),
+            None => self.base.contains_transaction(id),
+        }
+    }
+
+    fn set_balance(&mut self, account: &str, balance: Amount) {
+        let previous = self.balances.insert(account.to_string(), balance);
+        self.journal.push(Change::Balance(account.to_string(), previous));
+    }
+
+    fn set_nonce(&mut self, account: &str, nonce: u64) {
+        let previous = self.nonces.insert(account.to_string(), nonce);
+        self.journal.push(Change::Nonce(account.to_string(), previous));
+    }
+
+    fn set_signing_key(&mut self, account: &str, key: &str) {
+        let previous = self.signing_keys.insert(account.to_string(), key.to_string());
+        self.journal.push(Change::SigningKey(account.to_string(), previous));
+    }
+
+    fn set_revocation(&mut self, key: &str, revocation: Option<KeyRevocation>) {
+        let previous = self.revocations.insert(key.to_string(), revocation);
+        self.journal.push(Change::Revocation(key.to_string(), previous));
+    }
+
+    fn set_policy(&mut self, account: &str, policy: Option<MultisigPolicy>) {
+        let previous = self.policies.insert(account.to_string(), policy);
+        self.journal.push(Change::Policy(account.to_string(), previous));
+    }
+
+    fn set_flight_log(&mut self, drone: &str, log: FlightLog) {
+        let previous = self.flights.insert(drone.to_string(), log);
+        self.journal.push(Change::Flight(drone.to_string(), previous));
+    }
+
+    fn set_waypoint(&mut self, id: &str, waypoint: Option<Waypoint>) {
+        let previous = self.waypoints.insert(id.to_string(), waypoint);
+        self.journal.push(Change::Waypoint(id.to_string(), previous));
+    }
+
+    fn set_drone(&mut self, id: &str, drone: Option<Drone>) {
+        let previous = self.drones.insert(id.to_string(), drone);
+        self.journal.push(Change::Drone(id.to_string(), previous));
+    }
+
+    fn set_transaction(&mut self, id: Hash32, transaction: Option<Transaction>) {
+        let previous = self.transactions.insert(id, transaction);
+        self.journal.push(Change::Transaction(id, previous));
+    }
+
+    //  Position to pass to `rollback` to undo everything written after it
+    pub fn checkpoint(&self) -> usize {
+        self.journal.len()
+    }
+
+    pub fn rollback(&mut self, checkpoint: usize) {
+        while self.journal.len() > checkpoint {
+            match self.journal.pop().unwrap() {
+                Change::Balance(account, previous) => restore(&mut self.balances, account, previous),
+                Change::Nonce(account, previous) => restore(&mut self.nonces, account, previous),
+                Change::SigningKey(account, previous) =>
+                    restore(&mut self.signing_keys, account, previous),
+                Change::Revocation(key, previous) => restore(&mut self.revocations, key, previous),
+                Change::Policy(account, previous) => restore(&mut self.policies, account, previous),
+                Change::Flight(drone, previous) => restore(&mut self.flights, drone, previous),
+                Change::Waypoint(id, previous) => restore(&mut self.waypoints, id, previous),
+                Change::Drone(id, previous) => restore(&mut self.drones, id, previous),
+                Change::Transaction(id, previous) => restore(&mut self.transactions, id, previous),
+            }
+        }
+    }
+
+    /**
+        Checks the operation carries its sender's next nonce, then
+        carries it out. Accounts without a balance count as empty.
+        Nothing changes on error.
+     */
+    pub fn apply_operation(&mut self, operation: &Operation) -> Result<(), BlockValidationError> {
+        let sender = operation.get_sender();
+
+        let expected = self.get_nonce(&sender);
+        if operation.get_nonce() != expected {
+            return Err(BlockValidationError::BadNonce {
+                account: sender, expected, found: operation.get_nonce()
+            });
+        }
+
+        match operation.get_kind() {
+            OperationKind::Transfer { receiver, amount } =>
+                self.apply_transfer(&sender, receiver, *amount)?,
+            OperationKind::RotateKey { key, new_key } =>
+                self.apply_key_change(&sender, key, Some(new_key))?,
+            OperationKind::RevokeKey { key } =>
+                self.apply_key_change(&sender, key, None)?,
+            OperationKind::SetMultisig { previous, policy } =>
+                self.apply_policy_change(&sender, previous.as_ref(), policy)?,
+            kind @ OperationKind::JourneyLeg { drone, .. } => {
+                let leg = kind.get_leg().unwrap();
+                let route = leg.check().and_then(|_| self.check_route(&leg));
+                self.apply_flight(&sender, drone, |log, now| {
+                    route.and_then(|_| log.add_leg(leg, now))
+                })?
+            }
+            kind @ OperationKind::PositionReport { drone, .. } => {
+                let position = kind.get_position().unwrap();
+                self.apply_flight(&sender, drone, |log, now| log.add_position(position, now))?
+            }
+            kind @ OperationKind::RegisterWaypoint { .. } =>
+                self.apply_waypoint(&sender, kind.get_waypoint().unwrap())?,
+            kind @ OperationKind::RegisterDrone { .. } =>
+                self.apply_drone_registration(&sender, kind.get_drone().unwrap())?,
+            OperationKind::TransferDroneOwnership { drone: id, new_owner } => {
+                let mut drone = self.get_owned_drone(&sender, id)?;
+                if !Address::is_valid(new_owner) {
+                    return Err(BlockValidationError::InvalidDrone {
+                        drone: id.clone(), error: DroneError::InvalidOwner(new_owner.clone())
+                    });
+                }
+                drone.set_owner(new_owner.clone());
+                self.set_drone(id, Some(drone));
+            }
+            OperationKind::DecommissionDrone { drone: id } => {
+                let mut drone = self.get_owned_drone(&sender, id)?;
+                drone.set_status(DroneStatus::Decommissioned);
+                self.set_drone(id, Some(drone));
+            }
+        }
+
+        self.set_nonce(&sender, expected + 1);
+        Ok(())
+    }
+
+    //  Moves `amount` from sender to receiver
+    fn apply_transfer(
+        &mut self, sender: &str, receiver: &str, amount: Amount
+    ) -> Result<(), BlockValidationError> {
+        let balance = self.get_balance(sender);
+        let remaining = balance.checked_sub(amount).ok_or(
+            BlockValidationError::InsufficientBalance {
+                account: sender.to_string(), balance, amount
+            }
+        )?;
+        if sender != receiver {
+            let balance = self.get_balance(receiver);
+            let received = balance.checked_add(amount).ok_or(
+                BlockValidationError::Overflow { account: receiver.to_string() }
+            )?;
+            self.set_balance(sender, remaining);
+            self.set_balance(receiver, received);
+        }
+        Ok(())
+    }
+
+    /**
+        Revokes the sender's signing key `key` and, for a rotation,
+        makes `new_key` its signing key. `key` must be the current
+        one, or the key its address commits to if it never rotated,
+        and `new_key` a well formed key that was never revoked.
+     */
+    fn apply_key_change(
+        &mut self, sender: &str, key: &str, new_key: Option<&String>
+    ) -> Result<(), BlockValidationError> {
+        let invalid = |key: &str| BlockValidationError::InvalidKey {
+            account: sender.to_string(), key: key.to_string()
+        };
+        match self.get_signing_key(sender) {
+            Some(current) if self.get_revocation(current).is_some() =>
+                return Err(BlockValidationError::KeyRevoked(sender.to_string())),
+            Some(current) if current != key => return Err(invalid(key)),
+            Some(_) => {}
+            None if !Address::is_address_of(sender, key) => return Err(invalid(key)),
+            None => {}
+        }
+        if let Some(new_key) = new_key {
+            if new_key == key
+                || self.get_revocation(new_key).is_some()
+                || VerifyingKey::from_hex(new_key).is_err() {
+                return Err(invalid(new_key));
+            }
+        }
+        //  A revoked key stays recorded so the account is known to have none
+        self.set_signing_key(sender, new_key.map_or(key, String::as_str));
+
+        self.set_revocation(key, Some(KeyRevocation {
+            account: sender.to_string(),
+            height: self.height,
+            replaced_by: new_key.cloned(),
+        }));
+        Ok(())
+    }
+
+    /**
+        Puts the sender under `policy`. `previous` must be the policy
+        it has now, if any, and none of the new keys may be revoked.
+     */
+    fn apply_policy_change(
+        &mut self, sender: &str, previous: Option<&MultisigPolicy>, policy: &MultisigPolicy
+    ) -> Result<(), BlockValidationError> {
+        if previous != self.get_multisig_policy(sender)
+            || policy.get_keys().iter().any(|key| self.get_revocation(key).is_some()) {
+            return Err(BlockValidationError::InvalidPolicy(sender.to_string()));
+        }
+
+        self.set_policy(sender, Some(policy.clone()));
+        Ok(())
+    }
+
+    /**
+        Adds to the flight log of `drone` with `record`, which is
+        given the time of the block. Drones report their own flights
+        and must be registered and active.
+     */
+    fn apply_flight<F>(
+        &mut self, sender: &str, drone: &str, record: F
+    ) -> Result<(), BlockValidationError>
+    where F: FnOnce(&mut FlightLog, u64) -> Result<(), FlightError> {
+        let invalid = |error| BlockValidationError::InvalidFlight {
+            drone: drone.to_string(), error
+        };
+        if sender != drone {
+            return Err(invalid(FlightError::NotTheDrone(sender.to_string())));
+        }
+        match self.get_drone(drone) {
+            Some(registered) if registered.is_active() => {}
+            Some(_) => return Err(BlockValidationError::InvalidDrone {
+                drone: drone.to_string(), error: DroneError::Decommissioned
+            }),
+            None => return Err(BlockValidationError::InvalidDrone {
+                drone: drone.to_string(), error: DroneError::Unregistered
+            }),
+        }
+
+        let mut log = self.get_flight_log(drone).cloned().unwrap_or_default();
+        record(&mut log, self.timestamp).map_err(invalid)?;
+        self.set_flight_log(drone, log);
+        Ok(())
+    }
+
+    //  Legs run between registered waypoints and end at an active one
+    fn check_route(&self, leg: &Leg) -> Result<(), FlightError> {
+        let registered = |id: &str| self.get_waypoint(id)
+            .ok_or_else(|| FlightError::UnknownWaypoint(id.to_string()));
+
+        registered(leg.get_from_waypoint())?;
+        if registered(leg.get_to_waypoint())?.get_status() != WaypointStatus::Active {
+            return Err(FlightError::ClosedWaypoint(leg.get_to_waypoint().to_string()));
+        }
+        Ok(())
+    }
+
+    //  Adds `waypoint` to the registry under a new id, for its operator only
+    fn apply_waypoint(&mut self, sender: &str, waypoint: Waypoint) -> Result<(), BlockValidationError> {
+        let id = waypoint.get_id().to_string();
+        let invalid = |error| BlockValidationError::InvalidWaypoint { waypoint: id.clone(), error };
+        if sender != waypoint.get_operator() {
+            return Err(invalid(WaypointError::NotTheOperator(sender.to_string())));
+        }
+        waypoint.check().map_err(invalid)?;
+        if self.get_waypoint(&id).is_some() {
+            return Err(invalid(WaypointError::AlreadyRegistered(id.clone())));
+        }
+
+        self.set_waypoint(&id, Some(waypoint));
+        Ok(())
+    }
+
+    /**
+        Adds `drone` to the registry for its owner. Its id and serial
+        must be new, so a spoofed drone can't take another's place.
+     */
+    fn apply_drone_registration(&mut self, sender: &str, drone: Drone) -> Result<(), BlockValidationError> {
+        let id = drone.get_id().to_string();
+        let invalid = |error| BlockValidationError::InvalidDrone { drone: id.clone(), error };
+        if sender != drone.get_owner() {
+            return Err(invalid(DroneError::NotTheOwner(sender.to_string())));
+        }
+        drone.check().map_err(invalid)?;
+        if self.get_drone(&id).is_some() {
+            return Err(invalid(DroneError::AlreadyRegistered(id.clone())));
+        }
+        if self.is_serial_taken(drone.get_serial()) {
+            return Err(invalid(DroneError::SerialTaken(drone.get_serial().to_string())));
+        }
+
+        self.set_drone(&id, Some(drone));
+        Ok(())
+    }
+
+    //  Registered, active drone `id` if `sender` owns it
+    fn get_owned_drone(&self, sender: &str, id: &str) -> Result<Drone, BlockValidationError> {
+        let invalid = |error| BlockValidationError::InvalidDrone { drone: id.to_string(), error };
+        let drone = self.get_drone(id).ok_or(invalid(DroneError::Unregistered))?;
+        if drone.get_owner() != sender {
+            return Err(invalid(DroneError::NotTheOwner(sender.to_string())));
+        }
+        if !drone.is_active() {
+            return Err(invalid(DroneError::Decommissioned));
+        }
+        Ok(drone.clone())
+    }
+
+    //  Applies every operation and records the transaction, or nothing
+    pub fn apply_transaction(&mut self, transaction: &Transaction) -> Result<(), BlockValidationError> {
+        let id = transaction.get_id();
+        if self.contains_transaction(&id) {
+            return Err(BlockValidationError::ReplayedTransaction(id));
+        }
+
+        let checkpoint = self.checkpoint();
+        for operation in transaction.get_operations() {
+            if let Err(e) = self.apply_operation(&operation) {
+                self.rollback(checkpoint);
+                return Err(e);
+            }
+        }
+
+        self.set_transaction(id, Some(transaction.clone()));
+        Ok(())
+    }
+
+    //  Applies the transactions in order, all or nothing
+    pub fn apply_transactions(&mut self, transactions: &[Transaction]) -> Result<(), BlockValidationError> {
+        let checkpoint = self.checkpoint();
+        for transaction in transactions {
+            if let Err(e) = self.apply_transaction(transaction) {
+                self.rollback(checkpoint);
+                return Err(e);
+            }
+        }
+        Ok(())
+    }
+
+    /**
+        Undoes `apply_transaction` for a transaction applied earlier,
+        restoring balances, nonces and forgetting the transaction.
+     */
+    pub fn revert_transaction(&mut self, transaction: &Transaction) {
+        for operation in transaction.get_operations().iter().rev() {
+            let sender = operation.get_sender();
+
+            match operation.get_kind() {
+                OperationKind::Transfer { receiver, amount } => if sender != *receiver {
+                    let balance = self.get_balance(receiver).saturating_sub(*amount);
+                    self.set_balance(receiver, balance);
+                    let balance = self.get_balance(&sender).saturating_add(*amount);
+                    self.set_balance(&sender, balance);
+                },
+                OperationKind::RotateKey { key, .. } => {
+                    self.set_signing_key(&sender, key);
+                    self.set_revocation(key, None);
+                }
+                OperationKind::RevokeKey { key } => self.set_revocation(key, None),
+                OperationKind::SetMultisig { previous, .. } =>
+                    self.set_policy(&sender, previous.clone()),
+                OperationKind::JourneyLeg { drone, .. } => {
+                    let mut log = self.get_flight_log(drone).cloned().unwrap_or_default();
+                    log.remove_leg();
+                    self.set_flight_log(drone, log);
+                }
+                OperationKind::PositionReport { drone, .. } => {
+                    let mut log = self.get_flight_log(drone).cloned().unwrap_or_default();
+                    log.remove_position();
+                    self.set_flight_log(drone, log);
+                }
+                OperationKind::RegisterWaypoint { id, .. } => self.set_waypoint(id, None),
+                OperationKind::RegisterDrone { drone, .. } => self.set_drone(drone, None),
+                //  Only the owner transfers, so the sender owned it before
+                OperationKind::TransferDroneOwnership { drone: id, .. } => {
+                    if let Some(mut drone) = self.get_drone(id).cloned() {
+                        drone.set_owner(sender.clone());
+                        self.set_drone(id, Some(drone));
+                    }
+                }
+                OperationKind::DecommissionDrone { drone: id } => {
+                    if let Some(mut drone) = self.get_drone(id).cloned() {
+                        drone.set_status(DroneStatus::Active);
+                        self.set_drone(id, Some(drone));
+                    }
+                }
+            }
+            self.set_nonce(&sender, operation.get_nonce());
+        }
+        self.set_transaction(transaction.get_id(), None);
+    }
+
+    //  Root the state would have once the changes are committed
+    pub fn root(&self, algorithm: HashAlgorithm) -> Hash32 {
+        let mut balances = self.base.balances.clone();
+        balances.extend(self.balances.clone());
+        let mut nonces = self.base.nonces.clone();
+        nonces.extend(self.nonces.clone());
+        let mut signing_keys = self.base.signing_keys.clone();
+        signing_keys.extend(self.signing_keys.clone());
+        let mut revocations = self.base.revocations.clone();
+        for (key, revocation) in &self.revocations {
+            restore(&mut revocations, key.clone(), revocation.clone());
+        }
+        let mut policies = self.base.policies.clone();
+        for (account, policy) in &self.policies {
+            restore(&mut policies, account.clone(), policy.clone());
+        }
+        let mut flights = self.base.flights.clone();
+        flights.extend(self.flights.clone());
+        let mut waypoints = self.base.waypoints.clone();
+        for (id, waypoint) in &self.waypoints {
+            restore(&mut waypoints, id.clone(), waypoint.clone());
+        }
+        let mut drones = self.base.drones.clone();
+        for (id, drone) in &self.drones {
+            restore(&mut drones, id.clone(), drone.clone());
+        }
+
+        //  Transactions aren't part of the root, so they're left out
+        State {
+            balances, nonces, signing_keys, revocations, policies, flights, waypoints, drones,
+            transactions: HashMap::new(),
+        }.root(algorithm)
+    }
+
+    pub fn into_changes(self) -> StateChanges {
+        StateChanges {
+            balances: self.balances,
+            nonces: self.nonces,
+            signing_keys: self.signing_keys,
+            revocations: self.revocations,
+            policies: self.policies,
+            flights: self.flights,
+            waypoints: self.waypoints,
+            drones: self.drones,
+            transactions: self.transactions,
+        }
+    }
+}
+
+fn restore<K: std::hash::Hash + Eq, V>(map: &mut HashMap<K, V>, key: K, previous: Option<V>) {
+    match previous {
+        Some(value) => map.insert(key, value),
+        None => map.remove(&key),
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::account::Account;
+    use crate::amount::Amount;
+    use crate::blockchain::BlockValidationError;
+    use crate::drone::{DroneError, DroneStatus};
+    use crate::flight::{FlightError, Leg, Position};
+    use crate::hash::HashAlgorithm;
+    use crate::keysig::{KeyAlgorithm, KeySig};
+    use crate::multisig::{MultisigPolicy, SigningAuthority};
+    use crate::state::{State, StateOverlay};
+    use crate::transops::{Operation, OperationKind, Transaction};
+    use crate::waypoint::{WaypointError, WaypointStatus};
+
+    fn get_transfer(sender: &str, receiver: &str, amount: u64, nonce: u64) -> Operation {
+        Operation::new(sender.to_string(), receiver.to_string(), Amount::new(amount), nonce)
+    }
+
+    fn get_rotation(sender: &str, key: &str, new_key: &str, nonce: u64) -> Operation {
+        Operation::with_kind(
+            sender.to_string(),
+            OperationKind::RotateKey { key: key.to_string(), new_key: new_key.to_string() },
+            nonce
+        )
+    }
+
+    fn get_revocation(sender: &str, key: &str) -> Operation {
+        Operation::with_kind(sender.to_string(), OperationKind::RevokeKey { key: key.to_string() }, 0)
+    }
+
+    fn get_new_key() -> String {
+        hex::encode(KeySig::generate(KeyAlgorithm::Ed25519).get_public_key())
+    }
+
+    //  Address of a new account and the key it commits to
+    fn get_account() -> (String, String) {
+        let key = KeySig::generate(KeyAlgorithm::Ed25519);
+        (Account::address_of(&key), hex::encode(key.get_public_key()))
+    }
+
+    fn get_state() -> State {
+        let mut state = State::new();
+        state.set_balance("a", Amount::new(300));
+        state
+    }
+
+    #[test]
+    fn test_apply_operation() {
+        let state = get_state();
+        let mut overlay = state.overlay();
+
+        overlay.apply_operation(&get_transfer("a", "b", 256, 0)).unwrap();
+
+        assert_eq!(overlay.get_balance("a"), Amount::new(44));
+        assert_eq!(overlay.get_balance("b"), Amount::new(256));
+        assert_eq!(overlay.get_nonce("a"), 1);
+        assert_eq!(state.get_balance("a"), Amount::new(300));
+    }
+
+    #[test]
+    fn test_apply_operation_insufficient_balance() {
+        let state = get_state();
+        let mut overlay = state.overlay();
+
+        assert_eq!(
+            overlay.apply_operation(&get_transfer("a", "b", 301, 0)),
+            Err(BlockValidationError::InsufficientBalance {
+                account: "a".to_string(), balance: Amount::new(300), amount: Amount::new(301)
+            })
+        );
+        assert_eq!(overlay.get_nonce("a"), 0);
+    }
+
+    #[test]
+    fn test_apply_operation_overflow() {
+        let mut state = get_state();
+        state.set_balance("b", Amount::MAX);
+        let mut overlay = state.overlay();
+
+        assert_eq!(
+            overlay.apply_operation(&get_transfer("a", "b", 1, 0)),
+            Err(BlockValidationError::Overflow { account: "b".to_string() })
+        );
+        assert_eq!(overlay.get_balance("a"), Amount::new(300));
+    }
+
+    //  Second operation fails, so the first is undone with it
+    #[test]
+    fn test_transaction_is_atomic() {
+        let state = get_state();
+        let mut overlay = state.overlay();
+        let transaction = Transaction::create_transaction(vec![
+            get_transfer("a", "b", 100, 0),
+            get_transfer("a", "c", 500, 1),
+        ]);
+
+        assert!(overlay.apply_transaction(&transaction).is_err());
+        assert_eq!(overlay.get_balance("a"), Amount::new(300));
+        assert_eq!(overlay.get_balance("b"), Amount::ZERO);
+        assert_eq!(overlay.get_nonce("a"), 0);
+        assert!(!overlay.contains_transaction(&transaction.get_id()));
+    }
+
+    #[test]
+    fn test_transactions_are_atomic() {
+        let state = get_state();
+        let mut overlay = state.overlay();
+        let good = Transaction::create_transaction(vec![get_transfer("a", "b", 100, 0)]);
+        let bad = Transaction::create_transaction(vec![get_transfer("b", "c", 1, 5)]);
+
+        assert!(overlay.apply_transactions(&[good.clone(), bad]).is_err());
+        assert_eq!(overlay.get_balance("a"), Amount::new(300));
+        assert!(!overlay.contains_transaction(&good.get_id()));
+
+        overlay.apply_transaction(&good).unwrap();
+        assert!(overlay.apply_transaction(&good).is_err());
+    }
+
+    #[test]
+    fn test_commit_and_revert() {
+        let mut state = get_state();
+        let root = state.root(HashAlgorithm::Sha256);
+        let transaction = Transaction::create_transaction(vec![get_transfer("a", "b", 100, 0)]);
+
+        let mut overlay = state.overlay();
+        overlay.apply_transaction(&transaction).unwrap();
+        let pending_root = overlay.root(HashAlgorithm::Sha256);
+        let changes = overlay.into_changes();
+        state.commit(changes);
+
+        assert_eq!(state.root(HashAlgorithm::Sha256), pending_root);
+        assert_eq!(state.get_balance("b"), Amount::new(100));
+        assert!(state.contains_transaction(&transaction.get_id()));
+
+        let mut overlay = state.overlay();
+        overlay.revert_transaction(&transaction);
+        let changes = overlay.into_changes();
+        state.commit(changes);
+
+        assert_eq!(state.root(HashAlgorithm::Sha256), root);
+        assert!(!state.contains_transaction(&transaction.get_id()));
+    }
+
+    #[test]
+    fn test_rollback_to_checkpoint() {
+        let state = get_state();
+        let mut overlay = state.overlay();
+        overlay.apply_operation(&get_transfer("a", "b", 1, 0)).unwrap();
+        let checkpoint = overlay.checkpoint();
+        overlay.apply_operation(&get_transfer("a", "b", 1, 1)).unwrap();
+
+        overlay.rollback(checkpoint);
+
+        assert_eq!(overlay.get_balance("b"), Amount::new(1));
+        assert_eq!(overlay.get_nonce("a"), 1);
+    }
+
+    #[test]
+    fn test_rotate_key() {
+        let mut state = get_state();
+        let root = state.root(HashAlgorithm::Sha256);
+        let (account, key) = get_account();
+        let new_key = get_new_key();
+        let rotation = get_rotation(&account, &key, &new_key, 0);
+        let transaction = Transaction::create_transaction(vec![rotation]);
+
+        let mut overlay = state.overlay();
+        overlay.set_height(4);
+        overlay.apply_transaction(&transaction).unwrap();
+        let changes = overlay.into_changes();
+        state.commit(changes);
+
+        assert_eq!(state.get_authority(&account), Some(SigningAuthority::Key(new_key.clone())));
+        let revocation = state.get_revocation(&key).unwrap();
+        assert_eq!(revocation.get_height(), 4);
+        assert_eq!(revocation.get_replaced_by(), Some(new_key.as_str()));
+        assert_ne!(state.root(HashAlgorithm::Sha256), root);
+
+        let mut overlay = state.overlay();
+        overlay.revert_transaction(&transaction);
+        let changes = overlay.into_changes();
+        state.commit(changes);
+
+        assert_eq!(state.get_authority(&account), Some(SigningAuthority::Address(account.clone())));
+        assert!(state.get_revocation(&key).is_none());
+        assert_eq!(state.root(HashAlgorithm::Sha256), root);
+    }
+
+    #[test]
+    fn test_reject_invalid_rotation() {
+        let state = get_state();
+        let mut overlay = state.overlay();
+        let (account, key) = get_account();
+        let new_key = get_new_key();
+        let invalid = |key: &str| Err(BlockValidationError::InvalidKey {
+            account: account.clone(), key: key.to_string()
+        });
+
+        //  Only the current signing key can be rotated
+        let rotation = get_rotation(&account, &new_key, &get_new_key(), 0);
+        assert_eq!(overlay.apply_operation(&rotation), invalid(&new_key));
+        let rotation = get_rotation(&account, &key, "not a key", 0);
+        assert_eq!(overlay.apply_operation(&rotation), invalid("not a key"));
+        assert_eq!(overlay.apply_operation(&get_rotation(&account, &key, &key, 0)), invalid(&key));
+        assert_eq!(overlay.get_nonce(&account), 0);
+
+        //  A retired key can't come back
+        let other = get_new_key();
+        overlay.apply_operation(&get_rotation(&account, &key, &new_key, 0)).unwrap();
+        overlay.apply_operation(&get_rotation(&account, &new_key, &other, 1)).unwrap();
+        let rotation = get_rotation(&account, &other, &new_key, 2);
+        assert_eq!(overlay.apply_operation(&rotation), invalid(&new_key));
+    }
+
+    #[test]
+    fn test_revoke_key() {
+        let state = get_state();
+        let mut overlay = state.overlay();
+        let (account, key) = get_account();
+
+        overlay.apply_operation(&get_revocation(&account, &key)).unwrap();
+
+        assert_eq!(overlay.get_authority(&account), None);
+        assert_eq!(
+            overlay.apply_operation(&get_rotation(&account, &key, &get_new_key(), 1)),
+            Err(BlockValidationError::KeyRevoked(account))
+        );
+    }
+
+    fn get_policy(threshold: u32, keys: usize) -> MultisigPolicy {
+        MultisigPolicy::new(threshold, (0..keys).map(|_| get_new_key()).collect()).unwrap()
+    }
+
+    #[test]
+    fn test_set_multisig() {
+        let mut state = get_state();
+        let root = state.root(HashAlgorithm::Sha256);
+        let policy = get_policy(2, 3);
+        let setup = Operation::with_kind(
+            "a".to_string(), OperationKind::SetMultisig { previous: None, policy: policy.clone() }, 0
+        );
+        let transaction = Transaction::create_transaction(vec![setup]);
+
+        let mut overlay = state.overlay();
+        overlay.apply_transaction(&transaction).unwrap();
+        let changes = overlay.into_changes();
+        state.commit(changes);
+
+        assert_eq!(state.get_multisig_policy("a"), Some(&policy));
+        assert_eq!(state.get_authority("a"), Some(SigningAuthority::Multisig(policy)));
+        assert_ne!(state.root(HashAlgorithm::Sha256), root);
+
+        let mut overlay = state.overlay();
+        overlay.revert_transaction(&transaction);
+        let changes = overlay.into_changes();
+        state.commit(changes);
+
+        assert_eq!(state.get_multisig_policy("a"), None);
+        assert_eq!(state.get_authority("a"), Some(SigningAuthority::Address("a".to_string())));
+        assert_eq!(state.root(HashAlgorithm::Sha256), root);
+    }
+
+    #[test]
+    fn test_reject_invalid_policy_change() {
+        let state = get_state();
+        let mut overlay = state.overlay();
+        let policy = get_policy(2, 3);
+        let invalid = Err(BlockValidationError::InvalidPolicy("a".to_string()));
+
+        //  Has to name the policy it replaces
+        let stale = Operation::multisig_change("a".to_string(), get_policy(1, 1), policy.clone(), 0);
+        assert_eq!(overlay.apply_operation(&stale), invalid);
+
+        let setup = OperationKind::SetMultisig { previous: None, policy: policy.clone() };
+        overlay.apply_operation(&Operation::with_kind("a".to_string(), setup.clone(), 0)).unwrap();
+        assert_eq!(overlay.apply_operation(&Operation::with_kind("a".to_string(), setup, 1)), invalid);
+
+        //  Nor can it add a revoked key
+        let (account, revoked) = get_account();
+        overlay.apply_operation(&get_revocation(&account, &revoked)).unwrap();
+        let with_revoked = MultisigPolicy::new(1, vec![revoked, get_new_key()]).unwrap();
+        let change = Operation::multisig_change("a".to_string(), policy.clone(), with_revoked, 1);
+        assert_eq!(overlay.apply_operation(&change), invalid);
+
+        assert_eq!(overlay.get_multisig_policy("a"), Some(&policy));
+        assert_eq!(overlay.get_nonce("a"), 1);
+    }
+
+    fn get_leg(sender: &str, drone: &str, leg: &Leg, nonce: u64) -> Operation {
+        let kind = OperationKind::JourneyLeg {
+            drone: drone.to_string(),
+            from_waypoint: leg.get_from_waypoint().to_string(),
+            to_waypoint: leg.get_to_waypoint().to_string(),
+            departed_at: leg.get_departed_at(),
+            arrived_at: leg.get_arrived_at(),
+        };
+        Operation::with_kind(sender.to_string(), kind, nonce)
+    }
+
+    //  Registers a new drone owned by "a", returning its id
+    fn register_drone(overlay: &mut StateOverlay, serial: &str) -> String {
+        let (drone, transponder) = get_account();
+        let kind = OperationKind::RegisterDrone {
+            drone: drone.clone(), serial: serial.to_string(), transponder, owner: "a".to_string()
+        };
+        let nonce = overlay.get_nonce("a");
+        overlay.apply_operation(&Operation::with_kind("a".to_string(), kind, nonce)).unwrap();
+        drone
+    }
+
+    //  Registers a new waypoint run by "a", returning its id
+    fn register_waypoint(overlay: &mut StateOverlay) -> String {
+        let id = get_account().0;
+        let nonce = overlay.get_nonce("a");
+        overlay.apply_operation(&get_registration("a", "a", &id, 4, nonce)).unwrap();
+        id
+    }
+
+    #[test]
+    fn test_record_flight() {
+        let mut state = get_state();
+        let mut overlay = state.overlay();
+        let drone = register_drone(&mut overlay, "DJI-0001");
+        let (from, to) = (register_waypoint(&mut overlay), register_waypoint(&mut overlay));
+        let changes = overlay.into_changes();
+        state.commit(changes);
+        let root = state.root(HashAlgorithm::Sha256);
+
+        let leg = Leg::new(from, to.clone(), 10, 20);
+        let report = Operation::with_kind(drone.clone(), OperationKind::PositionReport {
+            drone: drone.clone(), lat: 1, lon: 2, alt: 3, heading: 4, speed: 5, timestamp: 20
+        }, 1);
+        let transaction = Transaction::create_transaction(vec![get_leg(&drone, &drone, &leg, 0), report]);
+
+        let mut overlay = state.overlay();
+        overlay.set_timestamp(30);
+        overlay.apply_transaction(&transaction).unwrap();
+        let changes = overlay.into_changes();
+        state.commit(changes);
+
+        let log = state.get_flight_log(&drone).unwrap();
+        assert_eq!(log.get_legs(), &[leg]);
+        assert_eq!(log.get_location(), Some(to.as_str()));
+        assert_eq!(log.get_last_position(), Some(&Position::new(1, 2, 3, 4, 5, 20)));
+        assert_ne!(state.root(HashAlgorithm::Sha256), root);
+
+        let mut overlay = state.overlay();
+        overlay.revert_transaction(&transaction);
+        let changes = overlay.into_changes();
+        state.commit(changes);
+
+        assert_eq!(state.get_flight_log(&drone), None);
+        assert_eq!(state.root(HashAlgorithm::Sha256), root);
+    }
+
+    #[test]
+    fn test_reject_invalid_flight() {
+        let state = get_state();
+        let mut overlay = state.overlay();
+        overlay.set_timestamp(30);
+        let drone = register_drone(&mut overlay, "DJI-0001");
+        let depot = register_waypoint(&mut overlay);
+        let leg = Leg::new(depot.clone(), register_waypoint(&mut overlay), 10, 40);
+
+        //  Only the drone reports its flights
+        assert_eq!(
+            overlay.apply_operation(&get_leg("a", &drone, &leg, 3)),
+            Err(BlockValidationError::InvalidFlight {
+                drone: drone.clone(), error: FlightError::NotTheDrone("a".to_string())
+            })
+        );
+
+        //  Nor ahead of the block recording it
+        assert_eq!(
+            overlay.apply_operation(&get_leg(&drone, &drone, &leg, 0)),
+            Err(BlockValidationError::InvalidFlight {
+                drone: drone.clone(),
+                error: FlightError::InFuture { timestamp: 40, block_timestamp: 30 }
+            })
+        );
+
+        //  Nor to a waypoint nobody registered
+        let (nowhere, _) = get_account();
+        let stray = Leg::new(depot, nowhere.clone(), 10, 20);
+        assert_eq!(
+            overlay.apply_operation(&get_leg(&drone, &drone, &stray, 0)),
+            Err(BlockValidationError::InvalidFlight {
+                drone: drone.clone(), error: FlightError::UnknownWaypoint(nowhere)
+            })
+        );
+        assert_eq!(overlay.get_flight_log(&drone), None);
+        assert_eq!(overlay.get_nonce(&drone), 0);
+
+        //  Drones nobody registered can't fly
+        let (unregistered, _) = get_account();
+        assert_eq!(
+            overlay.apply_operation(&get_leg(&unregistered, &unregistered, &leg, 0)),
+            Err(BlockValidationError::InvalidDrone {
+                drone: unregistered, error: DroneError::Unregistered
+            })
+        );
+    }
+
+    fn get_registration(
+        sender: &str, operator: &str, id: &str, capacity: u32, nonce: u64
+    ) -> Operation {
+        let kind = OperationKind::RegisterWaypoint {
+            id: id.to_string(),
+            operator: operator.to_string(),
+            lat: 504_501_000,
+            lon: 305_234_000,
+            min_alt: 0,
+            max_alt: 120_000,
+            capacity,
+            status: WaypointStatus::Active,
+        };
+        Operation::with_kind(sender.to_string(), kind, nonce)
+    }
+
+    #[test]
+    fn test_register_waypoint() {
+        let mut state = get_state();
+        let root = state.root(HashAlgorithm::Sha256);
+        let id = get_account().0;
+        let transaction = Transaction::create_transaction(vec![get_registration("a", "a", &id, 4, 0)]);
+
+        let mut overlay = state.overlay();
+        overlay.apply_transaction(&transaction).unwrap();
+        let changes = overlay.into_changes();
+        state.commit(changes);
+
+        let waypoint = state.get_waypoint(&id).unwrap();
+        assert_eq!(waypoint.get_operator(), "a");
+        assert_eq!(waypoint.get_capacity(), 4);
+        assert_eq!(state.get_waypoints_by_operator("a"), vec![waypoint]);
+        assert_ne!(state.root(HashAlgorithm::Sha256), root);
+
+        let mut overlay = state.overlay();
+        overlay.revert_transaction(&transaction);
+        let changes = overlay.into_changes();
+        state.commit(changes);
+
+        assert_eq!(state.get_waypoint(&id), None);
+        assert_eq!(state.root(HashAlgorithm::Sha256), root);
+    }
+
+    #[test]
+    fn test_reject_invalid_waypoint() {
+        let state = get_state();
+        let mut overlay = state.overlay();
+        let id = get_account().0;
+        let invalid = |error| Err(BlockValidationError::InvalidWaypoint { waypoint: id.clone(), error });
+
+        assert_eq!(
+            overlay.apply_operation(&get_registration("a", "b", &id, 4, 0)),
+            invalid(WaypointError::NotTheOperator("a".to_string()))
+        );
+        assert_eq!(
+            overlay.apply_operation(&get_registration("a", "a", &id, 0, 0)),
+            invalid(WaypointError::NoCapacity)
+        );
+
+        //  Ids are taken for good, even by the same operator
+        overlay.apply_operation(&get_registration("a", "a", &id, 4, 0)).unwrap();
+        assert_eq!(
+            overlay.apply_operation(&get_registration("a", "a", &id, 8, 1)),
+            invalid(WaypointError::AlreadyRegistered(id.clone()))
+        );
+        assert_eq!(overlay.get_waypoint(&id).unwrap().get_capacity(), 4);
+    }
+
+    #[test]
+    fn test_drone_lifecycle() {
+        let mut state = get_state();
+        let mut overlay = state.overlay();
+        let drone = register_drone(&mut overlay, "DJI-0001");
+        let changes = overlay.into_changes();
+        state.commit(changes);
+        assert_eq!(state.get_drone(&drone).unwrap().get_owner(), "a");
+        assert_eq!(state.get_drones_by_owner("a").len(), 1);
+        let root = state.root(HashAlgorithm::Sha256);
+        let (buyer, _) = get_account();
+
+        let handover = Operation::with_kind(
+            "a".to_string(),
+            OperationKind::TransferDroneOwnership { drone: drone.clone(), new_owner: buyer.clone() },
+            1
+        );
+        let retirement = Operation::with_kind(
+            buyer.clone(), OperationKind::DecommissionDrone { drone: drone.clone() }, 0
+        );
+        let transaction = Transaction::create_transaction(vec![handover, retirement]);
+
+        let mut overlay = state.overlay();
+        overlay.apply_transaction(&transaction).unwrap();
+        let changes = overlay.into_changes();
+        state.commit(changes);
+
+        let retired = state.get_drone(&drone).unwrap();
+        assert_eq!(retired.get_owner(), buyer);
+        assert_eq!(retired.get_status(), DroneStatus::Decommissioned);
+        assert!(state.get_drones_by_owner("a").is_empty());
+
+        let mut overlay = state.overlay();
+        overlay.revert_transaction(&transaction);
+        let changes = overlay.into_changes();
+        state.commit(changes);
+
+        let restored = state.get_drone(&drone).unwrap();
+        assert_eq!(restored.get_owner(), "a");
+        assert!(restored.is_active());
+        assert_eq!(state.root(HashAlgorithm::Sha256), root);
+    }
+
+    fn get_drone_registration(
+        sender: &str, drone: &str, serial: &str, transponder: &str, nonce: u64
+    ) -> Operation {
+        let kind = OperationKind::RegisterDrone {
+            drone: drone.to_string(),
+            serial: serial.to_string(),
+            transponder: transponder.to_string(),
+            owner: "a".to_string(),
+        };
+        Operation::with_kind(sender.to_string(), kind, nonce)
+    }
+
+    #[test]
+    fn test_reject_invalid_drone() {
+        let state = get_state();
+        let mut overlay = state.overlay();
+        let drone = register_drone(&mut overlay, "DJI-0001");
+        let (other, transponder) = get_account();
+        let invalid = |drone: &str, error| Err(BlockValidationError::InvalidDrone {
+            drone: drone.to_string(), error
+        });
+
+        //  Only the owner registers
+        assert_eq!(
+            overlay.apply_operation(&get_drone_registration("b", &other, "DJI-0002", &transponder, 0)),
+            invalid(&other, DroneError::NotTheOwner("b".to_string()))
+        );
+
+        //  Nor can it spoof a registered drone's id or serial
+        assert_eq!(
+            overlay.apply_operation(&get_drone_registration("a", &drone, "DJI-0002", &transponder, 1)),
+            invalid(&drone, DroneError::InvalidTransponder(transponder.clone()))
+        );
+        assert_eq!(
+            overlay.apply_operation(&get_drone_registration("a", &other, "DJI-0001", &transponder, 1)),
+            invalid(&other, DroneError::SerialTaken("DJI-0001".to_string()))
+        );
+
+        //  Drones are only handed over to addresses
+        let to_nobody = OperationKind::TransferDroneOwnership {
+            drone: drone.clone(), new_owner: "b".to_string()
+        };
+        assert_eq!(
+            overlay.apply_operation(&Operation::with_kind("a".to_string(), to_nobody, 1)),
+            invalid(&drone, DroneError::InvalidOwner("b".to_string()))
+        );
+        let (owner, _) = get_account();
+        let to_alias = OperationKind::TransferDroneOwnership {
+            drone: drone.clone(), new_owner: owner.to_uppercase()
+        };
+        assert_eq!(
+            overlay.apply_operation(&Operation::with_kind("a".to_string(), to_alias, 1)),
+            invalid(&drone, DroneError::InvalidOwner(owner.to_uppercase()))
+        );
+        assert_eq!(overlay.get_drone(&drone).unwrap().get_owner(), "a");
+
+        //  Decommissioned drones stay with their owner and can't fly
+        let retirement = OperationKind::DecommissionDrone { drone: drone.clone() };
+        assert_eq!(
+            overlay.apply_operation(&Operation::with_kind("b".to_string(), retirement.clone(), 0)),
+            invalid(&drone, DroneError::NotTheOwner("b".to_string()))
+        );
+        overlay.apply_operation(&Operation::with_kind("a".to_string(), retirement, 1)).unwrap();
+        let handover = OperationKind::TransferDroneOwnership {
+            drone: drone.clone(), new_owner: "b".to_string()
+        };
+        assert_eq!(
+            overlay.apply_operation(&Operation::with_kind("a".to_string(), handover, 2)),
+            invalid(&drone, DroneError::Decommissioned)
+        );
+        let report = OperationKind::PositionReport {
+            drone: drone.clone(), lat: 0, lon: 0, alt: 0, heading: 0, speed: 0, timestamp: 0
+        };
+        assert_eq!(
+            overlay.apply_operation(&Operation::with_kind(drone.clone(), report, 0)),
+            invalid(&drone, DroneError::Decommissioned)
+        );
+    }
+}
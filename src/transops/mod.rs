@@ -1,141 +1,793 @@
 //  Handles operations and transactions
 
-use std::collections::HashMap;
-use rand::Rng;
+use std::collections::{BTreeMap, HashMap};
+use std::error::Error;
+use std::fmt;
 use crate::account::Account;
-use crate::hash::to_sha1;
-use crate::utils::vec_to_string;
+use crate::amount::Amount;
+use crate::drone::Drone;
+use crate::encoding::{Encode, Encoder};
+use crate::flight::{Leg, Position};
+use crate::hash::{ChainHasher, Hash32, HashAlgorithm};
+use crate::keysig::KeySig;
+use crate::multisig::{MultisigPolicy, SigningAuthority};
+use crate::waypoint::{AltitudeBand, Waypoint, WaypointStatus};
 use serde::Serialize;
 
 
-const flight: &str = "Cargo Flight";
+//  Encoding tag of the bytes an operation's sender signs
+const SIGNING_TAG: u8 = 6;
 
+//  Encoding tag of the bytes a transaction's signers sign
+const TRANSACTION_SIGNING_TAG: u8 = 7;
+
+/**
+    What an operation does on behalf of its sender.
+    Keys are hex encoded tagged public keys, as in `Address::is_address_of`.
+ */
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub enum OperationKind {
+    //  Moves `amount` from the sender to `receiver`
+    Transfer { receiver: String, amount: Amount },
+    //  Replaces the sender's signing key `key` with `new_key`, revoking `key`
+    RotateKey { key: String, new_key: String },
+    //  Revokes the sender's signing key `key`, the account can't sign again
+    RevokeKey { key: String },
+    //  Puts the sender under `policy`, replacing `previous`, the policy it had if any
+    SetMultisig { previous: Option<MultisigPolicy>, policy: MultisigPolicy },
+    //  Drone completed a journey between two registered waypoints, see `Leg`
+    JourneyLeg {
+        drone: String,
+        from_waypoint: String,
+        to_waypoint: String,
+        departed_at: u64,
+        arrived_at: u64,
+    },
+    //  Drone's position in flight, see `Position` for units
+    PositionReport {
+        drone: String,
+        lat: i32,
+        lon: i32,
+        alt: i32,
+        heading: u16,
+        speed: u32,
+        timestamp: u64,
+    },
+    //  Operator adds a waypoint to the registry, see `Waypoint` for units
+    RegisterWaypoint {
+        id: String,
+        operator: String,
+        lat: i32,
+        lon: i32,
+        min_alt: i32,
+        max_alt: i32,
+        capacity: u32,
+        status: WaypointStatus,
+    },
+    //  Owner binds a drone to its transponder key, see `Drone`
+    RegisterDrone { drone: String, serial: String, transponder: String, owner: String },
+    //  Owner hands `drone` over to `new_owner`
+    TransferDroneOwnership { drone: String, new_owner: String },
+    //  Owner retires `drone`, it can't fly or change hands again
+    DecommissionDrone { drone: String },
+}
+
+/**
+    Action of one account, referenced by its id. An account id
+    is the address of its first public key, see `Address`, and
+    stays the same when the account rotates to another signing
+    key. The signer's public key travels with the signature.
+ */
 #[derive(Clone, Serialize)]
 pub struct Operation {
-    //  Waypoint
-    receiver: Account,
-
     //  Drone
-    sender: Account,
+    sender: String,
 
-    amount: u8,
+    kind: OperationKind,
 
-    signature: Vec<u8>
+    //  Number of operations the sender made before this one,
+    //  see `Blockchain::get_next_nonce`
+    nonce: u64,
+
+    //  Sender's signature over `get_signing_payload`, empty until signed
+    signature: Vec<u8>,
+
+    //  Hex encoded tagged public key that made `signature`. Left out
+    //  of the encoding, the chain only accepts the key it expects.
+    public_key: String,
+
+    //  Signatures of a multisig sender's policy keys by key, used
+    //  instead of `signature`. Left out of the encoding so they can
+    //  be collected in any order after the operation is made.
+    cosignatures: BTreeMap<String, Vec<u8>>
 }
 
 #[derive(Debug)]
 pub struct CoinUpdates {
     id: String,
-    amount: u8
+    amount: Amount
 }
 
+/**
+    Operations applied together. Every distinct sender signs the
+    whole transaction, so no operation can be taken out of it or
+    put into another one, e.g. a drone and its owning company
+    co-signing a delivery.
+ */
 #[derive(Clone, Serialize)]
 pub struct Transaction {
-    id: String,
+    id: Hash32,
     operations: Vec<Operation>,
-    nonce: u32
+    //  Signature of each signer by account id. Left out of the id
+    //  so signatures can be collected after the transaction is made.
+    signatures: BTreeMap<String, Vec<u8>>,
+    //  Signatures of multisig signers, by account id then policy key
+    cosignatures: BTreeMap<String, BTreeMap<String, Vec<u8>>>
+}
+
+impl OperationKind {
+    fn get_tag(&self) -> u8 {
+        match self {
+            OperationKind::Transfer { .. } => 0,
+            OperationKind::RotateKey { .. } => 1,
+            OperationKind::RevokeKey { .. } => 2,
+            OperationKind::SetMultisig { .. } => 3,
+            OperationKind::JourneyLeg { .. } => 4,
+            OperationKind::PositionReport { .. } => 5,
+            OperationKind::RegisterWaypoint { .. } => 6,
+            OperationKind::RegisterDrone { .. } => 7,
+            OperationKind::TransferDroneOwnership { .. } => 8,
+            OperationKind::DecommissionDrone { .. } => 9,
+        }
+    }
+
+    //  Kind tag then its fields, part of the operation's encoding
+    fn encode_fields(&self, encoder: &mut Encoder) {
+        encoder.put_u8(self.get_tag());
+        match self {
+            OperationKind::Transfer { receiver, amount } => {
+                encoder.put_str(receiver).put_u64(amount.get_tokens());
+            }
+            OperationKind::RotateKey { key, new_key } => {
+                encoder.put_str(key).put_str(new_key);
+            }
+            OperationKind::RevokeKey { key } => {
+                encoder.put_str(key);
+            }
+            OperationKind::SetMultisig { previous, policy } => {
+                match previous {
+                    Some(previous) => {
+                        encoder.put_u8(1);
+                        previous.encode_fields(encoder);
+                    }
+                    None => {
+                        encoder.put_u8(0);
+                    }
+                }
+                policy.encode_fields(encoder);
+            }
+            OperationKind::JourneyLeg { drone, .. } => {
+                encoder.put_str(drone);
+                self.get_leg().unwrap().encode_fields(encoder);
+            }
+            OperationKind::PositionReport { drone, .. } => {
+                encoder.put_str(drone);
+                self.get_position().unwrap().encode_fields(encoder);
+            }
+            OperationKind::RegisterWaypoint { id, operator, .. } => {
+                encoder.put_str(id).put_str(operator);
+                self.get_waypoint().unwrap().encode_fields(encoder);
+            }
+            OperationKind::RegisterDrone { drone, serial, transponder, owner } => {
+                encoder.put_str(drone).put_str(serial).put_str(transponder).put_str(owner);
+            }
+            OperationKind::TransferDroneOwnership { drone, new_owner } => {
+                encoder.put_str(drone).put_str(new_owner);
+            }
+            OperationKind::DecommissionDrone { drone } => {
+                encoder.put_str(drone);
+            }
+        }
+    }
+
+    //  Leg a `JourneyLeg` records, `None` for other kinds
+    pub fn get_leg(&self) -> Option<Leg> {
+        match self {
+            OperationKind::JourneyLeg { from_waypoint, to_waypoint, departed_at, arrived_at, .. } =>
+                Some(Leg::new(from_waypoint.clone(), to_waypoint.clone(), *departed_at, *arrived_at)),
+            _ => None,
+        }
+    }
+
+    //  Position a `PositionReport` records, `None` for other kinds
+    pub fn get_position(&self) -> Option<Position> {
+        match self {
+            OperationKind::PositionReport { lat, lon, alt, heading, speed, timestamp, .. } =>
+                Some(Position::new(*lat, *lon, *alt, *heading, *speed, *timestamp)),
+            _ => None,
+        }
+    }
+
+    //  Waypoint a `RegisterWaypoint` adds, `None` for other kinds
+    pub fn get_waypoint(&self) -> Option<Waypoint> {
+        match self {
+            OperationKind::RegisterWaypoint {
+                id, operator, lat, lon, min_alt, max_alt, capacity, status
+            } => Some(Waypoint::new(
+                id.clone(), operator.clone(), *lat, *lon,
+                AltitudeBand::new(*min_alt, *max_alt), *capacity, *status
+            )),
+            _ => None,
+        }
+    }
+
+    //  Drone a `RegisterDrone` adds, `None` for other kinds
+    pub fn get_drone(&self) -> Option<Drone> {
+        match self {
+            OperationKind::RegisterDrone { drone, serial, transponder, owner } =>
+                Some(Drone::new(drone.clone(), serial.clone(), transponder.clone(), owner.clone())),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for OperationKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            OperationKind::Transfer { receiver, amount } =>
+                write!(f, "{}\n{}", receiver, amount),
+            OperationKind::RotateKey { key, new_key } =>
+                write!(f, "rotate {}\n{}", key, new_key),
+            OperationKind::RevokeKey { key } =>
+                write!(f, "revoke {}", key),
+            OperationKind::SetMultisig { policy, .. } => write!(
+                f, "multisig {} of\n{}", policy.get_threshold(), policy.get_keys().join("\n")
+            ),
+            OperationKind::JourneyLeg { drone, from_waypoint, to_waypoint, departed_at, arrived_at } =>
+                write!(
+                    f, "journey {}\n{} {}\n{} {}",
+                    drone, from_waypoint, departed_at, to_waypoint, arrived_at
+                ),
+            OperationKind::PositionReport { drone, lat, lon, alt, heading, speed, timestamp } =>
+                write!(
+                    f, "position {}\n{} {} {} {} {} {}",
+                    drone, lat, lon, alt, heading, speed, timestamp
+                ),
+            OperationKind::RegisterWaypoint {
+                id, operator, lat, lon, min_alt, max_alt, capacity, status
+            } => write!(
+                f, "waypoint {}\n{}\n{} {} {} {} {} {}",
+                id, operator, lat, lon, min_alt, max_alt, capacity, status
+            ),
+            OperationKind::RegisterDrone { drone, serial, transponder, owner } =>
+                write!(f, "drone {} {}\n{}\n{}", drone, serial, transponder, owner),
+            OperationKind::TransferDroneOwnership { drone, new_owner } =>
+                write!(f, "transfer drone {}\n{}", drone, new_owner),
+            OperationKind::DecommissionDrone { drone } =>
+                write!(f, "decommission drone {}", drone),
+        }
+    }
 }
 
 impl Operation {
+    //  Unsigned transfer of `amount` from `sender` to `receiver`
+    pub fn new(sender: String, receiver: String, amount: Amount, nonce: u64) -> Self {
+        Self::with_kind(sender, OperationKind::Transfer { receiver, amount }, nonce)
+    }
+
+    //  Unsigned operation of any kind
+    pub fn with_kind(sender: String, kind: OperationKind, nonce: u64) -> Self {
+        Operation {
+            sender,
+            kind,
+            nonce,
+            signature: Vec::new(),
+            public_key: String::new(),
+            cosignatures: BTreeMap::new()
+        }
+    }
+
     //  We assume that all flights are cargo related
     //  Each operation only involves 1 drone transfer
     //  from
     pub fn create_operation(
-        receiver: Account, sender: Account, amount: u8
+        receiver: &Account, sender: &Account, amount: Amount, nonce: u64, chain_id: u32
     ) -> Self {
-        let signature = sender.sign_data(
-            flight.to_string(), 0
-        ).clone();
+        let mut operation = Self::new(
+            sender.get_id(), receiver.get_id(), amount, nonce
+        );
+        operation.sign(sender.get_signing_keysig(), chain_id);
+        operation
+    }
 
-        Operation {
-            receiver,
-            sender,
-            amount,
-            signature
-        }
+    /**
+        Rotation of `sender` from its current signing key to
+        `new_key`, signed with the current one. Once it is on the
+        chain the account signs with `new_key`, see
+        `Account::set_signing_key`.
+     */
+    pub fn create_key_rotation(
+        sender: &Account, new_key: &KeySig, nonce: u64, chain_id: u32
+    ) -> Self {
+        let kind = OperationKind::RotateKey {
+            key: hex::encode(sender.get_signing_keysig().get_public_key()),
+            new_key: hex::encode(new_key.get_public_key()),
+        };
+        let mut operation = Self::with_kind(sender.get_id(), kind, nonce);
+        operation.sign(sender.get_signing_keysig(), chain_id);
+        operation
+    }
+
+    //  Revocation of `sender`'s current signing key, e.g. for a lost transponder
+    pub fn create_key_revocation(sender: &Account, nonce: u64, chain_id: u32) -> Self {
+        let kind = OperationKind::RevokeKey {
+            key: hex::encode(sender.get_signing_keysig().get_public_key()),
+        };
+        let mut operation = Self::with_kind(sender.get_id(), kind, nonce);
+        operation.sign(sender.get_signing_keysig(), chain_id);
+        operation
+    }
+
+    /**
+        Puts `sender` under `policy` for the first time, signed with
+        its current signing key. From then on its operations and
+        transactions need cosignatures, see `Operation::cosign`.
+        Changing the policy later is a `SetMultisig` naming the
+        policy it replaces, cosigned under that policy.
+     */
+    pub fn create_multisig_setup(
+        sender: &Account, policy: MultisigPolicy, nonce: u64, chain_id: u32
+    ) -> Self {
+        let kind = OperationKind::SetMultisig { previous: None, policy };
+        let mut operation = Self::with_kind(sender.get_id(), kind, nonce);
+        operation.sign(sender.get_signing_keysig(), chain_id);
+        operation
+    }
+
+    //  Drone's report of completing `leg`, signed with its signing key
+    pub fn create_journey_leg(drone: &Account, leg: Leg, nonce: u64, chain_id: u32) -> Self {
+        let kind = OperationKind::JourneyLeg {
+            drone: drone.get_id(),
+            from_waypoint: leg.get_from_waypoint().to_string(),
+            to_waypoint: leg.get_to_waypoint().to_string(),
+            departed_at: leg.get_departed_at(),
+            arrived_at: leg.get_arrived_at(),
+        };
+        let mut operation = Self::with_kind(drone.get_id(), kind, nonce);
+        operation.sign(drone.get_signing_keysig(), chain_id);
+        operation
+    }
+
+    //  Drone's report of being at `position`, signed with its signing key
+    pub fn create_position_report(
+        drone: &Account, position: Position, nonce: u64, chain_id: u32
+    ) -> Self {
+        let kind = OperationKind::PositionReport {
+            drone: drone.get_id(),
+            lat: position.get_lat(),
+            lon: position.get_lon(),
+            alt: position.get_alt(),
+            heading: position.get_heading(),
+            speed: position.get_speed(),
+            timestamp: position.get_timestamp(),
+        };
+        let mut operation = Self::with_kind(drone.get_id(), kind, nonce);
+        operation.sign(drone.get_signing_keysig(), chain_id);
+        operation
+    }
+
+    //  Operator's registration of `waypoint`, signed with its signing key
+    pub fn create_waypoint_registration(
+        operator: &Account, waypoint: &Waypoint, nonce: u64, chain_id: u32
+    ) -> Self {
+        let kind = OperationKind::RegisterWaypoint {
+            id: waypoint.get_id().to_string(),
+            operator: operator.get_id(),
+            lat: waypoint.get_lat(),
+            lon: waypoint.get_lon(),
+            min_alt: waypoint.get_band().get_min(),
+            max_alt: waypoint.get_band().get_max(),
+            capacity: waypoint.get_capacity(),
+            status: waypoint.get_status(),
+        };
+        let mut operation = Self::with_kind(operator.get_id(), kind, nonce);
+        operation.sign(operator.get_signing_keysig(), chain_id);
+        operation
+    }
+
+    /**
+        Owner's registration of `drone` under `serial`, binding it to
+        the transponder key its id commits to. Signed with the
+        owner's signing key, the drone must also sign the transaction
+        carrying it with that transponder key.
+     */
+    pub fn create_drone_registration(
+        owner: &Account, drone: &Account, serial: &str, nonce: u64, chain_id: u32
+    ) -> Self {
+        let kind = OperationKind::RegisterDrone {
+            drone: drone.get_id(),
+            serial: serial.to_string(),
+            transponder: hex::encode(drone.get_keysig(0).get_public_key()),
+            owner: owner.get_id(),
+        };
+        let mut operation = Self::with_kind(owner.get_id(), kind, nonce);
+        operation.sign(owner.get_signing_keysig(), chain_id);
+        operation
+    }
+
+    //  Owner's handover of `drone` to `new_owner`, signed with its signing key
+    pub fn create_drone_transfer(
+        owner: &Account, drone: &str, new_owner: &str, nonce: u64, chain_id: u32
+    ) -> Self {
+        let kind = OperationKind::TransferDroneOwnership {
+            drone: drone.to_string(), new_owner: new_owner.to_string()
+        };
+        let mut operation = Self::with_kind(owner.get_id(), kind, nonce);
+        operation.sign(owner.get_signing_keysig(), chain_id);
+        operation
+    }
+
+    //  Owner's retirement of `drone`, signed with its signing key
+    pub fn create_drone_decommission(owner: &Account, drone: &str, nonce: u64, chain_id: u32) -> Self {
+        let kind = OperationKind::DecommissionDrone { drone: drone.to_string() };
+        let mut operation = Self::with_kind(owner.get_id(), kind, nonce);
+        operation.sign(owner.get_signing_keysig(), chain_id);
+        operation
+    }
+
+    //  Unsigned change of `sender`'s policy from `previous` to `policy`, to be cosigned under `previous`
+    pub fn multisig_change(
+        sender: String, previous: MultisigPolicy, policy: MultisigPolicy, nonce: u64
+    ) -> Self {
+        Self::with_kind(sender, OperationKind::SetMultisig { previous: Some(previous), policy }, nonce)
+    }
+
+    //  Signs for chain `chain_id` with the sender's key, replacing any signature
+    pub fn sign(&mut self, keysig: &KeySig, chain_id: u32) {
+        self.signature = keysig.sign(&self.get_signing_payload(chain_id));
+        self.public_key = hex::encode(keysig.get_public_key());
+    }
+
+    //  Signs for chain `chain_id` as one of a multisig sender's policy keys
+    pub fn cosign(&mut self, keysig: &KeySig, chain_id: u32) {
+        let signature = keysig.sign(&self.get_signing_payload(chain_id));
+        self.add_cosignature(&hex::encode(keysig.get_public_key()), signature);
+    }
+
+    //  Adds a cosignature made elsewhere by policy key `key`
+    pub fn add_cosignature(&mut self, key: &str, signature: Vec<u8>) {
+        self.cosignatures.insert(key.to_string(), signature);
+    }
+
+    /**
+        Bytes the sender signs. The chain id is not stored in the
+        operation, so a signature made for one chain fails on any
+        other.
+     */
+    pub fn get_signing_payload(&self, chain_id: u32) -> Vec<u8> {
+        let mut encoder = Encoder::versioned(SIGNING_TAG);
+        encoder.put_str(&self.sender);
+        self.kind.encode_fields(&mut encoder);
+        encoder
+            .put_u64(self.nonce)
+            .put_u32(chain_id);
+        encoder.finish()
     }
 
     pub fn get_signature(&self) -> Vec<u8> {
         self.signature.clone()
     }
 
-    pub fn get_sender(&self) -> Account {
+    pub fn get_public_key(&self) -> &str {
+        &self.public_key
+    }
+
+    pub fn get_cosignatures(&self) -> &BTreeMap<String, Vec<u8>> {
+        &self.cosignatures
+    }
+
+    pub fn get_sender(&self) -> String {
         self.sender.clone()
     }
 
-    pub fn get_receiver(&self) -> Account { self.receiver.clone() }
+    pub fn get_kind(&self) -> &OperationKind {
+        &self.kind
+    }
 
-    pub fn verify_operation(&self) -> bool {
-        let mut verified = false;
-        verified = self.sender.get_keysig(0).verify(
-            flight.as_bytes(),
-            &self.signature.clone()
-        );
+    //  Receiver of a transfer, `None` for other kinds
+    pub fn get_receiver(&self) -> Option<String> {
+        match &self.kind {
+            OperationKind::Transfer { receiver, .. } => Some(receiver.clone()),
+            _ => None,
+        }
+    }
 
-        if self.amount > self.sender.get_balance() {
-            verified = false;
+    //  Amount moved, zero for operations that aren't transfers
+    pub fn get_amount(&self) -> Amount {
+        match &self.kind {
+            OperationKind::Transfer { amount, .. } => *amount,
+            _ => Amount::ZERO,
         }
+    }
 
-        verified
+    pub fn get_nonce(&self) -> u64 {
+        self.nonce
     }
 
-    pub fn update_coin_db(&self, db: &mut HashMap<String, u8>) {
-        *db.get_mut(self.sender.get_id().as_str()).unwrap() -= self.amount;
-        *db.get_mut(self.receiver.get_id().as_str()).unwrap() += self.amount;
+    /**
+        Whether the sender's signature covers this operation's
+        fields on chain `chain_id`, made with a key its address
+        commits to.
+     */
+    pub fn verify_operation(&self, chain_id: u32) -> bool {
+        self.verify_operation_with(chain_id, &SigningAuthority::Address(self.sender.clone()))
     }
 
-}
+    //  Same as `verify_operation` for a sender that rotated to `key`
+    pub fn verify_operation_with_key(&self, chain_id: u32, key: &str) -> bool {
+        self.verify_operation_with(chain_id, &SigningAuthority::Key(key.to_string()))
+    }
 
-impl ToString for Operation {
-    fn to_string(&self) -> String {
-        let sender = self.sender.to_string();
-        let receiver = self.receiver.to_string();
-        let signature = hex::encode(&receiver);
+    /**
+        Same as `verify_operation` against the sender's authority on
+        chain: its signing key, or enough cosignatures under its
+        multisig policy.
+     */
+    pub fn verify_operation_with(&self, chain_id: u32, authority: &SigningAuthority) -> bool {
+        authority.verify(
+            &self.get_signing_payload(chain_id), &self.public_key, &self.signature, &self.cosignatures
+        )
+    }
+
+}
 
-        format!(
-            "{}\n{}\n{}",
-            sender, receiver, signature
+impl fmt::Display for Operation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}\n{}\n{}\n{}",
+            self.sender, self.kind, self.nonce,
+            hex::encode(&self.signature)
         )
     }
 }
 
+impl Encode for Operation {
+    const TAG: u8 = 1;
+
+    fn encode_fields(&self, encoder: &mut Encoder) {
+        encoder.put_str(&self.sender);
+        self.kind.encode_fields(encoder);
+        encoder
+            .put_u64(self.nonce)
+            .put_bytes(&self.signature);
+    }
+}
+
 impl Transaction {
-    pub fn create_transaction(ops: Vec<Operation>, nonce: u32) -> Self {
-        let id = to_sha1(
-            &vec_to_string(&ops)
-        );
-        Transaction {
-            id,
+    pub fn create_transaction(ops: Vec<Operation>) -> Self {
+        Self::create_transaction_with(ops, HashAlgorithm::default())
+    }
+
+    //  Transaction for a chain using `algorithm`
+    pub fn create_transaction_with(
+        ops: Vec<Operation>, algorithm: HashAlgorithm
+    ) -> Self {
+        let mut transaction = Transaction {
+            id: Hash32::default(),
             operations: ops,
-            nonce
+            signatures: BTreeMap::new(),
+            cosignatures: BTreeMap::new()
+        };
+        transaction.id = transaction.compute_id(algorithm);
+        transaction
+    }
+
+    //  Hash of the canonical encoding, see `Encode for Transaction`
+    pub fn compute_id(&self, algorithm: HashAlgorithm) -> Hash32 {
+        algorithm.hash(&self.encode())
+    }
+
+    pub fn get_id(&self) -> Hash32 {
+        self.id
+    }
+
+    pub fn verify_operations(&self, chain_id: u32) -> bool {
+        self.operations.iter().all(|op| op.verify_operation(chain_id))
+    }
+
+    pub fn get_operations(&self) -> Vec<Operation> {
+        self.operations.clone()
+    }
+
+    /**
+        Distinct accounts that must sign, in order of their first
+        operation: every sender, and every drone being registered,
+        so nobody claims a transponder they don't hold.
+     */
+    pub fn get_signers(&self) -> Vec<String> {
+        let mut signers: Vec<String> = Vec::new();
+        for (signer, _) in self.signer_keys() {
+            if !signers.iter().any(|s| s == signer) {
+                signers.push(signer.to_string());
+            }
+        }
+        signers
+    }
+
+    //  Each account an operation needs a signature from, with the public key it presents
+    fn signer_keys(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.operations.iter().flat_map(|operation| {
+            let drone = match &operation.kind {
+                OperationKind::RegisterDrone { drone, transponder, .. } =>
+                    Some((drone.as_str(), transponder.as_str())),
+                _ => None,
+            };
+            std::iter::once((operation.sender.as_str(), operation.public_key.as_str())).chain(drone)
+        })
+    }
+
+    //  Signers who haven't signed or cosigned yet
+    pub fn get_missing_signers(&self) -> Vec<String> {
+        self.get_signers().into_iter()
+            .filter(|signer| {
+                !self.signatures.contains_key(signer) && !self.cosignatures.contains_key(signer)
+            })
+            .collect()
+    }
+
+    pub fn get_signatures(&self) -> &BTreeMap<String, Vec<u8>> {
+        &self.signatures
+    }
+
+    pub fn get_cosignatures(&self) -> &BTreeMap<String, BTreeMap<String, Vec<u8>>> {
+        &self.cosignatures
+    }
+
+    //  Bytes every signer signs, bound to chain `chain_id` like operations
+    pub fn get_signing_payload(&self, chain_id: u32) -> Vec<u8> {
+        let mut encoder = Encoder::versioned(TRANSACTION_SIGNING_TAG);
+        self.encode_fields(&mut encoder);
+        encoder.put_u32(chain_id);
+        encoder.finish()
+    }
+
+    //  Signs as account `signer`, replacing its previous signature
+    pub fn sign(&mut self, signer: &str, keysig: &KeySig, chain_id: u32) {
+        let signature = keysig.sign(&self.get_signing_payload(chain_id));
+        self.add_signature(signer, signature);
+    }
+
+    /**
+        Adds a signature made elsewhere over `get_signing_payload`,
+        so each party can sign on its own device before submission.
+     */
+    pub fn add_signature(&mut self, signer: &str, signature: Vec<u8>) {
+        self.signatures.insert(signer.to_string(), signature);
+    }
+
+    //  Signs for multisig account `signer` as one of its policy keys
+    pub fn cosign(&mut self, signer: &str, keysig: &KeySig, chain_id: u32) {
+        let signature = keysig.sign(&self.get_signing_payload(chain_id));
+        self.add_cosignature(signer, &hex::encode(keysig.get_public_key()), signature);
+    }
+
+    //  Adds a cosignature made elsewhere by policy key `key` of `signer`
+    pub fn add_cosignature(&mut self, signer: &str, key: &str, signature: Vec<u8>) {
+        self.cosignatures.entry(signer.to_string()).or_default()
+            .insert(key.to_string(), signature);
+    }
+
+    //  Every signer has signed, whether validly or not, and nobody else did
+    pub fn check_signers(&self) -> Result<(), SignatureError> {
+        let signers = self.get_signers();
+        let mut accounts = self.signatures.keys().chain(self.cosignatures.keys());
+        if let Some(extra) = accounts.find(|s| !signers.contains(s)) {
+            return Err(SignatureError::Unexpected(extra.clone()));
         }
+        match self.get_missing_signers().into_iter().next() {
+            Some(signer) => Err(SignatureError::Missing(signer)),
+            None => Ok(()),
+        }
+    }
+
+    /**
+        Every signer has a valid signature for chain `chain_id` and
+        nobody else signed. Returns the first signer who is missing
+        or whose signature fails, or an account that shouldn't sign.
+        Signers are checked against the key their address commits to.
+     */
+    pub fn verify_signatures(&self, chain_id: u32) -> Result<(), SignatureError> {
+        self.verify_with(chain_id, |account| Some(SigningAuthority::Address(account.to_string())))
     }
 
-    pub fn get_id(&self) -> String {
-        self.id.clone()
+    //  Same as `verify_with` for accounts that each sign with the key `signing_key` returns
+    pub fn verify_with_keys<F>(&self, chain_id: u32, signing_key: F) -> Result<(), SignatureError>
+    where F: Fn(&str) -> Option<String> {
+        self.verify_with(chain_id, |account| signing_key(account).map(SigningAuthority::Key))
     }
 
-    pub fn verify_operations(&self) -> bool {
-        let mut is_valid = false;
+    /**
+        Checks the signers, then every operation and transaction
+        signature against the authority `authority` returns for its
+        account, `None` meaning the account's key was revoked. A
+        multisig signer's cosignatures must meet its policy. A
+        signer's transaction signature is made with the public key
+        presented by its first operation, or the transponder of
+        the first drone registration naming it.
+     */
+    pub fn verify_with<F>(&self, chain_id: u32, authority: F) -> Result<(), SignatureError>
+    where F: Fn(&str) -> Option<SigningAuthority> {
+        self.check_signers()?;
 
-        for i in 0..self.operations.len() {
-            if self.operations[i].verify_operation() {
-                is_valid = true;
+        let authority_of = |account: &str| authority(account)
+            .ok_or_else(|| SignatureError::Revoked(account.to_string()));
+        for operation in &self.operations {
+            if !operation.verify_operation_with(chain_id, &authority_of(&operation.sender)?) {
+                return Err(SignatureError::Invalid(operation.sender.clone()));
             }
         }
 
-        is_valid
+        let payload = self.get_signing_payload(chain_id);
+        let no_cosignatures = BTreeMap::new();
+        for signer in self.get_signers() {
+            let public_key = self.signer_keys()
+                .find(|(account, _)| *account == signer)
+                .map_or("", |(_, key)| key);
+            let signature = self.signatures.get(&signer).map_or(&[][..], |s| s.as_slice());
+            let cosignatures = self.cosignatures.get(&signer).unwrap_or(&no_cosignatures);
+            if !authority_of(&signer)?.verify(&payload, public_key, signature, cosignatures) {
+                return Err(SignatureError::Invalid(signer));
+            }
+        }
+        Ok(())
     }
+}
 
-    pub fn get_operations(&self) -> Vec<Operation> {
-        self.operations.clone()
+/**
+    Why `Transaction::verify_signatures` failed,
+    naming the account at fault.
+ */
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SignatureError {
+    Missing(String),
+    Invalid(String),
+    //  Signed by an account that isn't one of the transaction's signers
+    Unexpected(String),
+    //  Signer's key has been revoked and not replaced
+    Revoked(String),
+}
+
+impl fmt::Display for SignatureError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SignatureError::Missing(signer) =>
+                write!(f, "transaction is missing the signature of {}", signer),
+            SignatureError::Invalid(signer) =>
+                write!(f, "transaction signature of {} is invalid", signer),
+            SignatureError::Unexpected(signer) =>
+                write!(f, "transaction is signed by {} who is not one of its signers", signer),
+            SignatureError::Revoked(signer) =>
+                write!(f, "signing key of {} has been revoked", signer),
+        }
+    }
+}
+
+impl Error for SignatureError {}
+
+//  The id is left out, it is the hash of this encoding
+impl Encode for Transaction {
+    const TAG: u8 = 2;
+
+    fn encode_fields(&self, encoder: &mut Encoder) {
+        encoder.put_list(&self.operations);
     }
 }
 
 impl CoinUpdates {
     pub fn new() -> Vec<CoinUpdates> {
         vec![CoinUpdates{
-            id: "".to_string(), amount: 0
+            id: "".to_string(), amount: Amount::ZERO
         }]
     }
 
@@ -143,15 +795,14 @@ impl CoinUpdates {
     pub fn get_id(&self) -> &str {
         &self.id
     }
-    pub fn get_amount(&self) -> u8 {
+    pub fn get_amount(&self) -> Amount {
         self.amount
     }
 }
 
-impl ToString for Transaction {
-    fn to_string(&self) -> String {
-        let id = self.id.clone();
-        let nonce = self.nonce.clone();
+impl fmt::Display for Transaction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let id = self.id;
         let mut ops: HashMap<usize, String> = HashMap::new();
         for i in 0..self.operations.len() {
             ops.insert(
@@ -160,40 +811,42 @@ impl ToString for Transaction {
             );
         }
 
-        format!(
-            "{}\n{}\n{}",
+        write!(
+            f,
+            "{}\n{}",
             id,
-            serde_json::to_string(&ops).unwrap(),
-            nonce
+            serde_json::to_string(&ops).unwrap()
         )
     }
 }
 
-pub fn verify_operation(op: Operation) -> bool {
-    let sender = op.get_sender();
-
-    let is_verified = sender.get_keysig(0).verify(
-        flight.as_bytes(), &op.get_signature()
-    );
-
-    is_verified
-}
-
-pub fn get_nonce() -> u32 {
-    let mut rng = rand::thread_rng();
-    rng.gen()
+pub fn verify_operation(op: Operation, chain_id: u32) -> bool {
+    op.verify_operation(chain_id)
 }
 
 #[cfg(test)]
 mod tests {
     use crate::account::Account;
-    use crate::transops::{get_nonce, Operation, Transaction, verify_operation};
+    use crate::amount::Amount;
+    use crate::encoding::Encode;
+    use crate::hash::{Hash32, HashAlgorithm};
+    use crate::keysig::{KeyAlgorithm, KeySig};
+    use crate::multisig::{MultisigPolicy, SigningAuthority};
+    use crate::transops::{Operation, OperationKind, SignatureError, Transaction, verify_operation};
+
+    const CHAIN_ID: u32 = 7;
 
     fn get_operation() -> Operation {
         let account1 = Account::gen_account();
         let account2 = Account::gen_account();
 
-        Operation::create_operation(account1, account2, 1)
+        Operation::create_operation(&account1, &account2, Amount::new(1), 0, CHAIN_ID)
+    }
+
+    fn set_amount(op: &mut Operation, tokens: u64) {
+        if let OperationKind::Transfer { amount, .. } = &mut op.kind {
+            *amount = Amount::new(tokens);
+        }
     }
 
     #[test]
@@ -208,7 +861,194 @@ mod tests {
     fn test_verify_operation() {
         let op = get_operation();
 
-        assert!(verify_operation(op));
+        assert!(verify_operation(op, CHAIN_ID));
+    }
+
+    #[test]
+    fn test_reject_tampered_amount() {
+        let mut op = get_operation();
+        set_amount(&mut op, 2);
+
+        assert!(!op.verify_operation(CHAIN_ID));
+    }
+
+    #[test]
+    fn test_reject_tampered_receiver() {
+        let mut op = get_operation();
+        op.kind = OperationKind::Transfer {
+            receiver: Account::gen_account().get_id(), amount: op.get_amount()
+        };
+
+        assert!(!op.verify_operation(CHAIN_ID));
+    }
+
+    #[test]
+    fn test_reject_tampered_nonce() {
+        let mut op = get_operation();
+        op.nonce += 1;
+
+        assert!(!op.verify_operation(CHAIN_ID));
+    }
+
+    //  A signature moved onto another operation doesn't verify there
+    #[test]
+    fn test_reject_replayed_signature() {
+        let op = get_operation();
+        let mut other = Operation::new(
+            op.get_sender(), Account::gen_account().get_id(), Amount::new(1), 1
+        );
+        other.signature = op.get_signature();
+
+        assert!(!other.verify_operation(CHAIN_ID));
+    }
+
+    #[test]
+    fn test_reject_other_chain() {
+        let op = get_operation();
+
+        assert!(!op.verify_operation(CHAIN_ID + 1));
+    }
+
+    #[test]
+    fn test_sign_separately() {
+        let sender = Account::gen_account();
+        let mut op = Operation::new(
+            sender.get_id(), Account::gen_account().get_id(), Amount::new(1), 0
+        );
+        assert!(!op.verify_operation(CHAIN_ID));
+
+        op.sign(&KeySig::new(), CHAIN_ID);
+        assert!(!op.verify_operation(CHAIN_ID));
+
+        op.sign(&sender.get_keysig(0), CHAIN_ID);
+        assert!(op.verify_operation(CHAIN_ID));
+    }
+
+    //  A drone and its owning company each sign the same transaction
+    #[test]
+    fn test_collect_signatures() {
+        let drone = Account::gen_account();
+        let company = Account::gen_account();
+        let waypoint = Account::gen_account();
+        let mut trans = Transaction::create_transaction(vec![
+            Operation::create_operation(&waypoint, &drone, Amount::new(1), 0, CHAIN_ID),
+            Operation::create_operation(&waypoint, &company, Amount::new(2), 0, CHAIN_ID),
+            Operation::create_operation(&drone, &company, Amount::new(3), 1, CHAIN_ID),
+        ]);
+        let id = trans.get_id();
+        assert_eq!(trans.get_signers(), vec![drone.get_id(), company.get_id()]);
+
+        drone.sign_transaction(&mut trans, CHAIN_ID);
+        assert_eq!(trans.get_missing_signers(), vec![company.get_id()]);
+        assert_eq!(
+            trans.verify_signatures(CHAIN_ID),
+            Err(SignatureError::Missing(company.get_id()))
+        );
+
+        //  Signed on the company's own machine and handed back
+        let signature = company.get_keysig(0).sign(&trans.get_signing_payload(CHAIN_ID));
+        trans.add_signature(&company.get_id(), signature);
+
+        assert!(trans.get_missing_signers().is_empty());
+        assert_eq!(trans.verify_signatures(CHAIN_ID), Ok(()));
+        assert_eq!(trans.get_id(), id);
+    }
+
+    #[test]
+    fn test_reject_bad_transaction_signatures() {
+        let sender = Account::gen_account();
+        let outsider = Account::gen_account();
+        let mut trans = Transaction::create_transaction(vec![
+            Operation::create_operation(&outsider, &sender, Amount::new(1), 0, CHAIN_ID)
+        ]);
+
+        trans.sign(&sender.get_id(), &outsider.get_keysig(0), CHAIN_ID);
+        assert_eq!(
+            trans.verify_signatures(CHAIN_ID),
+            Err(SignatureError::Invalid(sender.get_id()))
+        );
+
+        sender.sign_transaction(&mut trans, CHAIN_ID);
+        assert_eq!(trans.verify_signatures(CHAIN_ID), Ok(()));
+        assert!(trans.verify_signatures(CHAIN_ID + 1).is_err());
+
+        outsider.sign_transaction(&mut trans, CHAIN_ID);
+        assert_eq!(
+            trans.verify_signatures(CHAIN_ID),
+            Err(SignatureError::Unexpected(outsider.get_id()))
+        );
+    }
+
+    //  After a rotation signatures are checked against the key the chain has on record
+    #[test]
+    fn test_verify_with_keys() {
+        let sender = Account::gen_account_with(KeyAlgorithm::Ed25519);
+        let new_key = KeySig::generate(KeyAlgorithm::Ed25519);
+        let rotation = Operation::create_key_rotation(&sender, &new_key, 0, CHAIN_ID);
+        assert!(rotation.verify_operation(CHAIN_ID));
+
+        let mut trans = Transaction::create_transaction(vec![Operation::new(
+            sender.get_id(), Account::gen_account_with(KeyAlgorithm::Ed25519).get_id(),
+            Amount::new(1), 1
+        )]);
+        let new_id = hex::encode(new_key.get_public_key());
+        trans.operations[0].sign(&new_key, CHAIN_ID);
+        trans.sign(&sender.get_id(), &new_key, CHAIN_ID);
+
+        assert_eq!(trans.verify_signatures(CHAIN_ID), Err(SignatureError::Invalid(sender.get_id())));
+        assert_eq!(trans.verify_with_keys(CHAIN_ID, |_| Some(new_id.clone())), Ok(()));
+        assert_eq!(
+            trans.verify_with_keys(CHAIN_ID, |_| None),
+            Err(SignatureError::Revoked(sender.get_id()))
+        );
+    }
+
+    //  Two of three treasurers approve a payout from the company account
+    #[test]
+    fn test_verify_multisig() {
+        let company = Account::gen_account_with(KeyAlgorithm::Ed25519);
+        let treasurers: Vec<KeySig> = (0..3).map(|_| KeySig::generate(KeyAlgorithm::Ed25519)).collect();
+        let keys = treasurers.iter().map(|key| hex::encode(key.get_public_key())).collect();
+        let policy = MultisigPolicy::new(2, keys).unwrap();
+        let setup = Operation::create_multisig_setup(&company, policy.clone(), 0, CHAIN_ID);
+        assert!(setup.verify_operation(CHAIN_ID));
+
+        let authority = SigningAuthority::Multisig(policy);
+        let mut trans = Transaction::create_transaction(vec![Operation::new(
+            company.get_id(), Account::gen_account().get_id(), Amount::new(1), 1
+        )]);
+        let id = trans.get_id();
+        trans.operations[0].sign(company.get_signing_keysig(), CHAIN_ID);
+        company.sign_transaction(&mut trans, CHAIN_ID);
+        assert!(!trans.operations[0].verify_operation_with(CHAIN_ID, &authority));
+        assert_eq!(
+            trans.verify_with(CHAIN_ID, |_| Some(authority.clone())),
+            Err(SignatureError::Invalid(company.get_id()))
+        );
+
+        for treasurer in &treasurers[1..] {
+            trans.operations[0].cosign(treasurer, CHAIN_ID);
+            trans.cosign(&company.get_id(), treasurer, CHAIN_ID);
+        }
+        trans.signatures.clear();
+        assert!(trans.operations[0].verify_operation_with(CHAIN_ID, &authority));
+        assert_eq!(trans.verify_with(CHAIN_ID, |_| Some(authority.clone())), Ok(()));
+        assert_eq!(trans.get_id(), id);
+
+        //  Cosigning for an account that sends nothing
+        let outsider = Account::gen_account().get_id();
+        trans.cosign(&outsider, &treasurers[0], CHAIN_ID);
+        assert_eq!(trans.check_signers(), Err(SignatureError::Unexpected(outsider)));
+    }
+
+    #[test]
+    fn test_verify_operations_requires_all() {
+        let valid = get_operation();
+        let mut tampered = get_operation();
+        set_amount(&mut tampered, 2);
+
+        assert!(Transaction::create_transaction(vec![valid.clone()]).verify_operations(CHAIN_ID));
+        assert!(!Transaction::create_transaction(vec![valid, tampered]).verify_operations(CHAIN_ID));
     }
 
     #[test]
@@ -221,27 +1061,76 @@ mod tests {
     fn test_create_transaction() {
         let op = get_operation();
         let trans = Transaction::create_transaction(
-            vec![op], get_nonce()
+            vec![op]
         );
 
-        assert!(
-            !trans.get_id().is_empty()
+        assert_ne!(trans.get_id(), Hash32::default());
+        assert_ne!(
+            trans.compute_id(HashAlgorithm::Blake3),
+            trans.get_id()
+        );
+    }
+
+    //  Golden vector, must not change between releases
+    #[test]
+    fn test_transaction_encoding() {
+        let trans = Transaction::create_transaction(vec![]);
+
+        assert_eq!(hex::encode(trans.encode()), "010200000000");
+        assert_eq!(
+            trans.get_id().to_hex(),
+            "361595a6901387c14718e6b6cc57183f07ed5c6440273d5adddeb76f70eea7a7"
+        );
+    }
+
+    //  Golden vector, must not change between releases
+    #[test]
+    fn test_operation_encoding() {
+        let mut op = Operation::new(
+            "drone".to_string(), "waypoint".to_string(), Amount::new(3), 4
+        );
+        op.signature = vec![0xab];
+
+        assert_eq!(
+            hex::encode(op.encode()),
+            "0101\
+             0000000564726f6e65\
+             00\
+             00000008776179706f696e74\
+             0000000000000003\
+             0000000000000004\
+             00000001ab"
+        );
+    }
+
+    //  Equal transfers from one sender differ by nonce, so do their transactions
+    #[test]
+    fn test_id_covers_nonce() {
+        let sender = Account::gen_account();
+        let receiver = Account::gen_account();
+        let first = Operation::create_operation(&receiver, &sender, Amount::new(1), 0, CHAIN_ID);
+        let second = Operation::create_operation(&receiver, &sender, Amount::new(1), 1, CHAIN_ID);
+
+        assert_ne!(
+            Transaction::create_transaction(vec![first]).get_id(),
+            Transaction::create_transaction(vec![second]).get_id()
         );
     }
 
     #[test]
     fn test_to_string() {
         let op = get_operation();
+        let sender = op.get_sender();
         assert!(
             op.to_string()
-                .contains("PUBLIC")
+                .contains(&sender)
         );
 
         let trans = Transaction::create_transaction(
-            vec![op], get_nonce()
+            vec![op]
         );
         assert!(
-            trans.to_string().contains("PUBLIC")
+            trans.to_string().contains(&sender)
         )
     }
 }
\ No newline at end of file
@@ -1,14 +1,24 @@
-pub fn vec_to_string<T: ToString>(vector: &Vec<T>) -> String {
+use std::time::{SystemTime, UNIX_EPOCH};
+
+pub fn vec_to_string<T: ToString>(vector: &[T]) -> String {
     let mut s = String::from("");
 
     for v in vector {
         s.push_str(v.to_string().as_str());
-        s.push_str(" ");
+        s.push(' ');
     }
 
     s
 }
 
+//  Seconds since the unix epoch
+pub fn get_timestamp() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap()
+        .as_secs()
+}
+
 #[cfg(test)]
 mod test {
     use crate::utils::vec_to_string;
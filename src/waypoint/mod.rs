@@ -0,0 +1,275 @@
+//  Registry of the waypoints drones fly between
+
+use std::error::Error;
+use std::fmt;
+use serde::Serialize;
+
+use crate::address::Address;
+use crate::encoding::Encoder;
+use crate::flight::{MAX_LATITUDE, MAX_LONGITUDE};
+
+/**
+    Whether a waypoint takes drones. Closed waypoints stay in
+    the registry so legs that reached them still resolve.
+ */
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+pub enum WaypointStatus {
+    Active,
+    Closed,
+}
+
+/**
+    Altitudes drones approach a waypoint at, in millimetres
+    above sea level like `Position`, both ends included.
+ */
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+pub struct AltitudeBand {
+    min: i32,
+    max: i32,
+}
+
+/**
+    Landing or pickup point run by `operator`. Its id is an
+    address, as journey legs name waypoints, and coordinates
+    are WGS84 in units of 1e-7 degrees.
+ */
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct Waypoint {
+    id: String,
+    operator: String,
+    lat: i32,
+    lon: i32,
+    band: AltitudeBand,
+    //  Drones it can hold at once
+    capacity: u32,
+    status: WaypointStatus,
+}
+
+/**
+    Area between two parallels and two meridians, in the units
+    of `Waypoint`. A box whose west edge is east of its east
+    edge crosses the antimeridian.
+ */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct BoundingBox {
+    south: i32,
+    west: i32,
+    north: i32,
+    east: i32,
+}
+
+//  Why a waypoint registration was refused
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WaypointError {
+    //  Only the operator registers its waypoints
+    NotTheOperator(String),
+    InvalidId(String),
+    AlreadyRegistered(String),
+    //  Coordinates outside WGS84 ranges
+    OutOfRange,
+    //  Band's floor is above its ceiling
+    EmptyBand,
+    NoCapacity,
+}
+
+impl fmt::Display for WaypointError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WaypointError::NotTheOperator(sender) =>
+                write!(f, "registered by {}, not the operator", sender),
+            WaypointError::InvalidId(id) => write!(f, "waypoint id {} is not a valid address", id),
+            WaypointError::AlreadyRegistered(id) => write!(f, "waypoint {} is already registered", id),
+            WaypointError::OutOfRange => write!(f, "coordinates are out of range"),
+            WaypointError::EmptyBand => write!(f, "altitude band floor is above its ceiling"),
+            WaypointError::NoCapacity => write!(f, "waypoint can't hold any drones"),
+        }
+    }
+}
+
+impl Error for WaypointError {}
+
+impl WaypointStatus {
+    pub fn get_tag(&self) -> u8 {
+        match self {
+            WaypointStatus::Active => 0,
+            WaypointStatus::Closed => 1,
+        }
+    }
+}
+
+impl fmt::Display for WaypointStatus {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WaypointStatus::Active => write!(f, "active"),
+            WaypointStatus::Closed => write!(f, "closed"),
+        }
+    }
+}
+
+impl AltitudeBand {
+    pub fn new(min: i32, max: i32) -> Self {
+        AltitudeBand { min, max }
+    }
+
+    pub fn get_min(&self) -> i32 {
+        self.min
+    }
+
+    pub fn get_max(&self) -> i32 {
+        self.max
+    }
+
+    pub fn contains(&self, alt: i32) -> bool {
+        self.min <= alt && alt <= self.max
+    }
+}
+
+impl Waypoint {
+    pub fn new(
+        id: String,
+        operator: String,
+        lat: i32,
+        lon: i32,
+        band: AltitudeBand,
+        capacity: u32,
+        status: WaypointStatus
+    ) -> Self {
+        Waypoint { id, operator, lat, lon, band, capacity, status }
+    }
+
+    pub fn get_id(&self) -> &str {
+        &self.id
+    }
+
+    pub fn get_operator(&self) -> &str {
+        &self.operator
+    }
+
+    pub fn get_lat(&self) -> i32 {
+        self.lat
+    }
+
+    pub fn get_lon(&self) -> i32 {
+        self.lon
+    }
+
+    pub fn get_band(&self) -> AltitudeBand {
+        self.band
+    }
+
+    pub fn get_capacity(&self) -> u32 {
+        self.capacity
+    }
+
+    pub fn get_status(&self) -> WaypointStatus {
+        self.status
+    }
+
+    //  Rules that hold for a waypoint on its own
+    pub fn check(&self) -> Result<(), WaypointError> {
+        if !Address::is_valid(&self.id) {
+            return Err(WaypointError::InvalidId(self.id.clone()));
+        }
+        if self.lat.unsigned_abs() > MAX_LATITUDE as u32
+            || self.lon.unsigned_abs() > MAX_LONGITUDE as u32 {
+            return Err(WaypointError::OutOfRange);
+        }
+        if self.band.min > self.band.max {
+            return Err(WaypointError::EmptyBand);
+        }
+        if self.capacity == 0 {
+            return Err(WaypointError::NoCapacity);
+        }
+        Ok(())
+    }
+
+    //  Everything but the id and operator, which the encoding carries elsewhere
+    pub fn encode_fields(&self, encoder: &mut Encoder) {
+        encoder
+            .put_u32(self.lat as u32)
+            .put_u32(self.lon as u32)
+            .put_u32(self.band.min as u32)
+            .put_u32(self.band.max as u32)
+            .put_u32(self.capacity)
+            .put_u8(self.status.get_tag());
+    }
+}
+
+impl BoundingBox {
+    pub fn new(south: i32, west: i32, north: i32, east: i32) -> Result<Self, WaypointError> {
+        if south > north
+            || [south, north].iter().any(|lat| lat.unsigned_abs() > MAX_LATITUDE as u32)
+            || [west, east].iter().any(|lon| lon.unsigned_abs() > MAX_LONGITUDE as u32) {
+            return Err(WaypointError::OutOfRange);
+        }
+        Ok(BoundingBox { south, west, north, east })
+    }
+
+    pub fn contains(&self, lat: i32, lon: i32) -> bool {
+        let within_lon = match self.west <= self.east {
+            true => self.west <= lon && lon <= self.east,
+            false => self.west <= lon || lon <= self.east,
+        };
+        self.south <= lat && lat <= self.north && within_lon
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::account::Account;
+    use super::{AltitudeBand, BoundingBox, Waypoint, WaypointError, WaypointStatus};
+
+    fn get_waypoint(lat: i32, lon: i32, band: AltitudeBand, capacity: u32) -> Waypoint {
+        Waypoint::new(
+            Account::gen_account().get_id(), Account::gen_account().get_id(),
+            lat, lon, band, capacity, WaypointStatus::Active
+        )
+    }
+
+    #[test]
+    fn test_check_waypoint() {
+        let band = AltitudeBand::new(0, 120_000);
+
+        assert_eq!(get_waypoint(504_501_000, 305_234_000, band, 4).check(), Ok(()));
+        assert_eq!(get_waypoint(900_000_001, 0, band, 4).check(), Err(WaypointError::OutOfRange));
+        assert_eq!(get_waypoint(i32::MIN, 0, band, 4).check(), Err(WaypointError::OutOfRange));
+        assert_eq!(get_waypoint(0, i32::MIN, band, 4).check(), Err(WaypointError::OutOfRange));
+        assert_eq!(
+            get_waypoint(0, 0, AltitudeBand::new(10, 0), 4).check(),
+            Err(WaypointError::EmptyBand)
+        );
+        assert_eq!(get_waypoint(0, 0, band, 0).check(), Err(WaypointError::NoCapacity));
+
+        let unnamed = Waypoint::new(
+            "depot".to_string(), Account::gen_account().get_id(), 0, 0, band, 4, WaypointStatus::Active
+        );
+        assert_eq!(unnamed.check(), Err(WaypointError::InvalidId("depot".to_string())));
+
+        //  An upper case id would register the same waypoint twice
+        let waypoint = get_waypoint(0, 0, band, 4);
+        let alias = waypoint.get_id().to_uppercase();
+        let shouted = Waypoint::new(
+            alias.clone(), waypoint.get_operator().to_string(), 0, 0, band, 4, WaypointStatus::Active
+        );
+        assert_eq!(shouted.check(), Err(WaypointError::InvalidId(alias)));
+    }
+
+    #[test]
+    fn test_bounding_box() {
+        //  Around Kyiv
+        let kyiv = BoundingBox::new(502_000_000, 302_000_000, 507_000_000, 309_000_000).unwrap();
+        assert!(kyiv.contains(504_501_000, 305_234_000));
+        assert!(!kyiv.contains(504_501_000, 310_000_000));
+        assert!(!kyiv.contains(510_000_000, 305_234_000));
+
+        //  Across the antimeridian, around Fiji
+        let fiji = BoundingBox::new(-200_000_000, 1_770_000_000, -150_000_000, -1_780_000_000).unwrap();
+        assert!(fiji.contains(-180_000_000, 1_790_000_000));
+        assert!(fiji.contains(-180_000_000, -1_790_000_000));
+        assert!(!fiji.contains(-180_000_000, 0));
+
+        assert_eq!(BoundingBox::new(10, 0, 0, 10), Err(WaypointError::OutOfRange));
+        assert_eq!(BoundingBox::new(i32::MIN, 0, 0, 10), Err(WaypointError::OutOfRange));
+        assert_eq!(BoundingBox::new(0, i32::MIN, 10, 10), Err(WaypointError::OutOfRange));
+    }
+}